@@ -0,0 +1,880 @@
+use parser::{layout::StateVariable, GeneratedSources};
+use rusqlite::{params, Connection, OptionalExtension, Row, Transaction, TransactionBehavior};
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    error::Error,
+    fmt,
+    sync::{Mutex, MutexGuard},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use uuid::Uuid;
+
+// applied in order, the schema version is tracked in sqlite's `user_version`
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE lambdas (
+        id TEXT PRIMARY KEY,
+        name TEXT NOT NULL,
+        source TEXT NOT NULL,
+        state TEXT NOT NULL,
+        dirname TEXT NOT NULL,
+        lambda_sol TEXT NOT NULL,
+        app_gateway_sol TEXT NOT NULL,
+        deployer_sol TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL
+    );
+    CREATE INDEX lambdas_dirname ON lambdas (dirname);
+    CREATE TABLE builds (
+        id TEXT PRIMARY KEY,
+        dirname TEXT NOT NULL,
+        status TEXT NOT NULL,
+        message TEXT,
+        artifacts TEXT,
+        diagnostics TEXT,
+        created_at INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL
+    );
+    CREATE INDEX builds_dirname ON builds (dirname);",
+    // every edit of a lambda becomes a new version, `lambdas` keeps the latest one
+    "ALTER TABLE lambdas ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+    ALTER TABLE lambdas ADD COLUMN layout TEXT NOT NULL DEFAULT '[]';
+    CREATE INDEX lambdas_name ON lambdas (name);
+    CREATE TABLE lambda_versions (
+        lambda_id TEXT NOT NULL REFERENCES lambdas (id) ON DELETE CASCADE,
+        version INTEGER NOT NULL,
+        source TEXT NOT NULL,
+        state TEXT NOT NULL,
+        layout TEXT NOT NULL,
+        dirname TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        PRIMARY KEY (lambda_id, version)
+    );
+    CREATE INDEX lambda_versions_dirname ON lambda_versions (dirname);
+    INSERT INTO lambda_versions
+        SELECT id, version, source, state, layout, dirname, created_at FROM lambdas;",
+    // on-chain activity of deployed lambdas, filled by the indexer
+    "CREATE TABLE deployments (
+        address TEXT PRIMARY KEY,
+        lambda_id TEXT NOT NULL REFERENCES lambdas (id) ON DELETE CASCADE,
+        kind TEXT NOT NULL,
+        created_at INTEGER NOT NULL
+    );
+    CREATE TABLE invocations (
+        tx_hash TEXT NOT NULL,
+        log_index INTEGER NOT NULL,
+        lambda_id TEXT NOT NULL REFERENCES lambdas (id) ON DELETE CASCADE,
+        kind TEXT NOT NULL,
+        block_number INTEGER NOT NULL,
+        block_timestamp INTEGER NOT NULL,
+        caller TEXT NOT NULL,
+        args TEXT NOT NULL,
+        success INTEGER NOT NULL,
+        gas_used INTEGER,
+        gas_price INTEGER,
+        PRIMARY KEY (tx_hash, log_index)
+    );
+    CREATE INDEX invocations_lambda_time ON invocations (lambda_id, block_timestamp);
+    CREATE INDEX invocations_block ON invocations (block_number);
+    CREATE TABLE indexed_blocks (
+        number INTEGER PRIMARY KEY,
+        hash TEXT NOT NULL
+    );",
+    // a name belongs to one lambda. concurrent submissions could store the same name twice, the
+    // older copies keep their versions under the name suffixed with their id
+    "UPDATE lambdas SET name = name || '-' || id
+        WHERE rowid != (SELECT latest.rowid FROM lambdas latest WHERE latest.name = lambdas.name
+            ORDER BY latest.updated_at DESC, latest.rowid DESC LIMIT 1);
+    DROP INDEX lambdas_name;
+    CREATE UNIQUE INDEX lambdas_name ON lambdas (name);",
+];
+
+#[derive(Debug)]
+pub enum DbError {
+    UnsupportedUrl(String),
+    // a row the operation depends on doesn't exist (anymore)
+    NotFound,
+    // lambda names are unique, another lambda already has this one
+    NameTaken(String),
+    Sqlite(rusqlite::Error),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DbError::UnsupportedUrl(url) => write!(f, "Unsupported database url `{}`", url),
+            DbError::NotFound => write!(f, "Record not found"),
+            DbError::NameTaken(name) => write!(f, "Another lambda is named `{}`", name),
+            DbError::Sqlite(e) => write!(f, "{}", e),
+            DbError::Json(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for DbError {}
+
+impl From<rusqlite::Error> for DbError {
+    fn from(e: rusqlite::Error) -> Self {
+        DbError::Sqlite(e)
+    }
+}
+
+impl From<serde_json::Error> for DbError {
+    fn from(e: serde_json::Error) -> Self {
+        DbError::Json(e)
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct LambdaRecord {
+    pub id: String,
+    pub name: String,
+    pub source: String,
+    pub state: String,
+    pub dirname: String,
+    pub lambda_sol: String,
+    pub app_gateway_sol: String,
+    pub deployer_sol: String,
+    pub version: i64,
+    pub layout: Vec<StateVariable>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+impl LambdaRecord {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get("id")?,
+            name: row.get("name")?,
+            source: row.get("source")?,
+            state: row.get("state")?,
+            dirname: row.get("dirname")?,
+            lambda_sol: row.get("lambda_sol")?,
+            app_gateway_sol: row.get("app_gateway_sol")?,
+            deployer_sol: row.get("deployer_sol")?,
+            version: row.get("version")?,
+            layout: layout(row)?,
+            created_at: row.get("created_at")?,
+            updated_at: row.get("updated_at")?,
+        })
+    }
+}
+
+// an earlier (or the current) source of a lambda
+#[derive(Clone, Debug, Serialize)]
+pub struct LambdaVersion {
+    pub version: i64,
+    pub source: String,
+    pub state: String,
+    pub layout: Vec<StateVariable>,
+    pub dirname: String,
+    pub created_at: i64,
+}
+
+impl LambdaVersion {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            version: row.get("version")?,
+            source: row.get("source")?,
+            state: row.get("state")?,
+            layout: layout(row)?,
+            dirname: row.get("dirname")?,
+            created_at: row.get("created_at")?,
+        })
+    }
+}
+
+fn layout(row: &Row) -> rusqlite::Result<Vec<StateVariable>> {
+    let text: String = row.get("layout")?;
+    Ok(serde_json::from_str(&text).unwrap_or_default())
+}
+
+pub struct NewLambda<'a> {
+    pub name: &'a str,
+    pub source: &'a str,
+    pub state: &'a str,
+    pub layout: &'a [StateVariable],
+    pub dirname: &'a str,
+    pub sources: &'a GeneratedSources,
+}
+
+// one compile of a lambda directory, keyed by the id of the job that ran it
+#[derive(Clone, Debug, Serialize)]
+pub struct BuildRecord {
+    pub id: String,
+    pub dirname: String,
+    pub status: String,
+    pub message: Option<String>,
+    pub artifacts: Option<Value>,
+    pub diagnostics: Option<Value>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+impl BuildRecord {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        let json = |column: &str| -> rusqlite::Result<Option<Value>> {
+            let text: Option<String> = row.get(column)?;
+            Ok(text.and_then(|text| serde_json::from_str(&text).ok()))
+        };
+        Ok(Self {
+            id: row.get("id")?,
+            dirname: row.get("dirname")?,
+            status: row.get("status")?,
+            message: row.get("message")?,
+            artifacts: json("artifacts")?,
+            diagnostics: json("diagnostics")?,
+            created_at: row.get("created_at")?,
+            updated_at: row.get("updated_at")?,
+        })
+    }
+}
+
+// terminal or intermediate outcome of a build, as written by the compile job
+pub struct BuildUpdate<'a> {
+    pub status: &'a str,
+    pub message: Option<&'a str>,
+    pub artifacts: Option<&'a Value>,
+    pub diagnostics: Option<&'a Value>,
+}
+
+// a contract address of a deployed lambda, `kind` is "lambda" or "gateway"
+#[derive(Clone, Debug, Serialize)]
+pub struct Deployment {
+    pub address: String,
+    pub lambda_id: String,
+    pub kind: String,
+    pub created_at: i64,
+}
+
+impl Deployment {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            address: row.get("address")?,
+            lambda_id: row.get("lambda_id")?,
+            kind: row.get("kind")?,
+            created_at: row.get("created_at")?,
+        })
+    }
+}
+
+// one call of a deployed lambda: a `LambdaCalled` event ("event") or a transaction sent to its
+// gateway ("transaction"). Transactions have no log, their log_index is -1.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Invocation {
+    pub tx_hash: String,
+    pub log_index: i64,
+    pub lambda_id: String,
+    pub kind: String,
+    pub block_number: i64,
+    pub block_timestamp: i64,
+    pub caller: String,
+    pub args: String,
+    pub success: bool,
+    pub gas_used: Option<i64>,
+    // effective price in wei
+    pub gas_price: Option<i64>,
+}
+
+impl Invocation {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            tx_hash: row.get("tx_hash")?,
+            log_index: row.get("log_index")?,
+            lambda_id: row.get("lambda_id")?,
+            kind: row.get("kind")?,
+            block_number: row.get("block_number")?,
+            block_timestamp: row.get("block_timestamp")?,
+            caller: row.get("caller")?,
+            args: row.get("args")?,
+            success: row.get("success")?,
+            gas_used: row.get("gas_used")?,
+            gas_price: row.get("gas_price")?,
+        })
+    }
+}
+
+pub struct Database {
+    conn: Mutex<Connection>,
+}
+
+impl Database {
+    // `sqlite://path/to/file.db` or `sqlite::memory:`
+    pub fn open(url: &str) -> Result<Self, DbError> {
+        let conn = match url.strip_prefix("sqlite:") {
+            Some(":memory:") => Connection::open_in_memory()?,
+            Some(path) => Connection::open(path.trim_start_matches("//"))?,
+            None => return Err(DbError::UnsupportedUrl(url.to_string())),
+        };
+        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+        let db = Self {
+            conn: Mutex::new(conn),
+        };
+        db.migrate()?;
+        Ok(db)
+    }
+
+    // the sqlite file at LAMBDA_DB_PATH if set, otherwise a lambdas.db next to the process.
+    // DATABASE_URL isn't read, deployments point it at databases of other services.
+    pub fn from_env() -> Result<Self, DbError> {
+        let path = std::env::var("LAMBDA_DB_PATH").unwrap_or_else(|_| "lambdas.db".to_string());
+        Self::open(&format!("sqlite://{}", path))
+    }
+
+    fn migrate(&self) -> Result<(), DbError> {
+        let mut conn = self.conn();
+        let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let tx = conn.transaction()?;
+            tx.execute_batch(migration)?;
+            tx.execute_batch(&format!("PRAGMA user_version = {}", index + 1))?;
+            tx.commit()?;
+        }
+        Ok(())
+    }
+
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        // a panic while holding the lock can't leave sqlite half written, keep going
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // stores `lambda` under its name: a new lambda, or the next version of the one with that name
+    // unless the source is what its latest version already has. the lookup and the write share a
+    // transaction, so concurrent submissions of a new name can't both insert it
+    pub fn save_lambda(&self, lambda: &NewLambda) -> Result<LambdaRecord, DbError> {
+        let id = {
+            let mut conn = self.conn();
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let existing = tx
+                .query_row(
+                    "SELECT id, dirname FROM lambdas WHERE name = ?1",
+                    [lambda.name],
+                    |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)),
+                )
+                .optional()?;
+            let id = match existing {
+                Some((id, dirname)) if dirname == lambda.dirname => id,
+                Some((id, _)) => {
+                    update_lambda_row(&tx, &id, lambda)?;
+                    record_version(&tx, &id)?;
+                    id
+                }
+                None => {
+                    let id = Uuid::new_v4().to_string();
+                    insert_lambda_row(&tx, &id, lambda)?;
+                    record_version(&tx, &id)?;
+                    id
+                }
+            };
+            tx.commit()?;
+            id
+        };
+        self.get_lambda(&id)?.ok_or(DbError::NotFound)
+    }
+
+    pub fn get_lambda(&self, id: &str) -> Result<Option<LambdaRecord>, DbError> {
+        Ok(self
+            .conn()
+            .query_row(
+                "SELECT * FROM lambdas WHERE id = ?1",
+                [id],
+                LambdaRecord::from_row,
+            )
+            .optional()?)
+    }
+
+    pub fn list_lambdas(&self) -> Result<Vec<LambdaRecord>, DbError> {
+        let conn = self.conn();
+        let mut statement = conn.prepare("SELECT * FROM lambdas ORDER BY created_at, rowid")?;
+        let lambdas = statement
+            .query_map([], LambdaRecord::from_row)?
+            .collect::<rusqlite::Result<Vec<LambdaRecord>>>()?;
+        Ok(lambdas)
+    }
+
+    // makes `lambda` the next version of `id` unless its source is unchanged, earlier versions
+    // stay in lambda_versions
+    pub fn add_version(
+        &self,
+        id: &str,
+        lambda: &NewLambda,
+    ) -> Result<Option<LambdaRecord>, DbError> {
+        {
+            let mut conn = self.conn();
+            let tx = conn.transaction()?;
+            let taken: bool = tx.query_row(
+                "SELECT EXISTS (SELECT 1 FROM lambdas WHERE name = ?1 AND id != ?2)
+                    AND EXISTS (SELECT 1 FROM lambdas WHERE id = ?2)",
+                params![lambda.name, id],
+                |row| row.get(0),
+            )?;
+            if taken {
+                return Err(DbError::NameTaken(lambda.name.to_string()));
+            }
+            let dirname: Option<String> = tx
+                .query_row("SELECT dirname FROM lambdas WHERE id = ?1", [id], |row| {
+                    row.get(0)
+                })
+                .optional()?;
+            match dirname {
+                None => return Ok(None),
+                // the same source generates the same directory, that's no new version
+                Some(dirname) if dirname == lambda.dirname => {}
+                Some(_) => {
+                    update_lambda_row(&tx, id, lambda)?;
+                    record_version(&tx, id)?;
+                    tx.commit()?;
+                }
+            }
+        }
+        self.get_lambda(id)
+    }
+
+    pub fn get_version(&self, id: &str, version: i64) -> Result<Option<LambdaVersion>, DbError> {
+        Ok(self
+            .conn()
+            .query_row(
+                "SELECT * FROM lambda_versions WHERE lambda_id = ?1 AND version = ?2",
+                params![id, version],
+                LambdaVersion::from_row,
+            )
+            .optional()?)
+    }
+
+    pub fn list_versions(&self, id: &str) -> Result<Vec<LambdaVersion>, DbError> {
+        let conn = self.conn();
+        let mut statement =
+            conn.prepare("SELECT * FROM lambda_versions WHERE lambda_id = ?1 ORDER BY version")?;
+        let versions = statement
+            .query_map([id], LambdaVersion::from_row)?
+            .collect::<rusqlite::Result<Vec<LambdaVersion>>>()?;
+        Ok(versions)
+    }
+
+    // returns the directories no remaining lambda version points at, their builds are dropped
+    pub fn delete_lambda(&self, id: &str) -> Result<Option<Vec<String>>, DbError> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        let dirnames = tx
+            .prepare("SELECT DISTINCT dirname FROM lambda_versions WHERE lambda_id = ?1")?
+            .query_map([id], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        if tx.execute("DELETE FROM lambdas WHERE id = ?1", [id])? == 0 {
+            return Ok(None);
+        }
+        let mut unused = Vec::new();
+        for dirname in dirnames {
+            let in_use: bool = tx.query_row(
+                "SELECT EXISTS (SELECT 1 FROM lambda_versions WHERE dirname = ?1)",
+                [&dirname],
+                |row| row.get(0),
+            )?;
+            if !in_use {
+                tx.execute("DELETE FROM builds WHERE dirname = ?1", [&dirname])?;
+                unused.push(dirname);
+            }
+        }
+        tx.commit()?;
+        Ok(Some(unused))
+    }
+
+    // whether any version of any lambda was generated into `dirname`
+    pub fn dirname_in_use(&self, dirname: &str) -> Result<bool, DbError> {
+        Ok(self.conn().query_row(
+            "SELECT EXISTS (SELECT 1 FROM lambda_versions WHERE dirname = ?1)",
+            [dirname],
+            |row| row.get(0),
+        )?)
+    }
+
+    pub fn insert_build(&self, id: &str, dirname: &str) -> Result<(), DbError> {
+        self.conn().execute(
+            "INSERT INTO builds (id, dirname, status, created_at, updated_at)
+             VALUES (?1, ?2, 'queued', ?3, ?3)",
+            params![id, dirname, now()],
+        )?;
+        Ok(())
+    }
+
+    pub fn update_build(&self, id: &str, update: &BuildUpdate) -> Result<(), DbError> {
+        let artifacts = update.artifacts.map(serde_json::to_string).transpose()?;
+        let diagnostics = update.diagnostics.map(serde_json::to_string).transpose()?;
+        self.conn().execute(
+            "UPDATE builds SET status = ?2, message = ?3, artifacts = ?4, diagnostics = ?5,
+                updated_at = ?6
+             WHERE id = ?1",
+            params![
+                id,
+                update.status,
+                update.message,
+                artifacts,
+                diagnostics,
+                now()
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_build(&self, id: &str) -> Result<Option<BuildRecord>, DbError> {
+        Ok(self
+            .conn()
+            .query_row(
+                "SELECT * FROM builds WHERE id = ?1",
+                [id],
+                BuildRecord::from_row,
+            )
+            .optional()?)
+    }
+
+    pub fn latest_build(&self, dirname: &str) -> Result<Option<BuildRecord>, DbError> {
+        Ok(self
+            .conn()
+            .query_row(
+                "SELECT * FROM builds WHERE dirname = ?1 ORDER BY created_at DESC, rowid DESC",
+                [dirname],
+                BuildRecord::from_row,
+            )
+            .optional()?)
+    }
+
+    // a lambda can be registered at several addresses, an address belongs to one lambda
+    pub fn add_deployment(
+        &self,
+        lambda_id: &str,
+        address: &str,
+        kind: &str,
+    ) -> Result<Deployment, DbError> {
+        let address = address.to_lowercase();
+        self.conn().execute(
+            "INSERT INTO deployments (address, lambda_id, kind, created_at)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (address) DO UPDATE SET lambda_id = excluded.lambda_id,
+                kind = excluded.kind",
+            params![address, lambda_id, kind, now()],
+        )?;
+        Ok(self.conn().query_row(
+            "SELECT * FROM deployments WHERE address = ?1",
+            [&address],
+            Deployment::from_row,
+        )?)
+    }
+
+    // every deployment when `lambda_id` is None
+    pub fn list_deployments(&self, lambda_id: Option<&str>) -> Result<Vec<Deployment>, DbError> {
+        let conn = self.conn();
+        let mut statement = conn.prepare(
+            "SELECT * FROM deployments WHERE ?1 IS NULL OR lambda_id = ?1 ORDER BY created_at",
+        )?;
+        let deployments = statement
+            .query_map([lambda_id], Deployment::from_row)?
+            .collect::<rusqlite::Result<Vec<Deployment>>>()?;
+        Ok(deployments)
+    }
+
+    pub fn last_indexed_block(&self) -> Result<Option<(i64, String)>, DbError> {
+        Ok(self
+            .conn()
+            .query_row(
+                "SELECT number, hash FROM indexed_blocks ORDER BY number DESC",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?)
+    }
+
+    pub fn indexed_block_hash(&self, number: i64) -> Result<Option<String>, DbError> {
+        Ok(self
+            .conn()
+            .query_row(
+                "SELECT hash FROM indexed_blocks WHERE number = ?1",
+                [number],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    // forgets every block after `number`, with whatever was indexed from them
+    pub fn rewind_blocks(&self, number: i64) -> Result<(), DbError> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM invocations WHERE block_number > ?1", [number])?;
+        tx.execute("DELETE FROM indexed_blocks WHERE number > ?1", [number])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    // stores a batch of processed blocks atomically, only the hashes of the last `keep` blocks are
+    // retained since reorgs deeper than that aren't detected anyway
+    pub fn record_blocks(
+        &self,
+        blocks: &[(i64, String)],
+        invocations: &[Invocation],
+        keep: i64,
+    ) -> Result<(), DbError> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        for (number, hash) in blocks {
+            tx.execute(
+                "INSERT OR REPLACE INTO indexed_blocks (number, hash) VALUES (?1, ?2)",
+                params![number, hash],
+            )?;
+        }
+        for invocation in invocations {
+            tx.execute(
+                "INSERT OR IGNORE INTO invocations (tx_hash, log_index, lambda_id, kind,
+                    block_number, block_timestamp, caller, args, success, gas_used, gas_price)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                params![
+                    invocation.tx_hash,
+                    invocation.log_index,
+                    invocation.lambda_id,
+                    invocation.kind,
+                    invocation.block_number,
+                    invocation.block_timestamp,
+                    invocation.caller,
+                    invocation.args,
+                    invocation.success,
+                    invocation.gas_used,
+                    invocation.gas_price,
+                ],
+            )?;
+        }
+        if let Some((last, _)) = blocks.iter().max_by_key(|(number, _)| *number) {
+            tx.execute(
+                "DELETE FROM indexed_blocks WHERE number <= ?1",
+                [last - keep],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    // invocations of `lambda_id` in blocks timestamped within [from, to)
+    pub fn list_invocations(
+        &self,
+        lambda_id: &str,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<Invocation>, DbError> {
+        let conn = self.conn();
+        let mut statement = conn.prepare(
+            "SELECT * FROM invocations
+             WHERE lambda_id = ?1 AND block_timestamp >= ?2 AND block_timestamp < ?3
+             ORDER BY block_number, log_index",
+        )?;
+        let invocations = statement
+            .query_map(params![lambda_id, from, to], Invocation::from_row)?
+            .collect::<rusqlite::Result<Vec<Invocation>>>()?;
+        Ok(invocations)
+    }
+}
+
+// snapshots the current row of `id` as its version
+fn insert_lambda_row(tx: &Transaction, id: &str, lambda: &NewLambda) -> Result<(), DbError> {
+    tx.execute(
+        "INSERT INTO lambdas (id, name, source, state, dirname, lambda_sol,
+            app_gateway_sol, deployer_sol, layout, created_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?10)",
+        params![
+            id,
+            lambda.name,
+            lambda.source,
+            lambda.state,
+            lambda.dirname,
+            lambda.sources.lambda,
+            lambda.sources.app_gateway,
+            lambda.sources.deployer,
+            serde_json::to_string(lambda.layout)?,
+            now(),
+        ],
+    )?;
+    Ok(())
+}
+
+fn update_lambda_row(tx: &Transaction, id: &str, lambda: &NewLambda) -> Result<(), DbError> {
+    tx.execute(
+        "UPDATE lambdas SET name = ?2, source = ?3, state = ?4, dirname = ?5,
+            lambda_sol = ?6, app_gateway_sol = ?7, deployer_sol = ?8, layout = ?9,
+            version = version + 1, updated_at = ?10
+         WHERE id = ?1",
+        params![
+            id,
+            lambda.name,
+            lambda.source,
+            lambda.state,
+            lambda.dirname,
+            lambda.sources.lambda,
+            lambda.sources.app_gateway,
+            lambda.sources.deployer,
+            serde_json::to_string(lambda.layout)?,
+            now(),
+        ],
+    )?;
+    Ok(())
+}
+
+fn record_version(tx: &Transaction, id: &str) -> rusqlite::Result<()> {
+    tx.execute(
+        "INSERT INTO lambda_versions (lambda_id, version, source, state, layout, dirname, created_at)
+         SELECT id, version, source, state, layout, dirname, updated_at FROM lambdas WHERE id = ?1",
+        [id],
+    )?;
+    Ok(())
+}
+
+pub(crate) fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::ContractGenerator;
+
+    fn sources(function: &str) -> GeneratedSources {
+        let mut generator = ContractGenerator::default();
+        generator.process_lambda(function.to_string()).unwrap();
+        generator.generate(function)
+    }
+
+    #[test]
+    fn test_lambda_and_build_round_trip() -> Result<(), DbError> {
+        let db = Database::open("sqlite::memory:")?;
+        // running migrations again is a no-op
+        db.migrate()?;
+
+        let function = "function lambda(address user) { balances[user] = 1; }";
+        let lambda = db.save_lambda(&NewLambda {
+            name: "balances",
+            source: function,
+            state: "mapping(address=>uint256) public balances;\n",
+            layout: &[],
+            dirname: "lambda_ab",
+            sources: &sources(function),
+        })?;
+        assert_eq!(db.get_lambda(&lambda.id)?.unwrap().source, function);
+        assert_eq!(db.list_lambdas()?.len(), 1);
+        assert!(lambda.lambda_sol.contains("balances"));
+
+        db.insert_build("job-1", "lambda_ab")?;
+        assert_eq!(db.latest_build("lambda_ab")?.unwrap().status, "queued");
+        let artifacts = serde_json::json!({"app_gateway": {"bytecode": "0x6080"}});
+        db.update_build(
+            "job-1",
+            &BuildUpdate {
+                status: "succeeded",
+                message: None,
+                artifacts: Some(&artifacts),
+                diagnostics: None,
+            },
+        )?;
+        let build = db.get_build("job-1")?.unwrap();
+        assert_eq!(build.status, "succeeded");
+        assert_eq!(build.artifacts, Some(artifacts));
+
+        let edited = "function lambda(address user) { balances[user] = 2; }";
+        let layout = [StateVariable {
+            name: "balances".to_string(),
+            var_type: "mapping(address=>uint256)".to_string(),
+        }];
+        let next = NewLambda {
+            name: "balances",
+            source: edited,
+            state: &lambda.state,
+            layout: &layout,
+            dirname: "lambda_cd",
+            sources: &sources(edited),
+        };
+        // an unchanged source is no new version
+        let current = NewLambda {
+            name: "balances",
+            source: function,
+            state: &lambda.state,
+            layout: &[],
+            dirname: "lambda_ab",
+            sources: &sources(function),
+        };
+        assert_eq!(db.add_version(&lambda.id, &current)?.unwrap().version, 1);
+        let updated = db.add_version(&lambda.id, &next)?.unwrap();
+        assert_eq!(
+            (updated.id.as_str(), updated.version),
+            (lambda.id.as_str(), 2)
+        );
+        assert_eq!(updated.layout, layout);
+        assert!(db.add_version("missing", &next)?.is_none());
+        // saving under a known name adds a version only when the source changed
+        assert_eq!(db.save_lambda(&next)?.version, 2);
+        let saved = db.save_lambda(&NewLambda {
+            dirname: "lambda_ef",
+            ..next
+        })?;
+        assert_eq!((saved.id.as_str(), saved.version), (lambda.id.as_str(), 3));
+        let other = db.save_lambda(&NewLambda {
+            name: "other",
+            ..next
+        })?;
+        assert_ne!(other.id, lambda.id);
+        assert!(matches!(
+            db.add_version(&other.id, &next),
+            Err(DbError::NameTaken(_))
+        ));
+        assert_eq!(db.list_lambdas()?.len(), 2);
+        let versions = db.list_versions(&lambda.id)?;
+        assert_eq!(versions.len(), 3);
+        assert_eq!(versions[0].source, function);
+        assert_eq!(db.get_version(&lambda.id, 2)?.unwrap().dirname, "lambda_cd");
+
+        let mut unused = db.delete_lambda(&lambda.id)?.unwrap();
+        unused.sort();
+        // `other` still uses lambda_cd
+        assert_eq!(unused, vec!["lambda_ab", "lambda_ef"]);
+        assert!(db.delete_lambda(&lambda.id)?.is_none());
+        assert!(db.list_versions(&lambda.id)?.is_empty());
+        assert!(db.latest_build("lambda_ab")?.is_none());
+
+        assert!(matches!(
+            Database::open("postgresql://localhost/lambdas"),
+            Err(DbError::UnsupportedUrl(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_duplicate_names_are_renamed_by_migration() -> Result<(), DbError> {
+        let conn = Connection::open_in_memory()?;
+        for migration in &MIGRATIONS[..3] {
+            conn.execute_batch(migration)?;
+        }
+        conn.execute_batch("PRAGMA user_version = 3")?;
+        for (id, updated_at) in [("old", 1), ("new", 2)] {
+            conn.execute(
+                "INSERT INTO lambdas (id, name, source, state, dirname, lambda_sol,
+                    app_gateway_sol, deployer_sol, created_at, updated_at)
+                 VALUES (?1, 'dup', '', '', '', '', '', '', ?2, ?2)",
+                params![id, updated_at],
+            )?;
+        }
+        let db = Database {
+            conn: Mutex::new(conn),
+        };
+        db.migrate()?;
+
+        let mut names = db
+            .list_lambdas()?
+            .into_iter()
+            .map(|lambda| (lambda.id, lambda.name))
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                ("new".to_string(), "dup".to_string()),
+                ("old".to_string(), "dup-old".to_string())
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,402 @@
+use actix_web::{web, HttpResponse};
+use parser::layout::{compare_layouts, LayoutChange};
+
+use super::write_lambda::generate_lambda;
+use crate::{
+    db::{now, Database, DbError, LambdaRecord, NewLambda},
+    metrics::{aggregate, bucket_count, MAX_BUCKETS},
+    AppState, DeploymentInput, LambdaDetail, LambdaInput, MetricsQuery,
+};
+
+// storage-breaking changes the current version makes to the previous one
+pub(crate) fn layout_changes(
+    db: &Database,
+    lambda: &LambdaRecord,
+) -> Result<Vec<LayoutChange>, DbError> {
+    Ok(db
+        .get_version(&lambda.id, lambda.version - 1)?
+        .map(|previous| compare_layouts(&previous.layout, &lambda.layout))
+        .unwrap_or_default())
+}
+
+fn with_build(db: &Database, lambda: LambdaRecord) -> Result<LambdaDetail, DbError> {
+    let build = db.latest_build(&lambda.dirname)?;
+    let layout_changes = layout_changes(db, &lambda)?;
+    Ok(LambdaDetail {
+        lambda,
+        build,
+        layout_changes,
+    })
+}
+
+fn db_failure(e: DbError) -> HttpResponse {
+    HttpResponse::InternalServerError().json(format!("Database error: {}", e))
+}
+
+fn not_found(id: &str) -> HttpResponse {
+    HttpResponse::NotFound().json(format!("Unknown lambda {}", id))
+}
+
+pub async fn list_lambdas(data: web::Data<AppState>) -> HttpResponse {
+    let lambdas = data.db.list_lambdas().and_then(|lambdas| {
+        lambdas
+            .into_iter()
+            .map(|lambda| with_build(&data.db, lambda))
+            .collect::<Result<Vec<LambdaDetail>, DbError>>()
+    });
+    match lambdas {
+        Ok(lambdas) => HttpResponse::Ok().json(lambdas),
+        Err(e) => db_failure(e),
+    }
+}
+
+pub async fn get_lambda(data: web::Data<AppState>, id: web::Path<String>) -> HttpResponse {
+    match data.db.get_lambda(&id) {
+        Ok(Some(lambda)) => match with_build(&data.db, lambda) {
+            Ok(detail) => HttpResponse::Ok().json(detail),
+            Err(e) => db_failure(e),
+        },
+        Ok(None) => not_found(&id),
+        Err(e) => db_failure(e),
+    }
+}
+
+pub async fn list_versions(data: web::Data<AppState>, id: web::Path<String>) -> HttpResponse {
+    match data.db.get_lambda(&id) {
+        Ok(Some(_)) => {}
+        Ok(None) => return not_found(&id),
+        Err(e) => return db_failure(e),
+    }
+    match data.db.list_versions(&id) {
+        Ok(versions) => HttpResponse::Ok().json(versions),
+        Err(e) => db_failure(e),
+    }
+}
+
+// registers a deployed contract of the lambda with the indexer
+pub async fn add_deployment(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    deployment: web::Json<DeploymentInput>,
+) -> HttpResponse {
+    let is_address = deployment.address.len() == 42
+        && deployment.address.starts_with("0x")
+        && deployment.address[2..]
+            .chars()
+            .all(|c| c.is_ascii_hexdigit());
+    if !is_address || !["lambda", "gateway"].contains(&deployment.kind.as_str()) {
+        return HttpResponse::BadRequest().json(format!(
+            "Expected a 0x address of kind `lambda` or `gateway`, got `{}` of kind `{}`",
+            deployment.address, deployment.kind
+        ));
+    }
+    match data.db.get_lambda(&id) {
+        Ok(Some(_)) => {}
+        Ok(None) => return not_found(&id),
+        Err(e) => return db_failure(e),
+    }
+    match data
+        .db
+        .add_deployment(&id, &deployment.address, &deployment.kind)
+    {
+        Ok(deployment) => HttpResponse::Created().json(deployment),
+        Err(e) => db_failure(e),
+    }
+}
+
+pub async fn list_deployments(data: web::Data<AppState>, id: web::Path<String>) -> HttpResponse {
+    match data.db.get_lambda(&id) {
+        Ok(Some(_)) => {}
+        Ok(None) => return not_found(&id),
+        Err(e) => return db_failure(e),
+    }
+    match data.db.list_deployments(Some(&id)) {
+        Ok(deployments) => HttpResponse::Ok().json(deployments),
+        Err(e) => db_failure(e),
+    }
+}
+
+// indexed activity of the lambda in time buckets. `from` and `to` are unix seconds and default to
+// the last 24 hours, `bucket` is in seconds and defaults to an hour.
+pub async fn lambda_metrics(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    query: web::Query<MetricsQuery>,
+) -> HttpResponse {
+    let to = query.to.unwrap_or_else(now);
+    let from = query.from.unwrap_or(to.saturating_sub(86_400));
+    let bucket = query.bucket.unwrap_or(3_600);
+    if bucket_count(from, to, bucket).is_none() {
+        return HttpResponse::BadRequest().json(format!(
+            "Expected from < to split into at most {} buckets, got from={} to={} bucket={}",
+            MAX_BUCKETS, from, to, bucket
+        ));
+    }
+    match data.db.get_lambda(&id) {
+        Ok(Some(_)) => {}
+        Ok(None) => return not_found(&id),
+        Err(e) => return db_failure(e),
+    }
+    match data.db.list_invocations(&id, from, to) {
+        Ok(invocations) => match aggregate(&invocations, from, to, bucket) {
+            Some(metrics) => HttpResponse::Ok().json(metrics),
+            None => HttpResponse::BadRequest().json("Invalid metrics range"),
+        },
+        Err(e) => db_failure(e),
+    }
+}
+
+// regenerates the project from the new source as the next version, the lambda keeps its id
+pub async fn update_lambda(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    lambda: web::Json<LambdaInput>,
+) -> HttpResponse {
+    match data.db.get_lambda(&id) {
+        Ok(Some(_)) => {}
+        Ok(None) => return not_found(&id),
+        Err(e) => return db_failure(e),
+    }
+    let generated = match generate_lambda(&data, &lambda.function) {
+        Ok(generated) => generated,
+        Err(response) => return response,
+    };
+    let updated = data.db.add_version(
+        &id,
+        &NewLambda {
+            name: &lambda.lambda_name,
+            source: &lambda.function,
+            state: &generated.state,
+            layout: &generated.layout,
+            dirname: &generated.dirname,
+            sources: &generated.sources,
+        },
+    );
+    match updated {
+        Ok(Some(record)) => match with_build(&data.db, record) {
+            Ok(detail) => HttpResponse::Ok().json(detail),
+            Err(e) => db_failure(e),
+        },
+        // deleted while we were generating
+        Ok(None) => not_found(&id),
+        Err(e @ DbError::NameTaken(_)) => HttpResponse::Conflict().json(e.to_string()),
+        Err(e) => db_failure(e),
+    }
+}
+
+pub async fn delete_lambda(data: web::Data<AppState>, id: web::Path<String>) -> HttpResponse {
+    let unused = match data.db.delete_lambda(&id) {
+        Ok(Some(unused)) => unused,
+        Ok(None) => return not_found(&id),
+        Err(e) => return db_failure(e),
+    };
+    // directories are shared by every version with the same source, only the last one removes it.
+    // builds of the directory hold its key, and a lambda stored meanwhile may use it again
+    for dirname in unused {
+        let Ok(dir) = data.workspace.lambda_dir(&dirname) else {
+            continue;
+        };
+        let removed = data
+            .jobs
+            .exclusive(&dirname, || match data.db.dirname_in_use(&dirname) {
+                Ok(true) => Ok(()),
+                Ok(false) => match std::fs::remove_dir_all(&dir) {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                        Err(format!("Failed to remove {}: {}", dir.display(), e))
+                    }
+                    _ => Ok(()),
+                },
+                Err(e) => Err(format!("Database error: {}", e)),
+            })
+            .await;
+        if let Err(message) = removed {
+            return HttpResponse::InternalServerError().json(message);
+        }
+    }
+    HttpResponse::NoContent().finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test, App};
+    use parser::{compile::ForgeCompiler, workspace::Workspace};
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+
+    use crate::{
+        handlers::write_lambda::handle_lambda,
+        jobs::{JobQueue, FINISHED_JOB_RETENTION},
+        telemetry::Telemetry,
+    };
+
+    #[actix_web::test]
+    async fn test_lambda_crud() {
+        let state = web::Data::new(AppState {
+            workspace: Workspace::new(env!("CARGO_MANIFEST_DIR")),
+            compiler: Arc::new(ForgeCompiler),
+            jobs: Arc::new(JobQueue::new(1, FINISHED_JOB_RETENTION)),
+            db: Arc::new(Database::open("sqlite::memory:").unwrap()),
+            telemetry: Arc::new(Telemetry::new()),
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(state.clone())
+                .route("/handle-lambda", web::post().to(handle_lambda))
+                .route("/lambdas", web::get().to(list_lambdas))
+                .route("/lambdas/{id}", web::get().to(get_lambda))
+                .route("/lambdas/{id}", web::put().to(update_lambda))
+                .route("/lambdas/{id}", web::delete().to(delete_lambda))
+                .route("/lambdas/{id}/versions", web::get().to(list_versions))
+                .route("/lambdas/{id}/deployments", web::get().to(list_deployments))
+                .route("/lambdas/{id}/deployments", web::post().to(add_deployment))
+                .route("/lambdas/{id}/metrics", web::get().to(lambda_metrics)),
+        )
+        .await;
+
+        let request = test::TestRequest::post()
+            .uri("/handle-lambda")
+            .set_json(json!({
+                "function": "function lambda(address user) { balances[user] = 1; }",
+                "lambda_name": "crud_lambda",
+            }))
+            .to_request();
+        let created: Value = test::call_and_read_body_json(&app, request).await;
+        let id = created["lambda_id"].as_str().unwrap().to_string();
+        let first_dir = created["dirname"].as_str().unwrap().to_string();
+
+        let request = test::TestRequest::get().uri("/lambdas").to_request();
+        let listed: Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(listed.as_array().unwrap().len(), 1);
+        assert_eq!(listed[0]["name"], "crud_lambda");
+        assert!(listed[0]["build"].is_null());
+
+        let request = test::TestRequest::put()
+            .uri(&format!("/lambdas/{}", id))
+            .set_json(json!({
+                "function": "function lambda(uint256 id) { owners[id] = msg.sender; }",
+                "lambda_name": "crud_lambda",
+            }))
+            .to_request();
+        let updated: Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(updated["id"], id.as_str());
+        assert_eq!(updated["version"], 2);
+        assert!(updated["state"].as_str().unwrap().contains("owners"));
+        // `balances` is gone from the new version, which would orphan its storage
+        assert_eq!(updated["layout_changes"][0]["change"], "removed");
+        assert_eq!(updated["layout_changes"][0]["name"], "balances");
+        let second_dir = updated["dirname"].as_str().unwrap().to_string();
+
+        // submitting the same source again adds no version
+        let request = test::TestRequest::put()
+            .uri(&format!("/lambdas/{}", id))
+            .set_json(json!({
+                "function": "function lambda(uint256 id) { owners[id] = msg.sender; }",
+                "lambda_name": "crud_lambda",
+            }))
+            .to_request();
+        let unchanged: Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(unchanged["version"], 2);
+
+        // a second lambda with the first source shares its directory
+        let request = test::TestRequest::post()
+            .uri("/handle-lambda")
+            .set_json(json!({
+                "function": "function lambda(address user) { balances[user] = 1; }",
+                "lambda_name": "crud_copy",
+            }))
+            .to_request();
+        let copy: Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(copy["dirname"], first_dir.as_str());
+        let copy_id = copy["lambda_id"].as_str().unwrap().to_string();
+
+        let request = test::TestRequest::get()
+            .uri(&format!("/lambdas/{}", id))
+            .to_request();
+        let fetched: Value = test::call_and_read_body_json(&app, request).await;
+        assert!(fetched["source"].as_str().unwrap().contains("owners"));
+
+        let request = test::TestRequest::get()
+            .uri(&format!("/lambdas/{}/versions", id))
+            .to_request();
+        let versions: Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(versions.as_array().unwrap().len(), 2);
+        assert_eq!(versions[0]["dirname"], first_dir.as_str());
+
+        let request = test::TestRequest::post()
+            .uri(&format!("/lambdas/{}/deployments", id))
+            .set_json(
+                json!({"address": "0x00000000000000000000000000000000000000AA", "kind": "gateway"}),
+            )
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, request).await.status(),
+            StatusCode::CREATED
+        );
+        let request = test::TestRequest::post()
+            .uri(&format!("/lambdas/{}/deployments", id))
+            .set_json(json!({"address": "0x1234", "kind": "gateway"}))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, request).await.status(),
+            StatusCode::BAD_REQUEST
+        );
+        let request = test::TestRequest::get()
+            .uri(&format!("/lambdas/{}/deployments", id))
+            .to_request();
+        let deployments: Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(
+            deployments[0]["address"],
+            "0x00000000000000000000000000000000000000aa"
+        );
+
+        let request = test::TestRequest::get()
+            .uri(&format!(
+                "/lambdas/{}/metrics?from=0&to=7200&bucket=3600",
+                id
+            ))
+            .to_request();
+        let metrics: Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(metrics["buckets"].as_array().unwrap().len(), 2);
+        assert_eq!(metrics["totals"]["transactions"], 0);
+        for query in [
+            "from=0&to=7200&bucket=0",
+            "from=-9223372036854775808&to=9223372036854775807&bucket=9223372036854775807",
+        ] {
+            let request = test::TestRequest::get()
+                .uri(&format!("/lambdas/{}/metrics?{}", id, query))
+                .to_request();
+            assert_eq!(
+                test::call_service(&app, request).await.status(),
+                StatusCode::BAD_REQUEST
+            );
+        }
+
+        let request = test::TestRequest::delete()
+            .uri(&format!("/lambdas/{}", id))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, request).await.status(),
+            StatusCode::NO_CONTENT
+        );
+        assert!(state.workspace.lambda_dir(&first_dir).unwrap().exists());
+        assert!(!state.workspace.lambda_dir(&second_dir).unwrap().exists());
+
+        let request = test::TestRequest::delete()
+            .uri(&format!("/lambdas/{}", copy_id))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, request).await.status(),
+            StatusCode::NO_CONTENT
+        );
+        assert!(!state.workspace.lambda_dir(&first_dir).unwrap().exists());
+
+        let request = test::TestRequest::get()
+            .uri(&format!("/lambdas/{}", id))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, request).await.status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+}
@@ -1,27 +1,253 @@
 use actix_web::{web, HttpResponse};
-use parser::compile::get_contract_bytecode;
+use parser::compile::{compile_artifacts, run_tests, CompileError, CompiledArtifacts};
+use serde_json::Value;
+use std::time::Instant;
+use uuid::Uuid;
 
-use crate::{AppState, CompileInput, CompileOutput};
+use crate::{
+    db::{BuildRecord, BuildUpdate, Database, DbError},
+    AppState, CompileInput, CompileJob, CompileOutput, JobResponse, JobStatus, TestReport,
+};
 
+pub mod lambdas;
 pub mod write_lambda;
 
+// builds run in the background, the client polls /jobs/{id} for the outcome
 pub async fn compile_lambda(
     data: web::Data<AppState>,
     lambda: web::Json<CompileInput>,
 ) -> HttpResponse {
-    let lambda_dir = lambda.0.dirname;
-    match get_contract_bytecode(lambda_dir) {
-        Ok((appgateway_bytecode, appgateway_abi, deployer_bytecode, deployer_abi)) => {
-            return HttpResponse::Ok().json(CompileOutput {
-                appgateway_bytecode,
-                deployer_bytecode,
-                appgateway_abi,
-                deployer_abi,
-            })
+    let workspace = data.workspace.clone();
+    let compiler = data.compiler.clone();
+    let db = data.db.clone();
+    let telemetry = data.telemetry.clone();
+    let CompileInput {
+        dirname,
+        run_tests: with_tests,
+    } = lambda.into_inner();
+
+    // only directories the generator wrote get a build recorded
+    match workspace.lambda_dir(&dirname) {
+        Ok(dir) if dir.is_dir() => {}
+        Ok(_) => {
+            return HttpResponse::NotFound().json(format!("Unknown lambda directory {}", dirname))
+        }
+        Err(e) => return HttpResponse::BadRequest().json(e.to_string()),
+    }
+
+    let job_id = Uuid::new_v4();
+    let build_id = job_id.to_string();
+    if let Err(e) = db.insert_build(&build_id, &dirname) {
+        return HttpResponse::InternalServerError().json(format!("Failed to record build: {}", e));
+    }
+    data.jobs.submit(job_id, dirname.clone(), move || {
+        let running = BuildUpdate {
+            status: "running",
+            message: None,
+            artifacts: None,
+            diagnostics: None,
+        };
+        if let Err(e) = db.update_build(&build_id, &running) {
+            eprintln!("Failed to record build {}: {}", build_id, e);
         }
+        let started = Instant::now();
+        let result = compile_artifacts(compiler.as_ref(), &workspace, &dirname);
+        let outcome = match &result {
+            Ok(_) => "succeeded",
+            Err(e) => {
+                let reason = match e {
+                    CompileError::Build { .. } => "build",
+                    _ => "error",
+                };
+                telemetry.forge_failures.with_label_values(&[reason]).inc();
+                "failed"
+            }
+        };
+        telemetry
+            .compile_duration
+            .with_label_values(&[outcome])
+            .observe(started.elapsed().as_secs_f64());
+        if let Err(e) = record_build(&db, &build_id, &result) {
+            eprintln!("Failed to record build {}: {}", build_id, e);
+        }
+        match result {
+            Ok(artifacts) => JobStatus::Succeeded {
+                result: CompileOutput {
+                    appgateway_bytecode: artifacts.app_gateway.bytecode,
+                    deployer_bytecode: artifacts.deployer.bytecode,
+                    appgateway_abi: artifacts.app_gateway.abi,
+                    deployer_abi: artifacts.deployer.abi,
+                    tests: with_tests.then(|| match run_tests(&workspace, &dirname) {
+                        Ok(results) => TestReport {
+                            passed: results.iter().all(|result| result.passed),
+                            results,
+                            error: None,
+                        },
+                        Err(e) => TestReport {
+                            passed: false,
+                            results: Vec::new(),
+                            error: Some(e.to_string()),
+                        },
+                    }),
+                },
+            },
+            // the user's code is at fault, report what forge said about it
+            Err(CompileError::Build { diagnostics }) => JobStatus::Failed {
+                message: "Failed to compile lambda".to_string(),
+                diagnostics,
+            },
+            Err(e) => JobStatus::Failed {
+                message: format!("Failed to compile lambda: {}", e),
+                diagnostics: Vec::new(),
+            },
+        }
+    });
+    HttpResponse::Accepted().json(CompileJob {
+        job_id,
+        queue_depth: data.jobs.queue_depth(),
+    })
+}
+
+fn record_build(
+    db: &Database,
+    id: &str,
+    result: &Result<CompiledArtifacts, CompileError>,
+) -> Result<(), DbError> {
+    match result {
+        Ok(artifacts) => db.update_build(
+            id,
+            &BuildUpdate {
+                status: "succeeded",
+                message: None,
+                artifacts: Some(&serde_json::to_value(artifacts)?),
+                diagnostics: None,
+            },
+        ),
         Err(e) => {
-            return HttpResponse::InternalServerError()
-                .json(format!("Failed to compile lambda: {}", e))
+            let diagnostics = match e {
+                CompileError::Build { diagnostics } => Some(serde_json::to_value(diagnostics)?),
+                _ => None,
+            };
+            db.update_build(
+                id,
+                &BuildUpdate {
+                    status: "failed",
+                    message: Some(&e.to_string()),
+                    artifacts: None,
+                    diagnostics: diagnostics.as_ref(),
+                },
+            )
         }
+    }
+}
+
+pub async fn prometheus_metrics(data: web::Data<AppState>) -> HttpResponse {
+    data.telemetry
+        .queue_depth
+        .set(data.jobs.queue_depth() as i64);
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(data.telemetry.render())
+}
+
+// recent jobs are answered from memory, older ones from the build they recorded
+pub async fn job_status(data: web::Data<AppState>, id: web::Path<Uuid>) -> HttpResponse {
+    let id = id.into_inner();
+    if let Some(status) = data.jobs.status(&id) {
+        return HttpResponse::Ok().json(JobResponse { id, status });
+    }
+    match data.db.get_build(&id.to_string()) {
+        Ok(Some(build)) => HttpResponse::Ok().json(JobResponse {
+            id,
+            status: build_status(build),
+        }),
+        Ok(None) => HttpResponse::NotFound().json(format!("Unknown job {}", id)),
+        Err(e) => HttpResponse::InternalServerError().json(format!("Failed to load job: {}", e)),
+    }
+}
+
+// the job status a stored build stands for. The test report isn't stored, so it's only
+// available while the job is kept in memory.
+fn build_status(build: BuildRecord) -> JobStatus {
+    let artifact = |contract: &str, field: &str| {
+        build
+            .artifacts
+            .as_ref()
+            .map_or(Value::Null, |artifacts| artifacts[contract][field].clone())
+    };
+    let bytecode = |contract: &str| {
+        artifact(contract, "bytecode")
+            .as_str()
+            .unwrap_or_default()
+            .to_string()
+    };
+    match build.status.as_str() {
+        "succeeded" => JobStatus::Succeeded {
+            result: CompileOutput {
+                appgateway_bytecode: bytecode("app_gateway"),
+                deployer_bytecode: bytecode("deployer"),
+                appgateway_abi: artifact("app_gateway", "abi"),
+                deployer_abi: artifact("deployer", "abi"),
+                tests: None,
+            },
+        },
+        "failed" => JobStatus::Failed {
+            message: build
+                .message
+                .clone()
+                .unwrap_or_else(|| "Failed to compile lambda".to_string()),
+            diagnostics: build
+                .diagnostics
+                .clone()
+                .and_then(|diagnostics| serde_json::from_value(diagnostics).ok())
+                .unwrap_or_default(),
+        },
+        // unfinished builds are always in memory, this one was cut short by a restart
+        _ => JobStatus::Failed {
+            message: "Build was interrupted".to_string(),
+            diagnostics: Vec::new(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test, App};
+    use parser::{compile::ForgeCompiler, workspace::Workspace};
+    use std::sync::Arc;
+
+    use crate::{
+        jobs::{JobQueue, FINISHED_JOB_RETENTION},
+        telemetry::Telemetry,
     };
+
+    #[actix_web::test]
+    async fn test_compile_rejects_unknown_directories() {
+        let state = web::Data::new(AppState {
+            workspace: Workspace::new(env!("CARGO_MANIFEST_DIR")),
+            compiler: Arc::new(ForgeCompiler),
+            jobs: Arc::new(JobQueue::new(1, FINISHED_JOB_RETENTION)),
+            db: Arc::new(Database::open("sqlite::memory:").unwrap()),
+            telemetry: Arc::new(Telemetry::new()),
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(state.clone())
+                .route("/compile", web::post().to(compile_lambda)),
+        )
+        .await;
+
+        for (dirname, status) in [
+            ("../template", StatusCode::BAD_REQUEST),
+            ("lambda_missing", StatusCode::NOT_FOUND),
+        ] {
+            let request = test::TestRequest::post()
+                .uri("/compile")
+                .set_json(serde_json::json!({ "dirname": dirname }))
+                .to_request();
+            assert_eq!(test::call_service(&app, request).await.status(), status);
+            assert!(state.db.latest_build(dirname).unwrap().is_none());
+        }
+    }
 }
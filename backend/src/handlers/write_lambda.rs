@@ -1,60 +1,113 @@
 use actix_web::{web, HttpResponse};
-use std::hash::DefaultHasher;
-use std::hash::Hash;
-use std::hash::Hasher;
+use parser::{layout::StateVariable, ContractGenerator, GeneratedSources};
 
-use crate::{AppState, LambdaInput, LambdaResponse};
+use super::lambdas::layout_changes;
+use crate::{db::NewLambda, AppState, LambdaError, LambdaInput, LambdaResponse};
+
+// a lambda written out to its project directory
+pub(crate) struct GeneratedLambda {
+    pub state: String,
+    pub layout: Vec<StateVariable>,
+    pub dirname: String,
+    pub sources: GeneratedSources,
+}
+
+// parses `function` and writes its project, failures come back as the response to send
+pub(crate) fn generate_lambda(
+    data: &AppState,
+    function: &str,
+) -> Result<GeneratedLambda, HttpResponse> {
+    let workspace = &data.workspace;
+    let submissions = &data.telemetry.submissions;
+    let failure = |outcome: &str, message: String, error: Option<LambdaError>| {
+        submissions.with_label_values(&[outcome]).inc();
+        HttpResponse::BadRequest().json(LambdaResponse {
+            success: false,
+            message,
+            lambda_id: None,
+            version: None,
+            dirname: None,
+            state_string: None,
+            layout_changes: Vec::new(),
+            error,
+        })
+    };
+
+    // one generator per request, so state inferred for one lambda never leaks into another
+    let mut generator = ContractGenerator::default();
+
+    if let Err(e) = generator.process_lambda(function.to_string()) {
+        let span = e.span();
+        data.telemetry
+            .parse_failures
+            .with_label_values(&[e.kind()])
+            .inc();
+        return Err(failure(
+            "parse_error",
+            format!("Failed to process lambda: {}", e),
+            Some(LambdaError {
+                kind: e.kind().to_string(),
+                line: span.map(|span| span.line),
+                column: span.map(|span| span.column),
+            }),
+        ));
+    }
+    let (state, dirname) = generator
+        .write_lambda(workspace, function.to_string())
+        .map_err(|e| {
+            failure(
+                "write_error",
+                format!("Failed to write lambda: {}", e),
+                None,
+            )
+        })?;
+
+    submissions.with_label_values(&["generated"]).inc();
+    Ok(GeneratedLambda {
+        state,
+        layout: generator.state_layout(),
+        dirname,
+        sources: generator.generate(function),
+    })
+}
 
 pub async fn handle_lambda(
     data: web::Data<AppState>,
     lambda: web::Json<LambdaInput>,
 ) -> HttpResponse {
-    let mut state_string = None;
-    let mut dirname = None;
-
-    let mut generator = match data.generator.lock() {
-        Ok(generator) => generator,
-        Err(e) => {
-            return HttpResponse::InternalServerError().json(LambdaResponse {
-                success: false,
-                message: format!("Failed to acquire lock: {}", e),
-                dirname,
-                state_string,
-            })
-        }
+    let generated = match generate_lambda(&data, &lambda.function) {
+        Ok(generated) => generated,
+        Err(response) => return response,
     };
 
-    if let Err(e) = generator.process_lambda(lambda.function.clone()) {
-        return HttpResponse::BadRequest().json(LambdaResponse {
-            success: false,
-            message: format!("Failed to process lambda: {}", e),
-            dirname,
-            state_string,
-        });
-    }
-    match generator.write_lambda(lambda.function.clone(), lambda.lambda_name.clone()) {
+    let new_lambda = NewLambda {
+        name: &lambda.lambda_name,
+        source: &lambda.function,
+        state: &generated.state,
+        layout: &generated.layout,
+        dirname: &generated.dirname,
+        sources: &generated.sources,
+    };
+    // a known name gets a new version, unless the source is what the latest version already has
+    let record = data.db.save_lambda(&new_lambda).and_then(|record| {
+        let changes = layout_changes(&data.db, &record)?;
+        Ok((record, changes))
+    });
+    let (record, changes) = match record {
+        Ok(record) => record,
         Err(e) => {
-            return HttpResponse::BadRequest().json(LambdaResponse {
+            return HttpResponse::InternalServerError().json(LambdaResponse {
                 success: false,
-                message: format!("Failed to write lambda: {}", e),
-                dirname,
-                state_string,
+                message: format!("Failed to store lambda: {}", e),
+                lambda_id: None,
+                version: None,
+                dirname: Some(generated.dirname),
+                state_string: Some(generated.state),
+                layout_changes: Vec::new(),
+                error: None,
             });
         }
-        Ok((state, final_dir)) => {
-            state_string = Some(state);
-            dirname = Some(final_dir);
-        }
-    }
-
-    if let Err(e) = generator.write_apg(dirname.clone().unwrap()) {
-        return HttpResponse::BadRequest().json(LambdaResponse {
-            success: false,
-            message: format!("Failed to write APG: {}", e),
-            dirname,
-            state_string,
-        });
-    }
+    };
 
     HttpResponse::Ok().json(LambdaResponse {
         success: true,
@@ -62,7 +115,69 @@ pub async fn handle_lambda(
             "Successfully processed and wrote lambda '{}'",
             lambda.lambda_name
         ),
-        dirname,
-        state_string,
+        lambda_id: Some(record.id),
+        version: Some(record.version),
+        dirname: Some(generated.dirname),
+        state_string: Some(generated.state),
+        layout_changes: changes,
+        error: None,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+    use parser::{compile::ForgeCompiler, workspace::Workspace};
+    use std::sync::Arc;
+
+    use crate::{
+        db::Database,
+        jobs::{JobQueue, FINISHED_JOB_RETENTION},
+        telemetry::Telemetry,
+    };
+
+    async fn submit(function: &str) -> LambdaResponse {
+        let state = web::Data::new(AppState {
+            workspace: Workspace::new(env!("CARGO_MANIFEST_DIR")),
+            compiler: Arc::new(ForgeCompiler),
+            jobs: Arc::new(JobQueue::new(1, FINISHED_JOB_RETENTION)),
+            db: Arc::new(Database::open("sqlite::memory:").unwrap()),
+            telemetry: Arc::new(Telemetry::new()),
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(state)
+                .route("/handle-lambda", web::post().to(handle_lambda)),
+        )
+        .await;
+        let request = test::TestRequest::post()
+            .uri("/handle-lambda")
+            .set_json(serde_json::json!({
+                "function": function,
+                "lambda_name": "test_lambda",
+            }))
+            .to_request();
+        test::call_and_read_body_json(&app, request).await
+    }
+
+    #[actix_web::test]
+    async fn test_lambdas_do_not_share_state() {
+        let first = submit("function lambda(address user) { balances[user] = 1; }").await;
+        let second = submit("function lambda(uint256 id) { owners[id] = msg.sender; }").await;
+
+        for response in [&first, &second] {
+            assert!(response.success, "{}", response.message);
+            let workspace = Workspace::new(env!("CARGO_MANIFEST_DIR"));
+            let lambda_dir = workspace.lambda_dir(response.dirname.as_ref().unwrap());
+            std::fs::remove_dir_all(lambda_dir.unwrap()).unwrap();
+        }
+        let first_state = first.state_string.unwrap();
+        let second_state = second.state_string.unwrap();
+        assert!(first_state.contains("balances"));
+        assert!(!first_state.contains("owners"));
+        assert!(second_state.contains("owners"));
+        assert!(first.lambda_id.is_some());
+        assert!(!second_state.contains("balances"));
+    }
+}
@@ -0,0 +1,541 @@
+use actix_web::{rt, web};
+use serde_json::{json, Value};
+use sha3::{Digest, Keccak256};
+use std::{collections::HashMap, error::Error, fmt, sync::Arc, time::Duration};
+
+use crate::db::{Database, DbError, Invocation};
+
+// emitted by the generated Lambda contract on every successful call
+pub const LAMBDA_CALLED_EVENT: &str = "LambdaCalled(address,bytes)";
+
+// block hashes kept for reorg detection, a deeper reorg goes unnoticed
+const REORG_WINDOW: i64 = 64;
+
+#[derive(Debug)]
+pub enum IndexerError {
+    Rpc(String),
+    Decode(String),
+    Db(DbError),
+}
+
+impl fmt::Display for IndexerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IndexerError::Rpc(message) => write!(f, "RPC error: {}", message),
+            IndexerError::Decode(message) => write!(f, "Unexpected RPC response: {}", message),
+            IndexerError::Db(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for IndexerError {}
+
+impl From<DbError> for IndexerError {
+    fn from(e: DbError) -> Self {
+        IndexerError::Db(e)
+    }
+}
+
+// an ethereum JSON-RPC endpoint, returns the `result` of a call
+pub trait Rpc: Send + Sync {
+    fn call(&self, method: &str, params: Value) -> Result<Value, IndexerError>;
+}
+
+pub struct HttpRpc {
+    url: String,
+    agent: ureq::Agent,
+}
+
+impl HttpRpc {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            agent: ureq::AgentBuilder::new()
+                .timeout(Duration::from_secs(30))
+                .build(),
+        }
+    }
+}
+
+impl Rpc for HttpRpc {
+    fn call(&self, method: &str, params: Value) -> Result<Value, IndexerError> {
+        let response: Value = self
+            .agent
+            .post(&self.url)
+            .send_json(json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": params}))
+            .map_err(|e| IndexerError::Rpc(format!("{} failed: {}", method, e)))?
+            .into_json()
+            .map_err(|e| IndexerError::Rpc(format!("{} failed: {}", method, e)))?;
+        if let Some(error) = response.get("error") {
+            return Err(IndexerError::Rpc(format!("{} failed: {}", method, error)));
+        }
+        Ok(response.get("result").cloned().unwrap_or(Value::Null))
+    }
+}
+
+// the parts of a block the indexer looks at
+struct Block {
+    number: i64,
+    hash: String,
+    timestamp: i64,
+    transactions: Vec<Value>,
+}
+
+// follows the chain behind `rpc` and records calls of every registered deployment. Indexing
+// starts at the cursor, so a deployment registered late isn't backfilled.
+pub struct Indexer {
+    rpc: Arc<dyn Rpc>,
+    db: Arc<Database>,
+    batch_size: i64,
+    start_block: Option<i64>,
+    interval: Duration,
+}
+
+impl Indexer {
+    pub fn new(rpc: Arc<dyn Rpc>, db: Arc<Database>) -> Self {
+        Self {
+            rpc,
+            db,
+            batch_size: 100,
+            start_block: None,
+            interval: Duration::from_secs(5),
+        }
+    }
+
+    // INDEXER_RPC_URL turns indexing on; INDEXER_START_BLOCK (default: the head at first start),
+    // INDEXER_BATCH_SIZE and INDEXER_POLL_SECS tune it
+    pub fn from_env(db: Arc<Database>) -> Option<Self> {
+        let url = std::env::var("INDEXER_RPC_URL").ok()?;
+        let env_number = |name: &str| std::env::var(name).ok()?.parse::<i64>().ok();
+        let mut indexer = Self::new(Arc::new(HttpRpc::new(&url)), db);
+        indexer.start_block = env_number("INDEXER_START_BLOCK");
+        if let Some(batch_size) = env_number("INDEXER_BATCH_SIZE") {
+            indexer.batch_size = batch_size.max(1);
+        }
+        if let Some(secs) = env_number("INDEXER_POLL_SECS") {
+            indexer.interval = Duration::from_secs(secs.max(1) as u64);
+        }
+        Some(indexer)
+    }
+
+    // polls on the blocking pool until the server stops, a failed round is retried on the next
+    pub fn spawn(self) {
+        let indexer = Arc::new(self);
+        rt::spawn(async move {
+            loop {
+                let round = indexer.clone();
+                match web::block(move || round.poll()).await {
+                    Ok(Ok(_)) => {}
+                    Ok(Err(e)) => eprintln!("Indexer: {}", e),
+                    Err(e) => eprintln!("Indexer: {}", e),
+                }
+                rt::time::sleep(indexer.interval).await;
+            }
+        });
+    }
+
+    // one round: undo a reorg if there was one, then index up to `batch_size` new blocks.
+    // Returns the last indexed block.
+    pub fn poll(&self) -> Result<Option<i64>, IndexerError> {
+        let head = quantity(&self.rpc.call("eth_blockNumber", json!([]))?)?;
+        let next = match self.db.last_indexed_block()? {
+            Some((number, hash)) => self.unwind(number, hash)? + 1,
+            None => self.start_block.unwrap_or(head),
+        };
+        if next > head {
+            return Ok(self.db.last_indexed_block()?.map(|(number, _)| number));
+        }
+        let last = head.min(next + self.batch_size - 1);
+
+        let deployments = self.db.list_deployments(None)?;
+        let watched = |kind: &str| {
+            deployments
+                .iter()
+                .filter(|deployment| deployment.kind == kind)
+                .map(|deployment| (deployment.address.clone(), deployment.lambda_id.clone()))
+                .collect::<HashMap<String, String>>()
+        };
+        let (lambdas, gateways) = (watched("lambda"), watched("gateway"));
+
+        // with nothing to watch only the cursor has to move
+        let first = if deployments.is_empty() { last } else { next };
+        let mut blocks = Vec::new();
+        for number in first..=last {
+            let block = self
+                .block(number, !gateways.is_empty())?
+                .ok_or_else(|| IndexerError::Decode(format!("block {} is missing", number)))?;
+            blocks.push(block);
+        }
+
+        let mut invocations = Vec::new();
+        for block in &blocks {
+            for transaction in &block.transactions {
+                let to = transaction["to"].as_str().map(str::to_lowercase);
+                if let Some(lambda_id) = to.and_then(|to| gateways.get(&to)) {
+                    invocations.push(self.gateway_transaction(block, transaction, lambda_id)?);
+                }
+            }
+        }
+        if !lambdas.is_empty() {
+            invocations.extend(self.lambda_events(&blocks, &lambdas)?);
+        }
+
+        let hashes = blocks
+            .iter()
+            .map(|block| (block.number, block.hash.clone()))
+            .collect::<Vec<(i64, String)>>();
+        self.db.record_blocks(&hashes, &invocations, REORG_WINDOW)?;
+        Ok(Some(last))
+    }
+
+    // walks back from the last indexed block until the chain agrees with what was indexed, and
+    // drops everything after that point. Returns the block indexing continues after.
+    fn unwind(&self, last: i64, last_hash: String) -> Result<i64, IndexerError> {
+        let mut number = last;
+        let mut stored = Some(last_hash);
+        while let Some(hash) = stored {
+            let on_chain = self.block(number, false)?.map(|block| block.hash);
+            if on_chain.as_deref() == Some(hash.as_str()) {
+                break;
+            }
+            number -= 1;
+            stored = match number {
+                0.. => self.db.indexed_block_hash(number)?,
+                _ => None,
+            };
+        }
+        if number < last {
+            self.db.rewind_blocks(number)?;
+        }
+        Ok(number)
+    }
+
+    fn block(&self, number: i64, full: bool) -> Result<Option<Block>, IndexerError> {
+        let block = self.rpc.call(
+            "eth_getBlockByNumber",
+            json!([format!("{:#x}", number), full]),
+        )?;
+        if block.is_null() {
+            return Ok(None);
+        }
+        Ok(Some(Block {
+            number,
+            hash: string(&block["hash"])?,
+            timestamp: quantity(&block["timestamp"])?,
+            transactions: match &block["transactions"] {
+                Value::Array(transactions) if full => transactions.clone(),
+                _ => Vec::new(),
+            },
+        }))
+    }
+
+    fn gateway_transaction(
+        &self,
+        block: &Block,
+        transaction: &Value,
+        lambda_id: &str,
+    ) -> Result<Invocation, IndexerError> {
+        let tx_hash = string(&transaction["hash"])?;
+        let receipt = self
+            .rpc
+            .call("eth_getTransactionReceipt", json!([tx_hash]))?;
+        let gas_price = match &receipt["effectiveGasPrice"] {
+            Value::Null => quantity(&transaction["gasPrice"])?,
+            price => quantity(price)?,
+        };
+        Ok(Invocation {
+            tx_hash,
+            log_index: -1,
+            lambda_id: lambda_id.to_string(),
+            kind: "transaction".to_string(),
+            block_number: block.number,
+            block_timestamp: block.timestamp,
+            caller: string(&transaction["from"])?,
+            args: string(&transaction["input"])?,
+            success: quantity(&receipt["status"])? == 1,
+            gas_used: Some(quantity(&receipt["gasUsed"])?),
+            gas_price: Some(gas_price),
+        })
+    }
+
+    fn lambda_events(
+        &self,
+        blocks: &[Block],
+        lambdas: &HashMap<String, String>,
+    ) -> Result<Vec<Invocation>, IndexerError> {
+        let (Some(first), Some(last)) = (blocks.first(), blocks.last()) else {
+            return Ok(Vec::new());
+        };
+        let logs = self.rpc.call(
+            "eth_getLogs",
+            json!([{
+                "fromBlock": format!("{:#x}", first.number),
+                "toBlock": format!("{:#x}", last.number),
+                "address": lambdas.keys().collect::<Vec<&String>>(),
+                "topics": [event_topic(LAMBDA_CALLED_EVENT)],
+            }]),
+        )?;
+
+        let mut invocations = Vec::new();
+        for log in logs.as_array().into_iter().flatten() {
+            if log["removed"].as_bool() == Some(true) {
+                continue;
+            }
+            let address = string(&log["address"])?;
+            let Some(lambda_id) = lambdas.get(&address) else {
+                continue;
+            };
+            let block_number = quantity(&log["blockNumber"])?;
+            let block = blocks
+                .iter()
+                .find(|block| block.number == block_number)
+                .ok_or_else(|| IndexerError::Decode(format!("log outside of {}", block_number)))?;
+            // the blocks were fetched before the logs, a mismatch means the chain moved in between
+            if string(&log["blockHash"])? != block.hash {
+                return Err(IndexerError::Rpc(format!(
+                    "block {} was reorganized while indexing",
+                    block_number
+                )));
+            }
+            let (caller, args) = decode_lambda_called(&string(&log["data"])?)?;
+            invocations.push(Invocation {
+                tx_hash: string(&log["transactionHash"])?,
+                log_index: quantity(&log["logIndex"])?,
+                lambda_id: lambda_id.clone(),
+                kind: "event".to_string(),
+                block_number,
+                block_timestamp: block.timestamp,
+                caller,
+                args,
+                success: true,
+                gas_used: None,
+                gas_price: None,
+            });
+        }
+        Ok(invocations)
+    }
+}
+
+// topic0 of an event, the keccak256 of its signature
+pub fn event_topic(signature: &str) -> String {
+    let hash = Keccak256::digest(signature.as_bytes());
+    format!(
+        "0x{}",
+        hash.iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>()
+    )
+}
+
+// abi-decodes the `(address caller, bytes args)` data of a LambdaCalled log into hex strings
+fn decode_lambda_called(data: &str) -> Result<(String, String), IndexerError> {
+    let invalid = || IndexerError::Decode(format!("invalid LambdaCalled data {}", data));
+    let hex = data.strip_prefix("0x").ok_or_else(invalid)?;
+    let word = |offset: usize| hex.get(offset * 2..(offset + 32) * 2).ok_or_else(invalid);
+    let number = |offset: usize| {
+        usize::from_str_radix(word(offset)?, 16)
+            .ok()
+            .filter(|number| *number <= hex.len())
+            .ok_or_else(invalid)
+    };
+
+    let caller = format!("0x{}", &word(0)?[24..]);
+    let args_offset = number(32)?;
+    let args_len = number(args_offset)?;
+    let start = (args_offset + 32) * 2;
+    let args = hex.get(start..start + args_len * 2).ok_or_else(invalid)?;
+    Ok((caller, format!("0x{}", args)))
+}
+
+fn string(value: &Value) -> Result<String, IndexerError> {
+    value
+        .as_str()
+        .map(str::to_lowercase)
+        .ok_or_else(|| IndexerError::Decode(format!("expected a string, got {}", value)))
+}
+
+// a hex encoded JSON-RPC quantity, e.g. "0x1b4"
+fn quantity(value: &Value) -> Result<i64, IndexerError> {
+    value
+        .as_str()
+        .and_then(|hex| hex.strip_prefix("0x"))
+        .and_then(|hex| i64::from_str_radix(hex, 16).ok())
+        .ok_or_else(|| IndexerError::Decode(format!("expected a quantity, got {}", value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::NewLambda;
+    use parser::ContractGenerator;
+    use std::sync::Mutex;
+
+    const LAMBDA: &str = "0x00000000000000000000000000000000000000aa";
+    const GATEWAY: &str = "0x00000000000000000000000000000000000000bb";
+    const CALLER: &str = "0x00000000000000000000000000000000000000cc";
+
+    // an in-memory chain answering the handful of calls the indexer makes
+    #[derive(Default)]
+    struct FakeChain {
+        // hash and transactions per block, the index is the block number
+        blocks: Mutex<Vec<(String, Vec<Value>)>>,
+        logs: Mutex<Vec<Value>>,
+    }
+
+    impl FakeChain {
+        fn push_block(&self, hash: &str, transactions: Vec<Value>) {
+            self.blocks
+                .lock()
+                .unwrap()
+                .push((hash.to_string(), transactions));
+        }
+    }
+
+    impl Rpc for FakeChain {
+        fn call(&self, method: &str, params: Value) -> Result<Value, IndexerError> {
+            let blocks = self.blocks.lock().unwrap();
+            match method {
+                "eth_blockNumber" => Ok(json!(format!("{:#x}", blocks.len() - 1))),
+                "eth_getBlockByNumber" => {
+                    let number = quantity(&params[0])? as usize;
+                    Ok(blocks.get(number).map_or(Value::Null, |(hash, txs)| {
+                        json!({"hash": hash, "timestamp": format!("{:#x}", 1000 + number * 12), "transactions": txs})
+                    }))
+                }
+                "eth_getTransactionReceipt" => Ok(json!({
+                    "status": "0x0",
+                    "gasUsed": "0x5208",
+                    "effectiveGasPrice": "0x3b9aca00",
+                })),
+                "eth_getLogs" => {
+                    let (from, to) = (
+                        quantity(&params[0]["fromBlock"])?,
+                        quantity(&params[0]["toBlock"])?,
+                    );
+                    let logs = self.logs.lock().unwrap();
+                    Ok(json!(logs
+                        .iter()
+                        .filter(|log| {
+                            let number = quantity(&log["blockNumber"]).unwrap();
+                            let hash = log["blockHash"].as_str().unwrap();
+                            (from..=to).contains(&number) && blocks[number as usize].0 == hash
+                        })
+                        .collect::<Vec<&Value>>()))
+                }
+                _ => Err(IndexerError::Rpc(format!("unexpected {}", method))),
+            }
+        }
+    }
+
+    fn lambda_called_data(caller: &str, args: &[u8]) -> String {
+        let args_hex = args
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+        format!(
+            "0x{:0>64}{:064x}{:064x}{:0<width$}",
+            &caller[2..],
+            64,
+            args.len(),
+            args_hex,
+            width = args.len().div_ceil(32) * 64
+        )
+    }
+
+    #[test]
+    fn test_decode_lambda_called() {
+        let data = lambda_called_data(CALLER, &[0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(
+            decode_lambda_called(&data).unwrap(),
+            (CALLER.to_string(), "0xdeadbeef".to_string())
+        );
+        assert!(decode_lambda_called("0x1234").is_err());
+        assert_eq!(
+            event_topic("Transfer(address,address,uint256)"),
+            "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef"
+        );
+    }
+
+    #[test]
+    fn test_indexer_follows_reorgs() -> Result<(), Box<dyn Error>> {
+        let db = Arc::new(Database::open("sqlite::memory:")?);
+        let function = "function lambda(uint256 value) { count = value; }";
+        let mut generator = ContractGenerator::default();
+        generator.process_lambda(function.to_string())?;
+        let lambda = db.save_lambda(&NewLambda {
+            name: "counter",
+            source: function,
+            state: &generator.global_state_string(),
+            layout: &generator.state_layout(),
+            dirname: "lambda_counter",
+            sources: &generator.generate(function),
+        })?;
+        db.add_deployment(&lambda.id, LAMBDA, "lambda")?;
+        db.add_deployment(
+            &lambda.id,
+            &GATEWAY.to_uppercase().replace("0X", "0x"),
+            "gateway",
+        )?;
+
+        let chain = Arc::new(FakeChain::default());
+        chain.push_block("0xb0", vec![]);
+        chain.push_block("0xb1", vec![]);
+        chain.push_block(
+            "0xb2",
+            vec![json!({"hash": "0x7702", "from": CALLER, "to": GATEWAY, "input": "0x01", "gasPrice": "0x1"})],
+        );
+        chain.logs.lock().unwrap().push(json!({
+            "address": LAMBDA,
+            "blockNumber": "0x2",
+            "blockHash": "0xb2",
+            "transactionHash": "0x1002",
+            "logIndex": "0x0",
+            "data": lambda_called_data(CALLER, &[7]),
+        }));
+        chain.push_block("0xb3", vec![]);
+
+        let mut indexer = Indexer::new(chain.clone(), db.clone());
+        indexer.start_block = Some(0);
+        assert_eq!(indexer.poll()?, Some(3));
+        let invocations = db.list_invocations(&lambda.id, 0, i64::MAX)?;
+        assert_eq!(invocations.len(), 2);
+        let transaction = invocations
+            .iter()
+            .find(|i| i.kind == "transaction")
+            .unwrap();
+        assert!(!transaction.success);
+        assert_eq!(transaction.gas_used, Some(21000));
+        assert_eq!(transaction.gas_price, Some(1_000_000_000));
+        let event = invocations.iter().find(|i| i.kind == "event").unwrap();
+        assert_eq!(
+            (event.caller.as_str(), event.args.as_str()),
+            (CALLER, "0x07")
+        );
+        assert_eq!(event.block_timestamp, 1024);
+
+        // blocks 2 and 3 get replaced by a longer fork without the calls
+        {
+            let mut blocks = chain.blocks.lock().unwrap();
+            blocks.truncate(2);
+            blocks.push(("0xc2".to_string(), vec![]));
+            blocks.push(("0xc3".to_string(), vec![]));
+            blocks.push(("0xc4".to_string(), vec![]));
+        }
+        assert_eq!(indexer.poll()?, Some(4));
+        assert!(db.list_invocations(&lambda.id, 0, i64::MAX)?.is_empty());
+        assert_eq!(db.indexed_block_hash(2)?.as_deref(), Some("0xc2"));
+        assert_eq!(indexer.poll()?, Some(4));
+        Ok(())
+    }
+
+    // needs a node, e.g. `anvil`, at ANVIL_RPC_URL (default http://127.0.0.1:8545)
+    #[test]
+    #[ignore]
+    fn test_anvil_block_number() {
+        let url = std::env::var("ANVIL_RPC_URL").unwrap_or("http://127.0.0.1:8545".to_string());
+        let head = HttpRpc::new(&url)
+            .call("eth_blockNumber", json!([]))
+            .unwrap();
+        assert!(quantity(&head).is_ok());
+    }
+}
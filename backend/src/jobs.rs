@@ -0,0 +1,273 @@
+use actix_web::{rt, web};
+use parser::diagnostics::Diagnostic;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::{Mutex as AsyncMutex, Semaphore};
+use uuid::Uuid;
+
+use crate::CompileOutput;
+
+// finished jobs stay in memory this long, later polls are answered from the builds table
+pub const FINISHED_JOB_RETENTION: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Clone, Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded {
+        result: CompileOutput,
+    },
+    Failed {
+        message: String,
+        diagnostics: Vec<Diagnostic>,
+    },
+}
+
+struct Job {
+    status: JobStatus,
+    finished_at: Option<Instant>,
+}
+
+// background compile jobs: at most `workers` run at once, the rest wait their turn. Jobs are
+// detached from the request that submitted them, so a client timing out doesn't cancel a build.
+pub struct JobQueue {
+    jobs: Mutex<HashMap<Uuid, Job>>,
+    permits: Arc<Semaphore>,
+    // jobs sharing a key run one after the other, forge can't build one directory twice at once
+    keys: Mutex<HashMap<String, Arc<AsyncMutex<()>>>>,
+    retention: Duration,
+}
+
+impl JobQueue {
+    pub fn new(workers: usize, retention: Duration) -> Self {
+        Self {
+            jobs: Mutex::new(HashMap::new()),
+            permits: Arc::new(Semaphore::new(workers.max(1))),
+            keys: Mutex::new(HashMap::new()),
+            retention,
+        }
+    }
+
+    // COMPILE_WORKERS if set, otherwise one worker per available core
+    pub fn from_env() -> Self {
+        let workers = std::env::var("COMPILE_WORKERS")
+            .ok()
+            .and_then(|workers| workers.parse().ok())
+            .or_else(|| std::thread::available_parallelism().ok().map(Into::into))
+            .unwrap_or(1);
+        Self::new(workers, FINISHED_JOB_RETENTION)
+    }
+
+    // `work` runs on the blocking pool and must return Succeeded or Failed. The caller picks
+    // `id`, so it can record the job elsewhere before the work can possibly start. Jobs with the
+    // same `key` never run concurrently.
+    pub fn submit<F>(self: &Arc<Self>, id: Uuid, key: String, work: F)
+    where
+        F: FnOnce() -> JobStatus + Send + 'static,
+    {
+        self.set(id, JobStatus::Queued);
+
+        let queue = Arc::clone(self);
+        rt::spawn(async move {
+            let lock = queue.key_lock(&key);
+            // wait for the key before taking a permit, so a blocked job doesn't hold a worker
+            let guard = lock.lock().await;
+            let Ok(_permit) = queue.permits.clone().acquire_owned().await else {
+                return;
+            };
+            queue.set(id, JobStatus::Running);
+            let status = web::block(work)
+                .await
+                .unwrap_or_else(|e| JobStatus::Failed {
+                    message: format!("Compile worker failed: {}", e),
+                    diagnostics: Vec::new(),
+                });
+            queue.set(id, status);
+            drop(guard);
+            queue.release_key(&key, lock);
+        });
+    }
+
+    // runs `f` while no job with `key` runs or starts, e.g. to remove the directory they build
+    pub async fn exclusive<T>(&self, key: &str, f: impl FnOnce() -> T) -> T {
+        let lock = self.key_lock(key);
+        let guard = lock.lock().await;
+        let result = f();
+        drop(guard);
+        self.release_key(key, lock);
+        result
+    }
+
+    pub fn status(&self, id: &Uuid) -> Option<JobStatus> {
+        Some(self.jobs.lock().ok()?.get(id)?.status.clone())
+    }
+
+    // jobs submitted but not yet picked up by a worker
+    pub fn queue_depth(&self) -> usize {
+        self.jobs.lock().map_or(0, |jobs| {
+            jobs.values()
+                .filter(|job| matches!(job.status, JobStatus::Queued))
+                .count()
+        })
+    }
+
+    fn set(&self, id: Uuid, status: JobStatus) {
+        if let Ok(mut jobs) = self.jobs.lock() {
+            let now = Instant::now();
+            jobs.retain(|_, job| {
+                job.finished_at
+                    .is_none_or(|finished_at| now.duration_since(finished_at) < self.retention)
+            });
+            let finished = matches!(
+                status,
+                JobStatus::Succeeded { .. } | JobStatus::Failed { .. }
+            );
+            jobs.insert(
+                id,
+                Job {
+                    status,
+                    finished_at: finished.then_some(now),
+                },
+            );
+        }
+    }
+
+    fn key_lock(&self, key: &str) -> Arc<AsyncMutex<()>> {
+        let mut keys = self.keys.lock().unwrap_or_else(|e| e.into_inner());
+        keys.entry(key.to_string()).or_default().clone()
+    }
+
+    // forgets the key once no other job holds or waits for it
+    fn release_key(&self, key: &str, lock: Arc<AsyncMutex<()>>) {
+        let mut keys = self.keys.lock().unwrap_or_else(|e| e.into_inner());
+        drop(lock);
+        if keys
+            .get(key)
+            .is_some_and(|lock| Arc::strong_count(lock) == 1)
+        {
+            keys.remove(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+        time::Duration,
+    };
+
+    fn output() -> CompileOutput {
+        CompileOutput {
+            appgateway_bytecode: "0x01".to_string(),
+            deployer_bytecode: "0x02".to_string(),
+            appgateway_abi: serde_json::json!([]),
+            deployer_abi: serde_json::json!([]),
+            tests: None,
+        }
+    }
+
+    async fn wait_until_done(queue: &JobQueue, id: &Uuid) -> JobStatus {
+        loop {
+            match queue.status(id) {
+                Some(JobStatus::Queued | JobStatus::Running) => {
+                    rt::time::sleep(Duration::from_millis(5)).await
+                }
+                Some(status) => return status,
+                None => panic!("unknown job"),
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_jobs_run_with_bounded_concurrency() {
+        let queue = Arc::new(JobQueue::new(2, FINISHED_JOB_RETENTION));
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+
+        let ids = (0..6)
+            .map(|_| {
+                let (running, peak) = (running.clone(), peak.clone());
+                let id = Uuid::new_v4();
+                queue.submit(id, id.to_string(), move || {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(20));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    JobStatus::Succeeded { result: output() }
+                });
+                id
+            })
+            .collect::<Vec<Uuid>>();
+        assert!(queue.queue_depth() > 0);
+
+        for id in &ids {
+            assert!(matches!(
+                wait_until_done(&queue, id).await,
+                JobStatus::Succeeded { .. }
+            ));
+        }
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+        assert_eq!(queue.queue_depth(), 0);
+        assert!(queue.status(&Uuid::new_v4()).is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_jobs_sharing_a_key_run_one_at_a_time() {
+        let queue = Arc::new(JobQueue::new(4, FINISHED_JOB_RETENTION));
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+
+        let ids = (0..3)
+            .map(|_| {
+                let (running, peak) = (running.clone(), peak.clone());
+                let id = Uuid::new_v4();
+                queue.submit(id, "lambda_ab".to_string(), move || {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(20));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    JobStatus::Succeeded { result: output() }
+                });
+                id
+            })
+            .collect::<Vec<Uuid>>();
+        while !matches!(queue.status(&ids[0]), Some(JobStatus::Running)) {
+            rt::time::sleep(Duration::from_millis(1)).await;
+        }
+        // an exclusive section waits for the running job
+        let running_jobs = queue
+            .exclusive("lambda_ab", || running.load(Ordering::SeqCst))
+            .await;
+        assert_eq!(running_jobs, 0);
+        for id in &ids {
+            wait_until_done(&queue, id).await;
+        }
+        assert_eq!(peak.load(Ordering::SeqCst), 1);
+        assert!(queue.keys.lock().unwrap().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_finished_jobs_expire() {
+        let queue = Arc::new(JobQueue::new(1, Duration::ZERO));
+        let finished = Uuid::new_v4();
+        queue.submit(finished, "lambda_ab".to_string(), || JobStatus::Succeeded {
+            result: output(),
+        });
+        wait_until_done(&queue, &finished).await;
+
+        let next = Uuid::new_v4();
+        queue.submit(next, "lambda_cd".to_string(), || JobStatus::Succeeded {
+            result: output(),
+        });
+        assert!(queue.status(&finished).is_none());
+        assert!(queue.status(&next).is_some());
+    }
+}
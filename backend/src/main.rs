@@ -1,14 +1,33 @@
 use actix_cors::Cors;
-use actix_web::{web, App, HttpServer};
-use handlers::{compile_lambda, write_lambda::handle_lambda};
-use parser::ContractGenerator;
+use actix_web::{middleware, web, App, HttpServer};
+use db::{BuildRecord, Database, LambdaRecord};
+use handlers::{
+    compile_lambda, job_status,
+    lambdas::{
+        add_deployment, delete_lambda, get_lambda, lambda_metrics, list_deployments, list_lambdas,
+        list_versions, update_lambda,
+    },
+    prometheus_metrics,
+    write_lambda::handle_lambda,
+};
+use indexers::Indexer;
+use jobs::{JobQueue, JobStatus};
+use parser::{
+    compile::{compiler_from_env, Compiler, TestResult},
+    layout::LayoutChange,
+    workspace::Workspace,
+};
 use serde::{Deserialize, Serialize};
-use std::sync::Mutex;
+use std::sync::Arc;
+use telemetry::{observe_requests, Telemetry};
+use uuid::Uuid;
 
+mod db;
 mod handlers;
 mod indexers;
+mod jobs;
 mod metrics;
-mod db;
+mod telemetry;
 
 #[derive(Deserialize)]
 struct LambdaInput {
@@ -19,32 +38,106 @@ struct LambdaInput {
 #[derive(Deserialize)]
 struct CompileInput {
     dirname: String,
+    // also run the generated foundry test once the build succeeded
+    #[serde(default)]
+    run_tests: bool,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 struct CompileOutput {
     appgateway_bytecode: String,
     deployer_bytecode: String,
     appgateway_abi: serde_json::Value,
     deployer_abi: serde_json::Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tests: Option<TestReport>,
+}
+
+// outcome of `forge test` on the generated LambdaTest.sol, a failed run doesn't fail the build
+#[derive(Clone, Serialize, Deserialize)]
+struct TestReport {
+    passed: bool,
+    results: Vec<TestResult>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DeploymentInput {
+    address: String,
+    // "lambda" or "gateway"
+    kind: String,
+}
+
+#[derive(Deserialize)]
+struct MetricsQuery {
+    from: Option<i64>,
+    to: Option<i64>,
+    bucket: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct CompileJob {
+    job_id: Uuid,
+    // jobs waiting for a worker, including this one if it hasn't started yet
+    queue_depth: usize,
+}
+
+#[derive(Serialize)]
+struct JobResponse {
+    id: Uuid,
+    #[serde(flatten)]
+    status: JobStatus,
 }
 
 #[derive(Serialize, Deserialize)]
 struct LambdaResponse {
     success: bool,
     message: String,
+    lambda_id: Option<String>,
+    version: Option<i64>,
     dirname: Option<String>,
     state_string: Option<String>,
+    layout_changes: Vec<LayoutChange>,
+    error: Option<LambdaError>,
+}
+
+// a stored lambda with the outcome of its most recent build
+#[derive(Serialize)]
+struct LambdaDetail {
+    #[serde(flatten)]
+    lambda: LambdaRecord,
+    build: Option<BuildRecord>,
+    layout_changes: Vec<LayoutChange>,
+}
+
+// position of a parse failure inside the submitted lambda source
+#[derive(Serialize, Deserialize)]
+struct LambdaError {
+    kind: String,
+    line: Option<usize>,
+    column: Option<usize>,
 }
 
 struct AppState {
-    generator: Mutex<ContractGenerator>,
+    workspace: Workspace,
+    compiler: Arc<dyn Compiler>,
+    jobs: Arc<JobQueue>,
+    db: Arc<Database>,
+    telemetry: Arc<Telemetry>,
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let generator = web::Data::new(AppState {
-        generator: Mutex::new(ContractGenerator::default()),
+    let db = Arc::new(Database::from_env().map_err(std::io::Error::other)?);
+    if let Some(indexer) = Indexer::from_env(db.clone()) {
+        indexer.spawn();
+    }
+    let state = web::Data::new(AppState {
+        workspace: Workspace::from_env()?,
+        compiler: Arc::from(compiler_from_env()),
+        jobs: Arc::new(JobQueue::from_env()),
+        db,
+        telemetry: Arc::new(Telemetry::new()),
     });
 
     println!("Starting server at http://localhost:8080");
@@ -58,9 +151,20 @@ async fn main() -> std::io::Result<()> {
 
         App::new()
             .wrap(cors)
-            .app_data(generator.clone())
+            .wrap(middleware::from_fn(observe_requests))
+            .app_data(state.clone())
             .route("/handle-lambda", web::post().to(handle_lambda))
             .route("/compile", web::post().to(compile_lambda))
+            .route("/jobs/{id}", web::get().to(job_status))
+            .route("/metrics", web::get().to(prometheus_metrics))
+            .route("/lambdas", web::get().to(list_lambdas))
+            .route("/lambdas/{id}", web::get().to(get_lambda))
+            .route("/lambdas/{id}", web::put().to(update_lambda))
+            .route("/lambdas/{id}", web::delete().to(delete_lambda))
+            .route("/lambdas/{id}/versions", web::get().to(list_versions))
+            .route("/lambdas/{id}/deployments", web::get().to(list_deployments))
+            .route("/lambdas/{id}/deployments", web::post().to(add_deployment))
+            .route("/lambdas/{id}/metrics", web::get().to(lambda_metrics))
     })
     .bind("0.0.0.0:8080")?
     .run()
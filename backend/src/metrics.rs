@@ -0,0 +1,194 @@
+use serde::Serialize;
+
+use crate::db::Invocation;
+
+// more buckets than this is almost certainly a mistaken `bucket` size
+pub const MAX_BUCKETS: i64 = 10_000;
+
+// activity of a lambda over one time bucket, or over the whole range for the totals
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct MetricsBucket {
+    // unix seconds, inclusive
+    pub start: i64,
+    // LambdaCalled events, i.e. calls that executed
+    pub calls: u64,
+    // transactions sent to the gateway and how many of them reverted
+    pub transactions: u64,
+    pub failures: u64,
+    pub failure_rate: f64,
+    pub gas_used: u64,
+    // wei, as a string since it overflows javascript numbers
+    pub fees: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LambdaMetrics {
+    pub from: i64,
+    pub to: i64,
+    pub bucket: i64,
+    pub buckets: Vec<MetricsBucket>,
+    pub totals: MetricsBucket,
+}
+
+// sums up into a fee while aggregating, `MetricsBucket::fees` is only filled at the end
+struct Accumulator {
+    bucket: MetricsBucket,
+    fees: u128,
+}
+
+impl Accumulator {
+    fn add(&mut self, invocation: &Invocation) {
+        match invocation.kind.as_str() {
+            "event" => self.bucket.calls += 1,
+            _ => {
+                self.bucket.transactions += 1;
+                if !invocation.success {
+                    self.bucket.failures += 1;
+                }
+            }
+        }
+        let gas_used = invocation.gas_used.unwrap_or_default().max(0) as u64;
+        let gas_price = invocation.gas_price.unwrap_or_default().max(0) as u128;
+        self.bucket.gas_used += gas_used;
+        self.fees += gas_used as u128 * gas_price;
+    }
+
+    fn finish(mut self) -> MetricsBucket {
+        if self.bucket.transactions > 0 {
+            self.bucket.failure_rate =
+                self.bucket.failures as f64 / self.bucket.transactions as f64;
+        }
+        self.bucket.fees = self.fees.to_string();
+        self.bucket
+    }
+}
+
+// how many `bucket` second wide buckets cover [from, to), None unless 0 < bucket, from < to and
+// the count is at most MAX_BUCKETS. Checked, the bounds come straight from the query string.
+pub fn bucket_count(from: i64, to: i64, bucket: i64) -> Option<i64> {
+    if bucket <= 0 || from >= to {
+        return None;
+    }
+    let count = to
+        .checked_sub(from)?
+        .checked_add(bucket - 1)?
+        .checked_div(bucket)?;
+    (count <= MAX_BUCKETS).then_some(count)
+}
+
+// splits [from, to) into `bucket` second wide buckets, the last one may be cut short by `to`.
+// None for ranges bucket_count rejects.
+pub fn aggregate(
+    invocations: &[Invocation],
+    from: i64,
+    to: i64,
+    bucket: i64,
+) -> Option<LambdaMetrics> {
+    let count = bucket_count(from, to, bucket)?;
+    let mut buckets = (0..count)
+        .map(|index| Accumulator {
+            bucket: MetricsBucket {
+                start: from + index * bucket,
+                ..MetricsBucket::default()
+            },
+            fees: 0,
+        })
+        .collect::<Vec<Accumulator>>();
+    let mut totals = Accumulator {
+        bucket: MetricsBucket {
+            start: from,
+            ..MetricsBucket::default()
+        },
+        fees: 0,
+    };
+
+    for invocation in invocations {
+        let timestamp = invocation.block_timestamp;
+        if timestamp < from || timestamp >= to {
+            continue;
+        }
+        buckets[((timestamp - from) / bucket) as usize].add(invocation);
+        totals.add(invocation);
+    }
+
+    Some(LambdaMetrics {
+        from,
+        to,
+        bucket,
+        buckets: buckets.into_iter().map(Accumulator::finish).collect(),
+        totals: totals.finish(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invocation(kind: &str, timestamp: i64, success: bool) -> Invocation {
+        let transaction = kind == "transaction";
+        Invocation {
+            tx_hash: format!("0x{:x}", timestamp),
+            log_index: if transaction { -1 } else { 0 },
+            lambda_id: "lambda".to_string(),
+            kind: kind.to_string(),
+            block_number: timestamp / 12,
+            block_timestamp: timestamp,
+            caller: "0xcc".to_string(),
+            args: "0x".to_string(),
+            success,
+            gas_used: transaction.then_some(50_000),
+            gas_price: transaction.then_some(3_000_000_000_000_000),
+        }
+    }
+
+    #[test]
+    fn test_aggregate_into_buckets() {
+        let invocations = [
+            invocation("transaction", 100, true),
+            invocation("event", 100, true),
+            invocation("transaction", 130, false),
+            invocation("transaction", 190, true),
+            invocation("event", 190, true),
+            // outside of [100, 250)
+            invocation("transaction", 250, true),
+        ];
+        let metrics = aggregate(&invocations, 100, 250, 60).unwrap();
+
+        assert_eq!(
+            metrics
+                .buckets
+                .iter()
+                .map(|bucket| bucket.start)
+                .collect::<Vec<i64>>(),
+            vec![100, 160, 220]
+        );
+        let first = &metrics.buckets[0];
+        assert_eq!((first.calls, first.transactions, first.failures), (1, 2, 1));
+        assert_eq!(first.failure_rate, 0.5);
+        assert_eq!(first.gas_used, 100_000);
+        // 100k gas at 3e15 wei overflows i64 but not the u128 sum
+        assert_eq!(first.fees, "300000000000000000000");
+        assert_eq!(metrics.buckets[1].calls, 1);
+        assert_eq!(
+            metrics.buckets[2],
+            MetricsBucket {
+                start: 220,
+                fees: "0".to_string(),
+                ..MetricsBucket::default()
+            }
+        );
+        assert_eq!(metrics.totals.transactions, 3);
+        assert_eq!(metrics.totals.fees, "450000000000000000000");
+    }
+
+    #[test]
+    fn test_bucket_count_rejects_extreme_ranges() {
+        assert_eq!(bucket_count(100, 250, 60), Some(3));
+        assert_eq!(bucket_count(250, 100, 60), None);
+        assert_eq!(bucket_count(100, 250, 0), None);
+        assert_eq!(bucket_count(i64::MIN, i64::MAX, 1), None);
+        assert_eq!(bucket_count(0, i64::MAX, i64::MAX), None);
+        assert_eq!(bucket_count(0, MAX_BUCKETS + 1, 1), None);
+        assert!(aggregate(&[], i64::MIN, 0, i64::MAX).is_none());
+    }
+}
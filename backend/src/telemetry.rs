@@ -0,0 +1,152 @@
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Next,
+    web, Error,
+};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use std::time::Instant;
+
+use crate::AppState;
+
+// compiles run from under a second (cached artifacts) to minutes (cold forge builds)
+const COMPILE_BUCKETS: &[f64] = &[0.1, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0];
+
+// operational metrics of the backend, served in the prometheus text format on /metrics
+pub struct Telemetry {
+    registry: Registry,
+    // labelled by outcome: generated, parse_error or write_error
+    pub submissions: IntCounterVec,
+    // labelled by `ParseError::kind`
+    pub parse_failures: IntCounterVec,
+    // labelled by outcome: succeeded or failed
+    pub compile_duration: HistogramVec,
+    // labelled by reason: build (the code didn't compile) or error (forge itself failed)
+    pub forge_failures: IntCounterVec,
+    pub queue_depth: IntGauge,
+    pub http_duration: HistogramVec,
+}
+
+impl Telemetry {
+    pub fn new() -> Self {
+        let submissions = IntCounterVec::new(
+            Opts::new(
+                "lambda_submissions_total",
+                "Lambdas submitted for generation",
+            ),
+            &["outcome"],
+        )
+        .unwrap();
+        let parse_failures = IntCounterVec::new(
+            Opts::new(
+                "lambda_parse_failures_total",
+                "Submitted lambdas that failed to parse",
+            ),
+            &["kind"],
+        )
+        .unwrap();
+        let compile_duration = HistogramVec::new(
+            HistogramOpts::new("compile_duration_seconds", "Time spent compiling a lambda")
+                .buckets(COMPILE_BUCKETS.to_vec()),
+            &["outcome"],
+        )
+        .unwrap();
+        let forge_failures = IntCounterVec::new(
+            Opts::new(
+                "forge_failures_total",
+                "Compiles that didn't produce artifacts",
+            ),
+            &["reason"],
+        )
+        .unwrap();
+        let queue_depth =
+            IntGauge::new("compile_queue_depth", "Compile jobs waiting for a worker").unwrap();
+        let http_duration = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "HTTP request latency"),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+
+        let registry = Registry::new();
+        registry.register(Box::new(submissions.clone())).unwrap();
+        registry.register(Box::new(parse_failures.clone())).unwrap();
+        registry
+            .register(Box::new(compile_duration.clone()))
+            .unwrap();
+        registry.register(Box::new(forge_failures.clone())).unwrap();
+        registry.register(Box::new(queue_depth.clone())).unwrap();
+        registry.register(Box::new(http_duration.clone())).unwrap();
+
+        Self {
+            registry,
+            submissions,
+            parse_failures,
+            compile_duration,
+            forge_failures,
+            queue_depth,
+            http_duration,
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        // encoding into a Vec only fails for malformed metric families, which `new` rules out
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap_or_default();
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
+
+// records the latency of every request under its route pattern, so `/lambdas/{id}` is one series
+pub async fn observe_requests(
+    request: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let started = Instant::now();
+    let telemetry = request
+        .app_data::<web::Data<AppState>>()
+        .map(|data| data.telemetry.clone());
+    let method = request.method().to_string();
+    let response = next.call(request).await?;
+    if let Some(telemetry) = telemetry {
+        let route = response
+            .request()
+            .match_pattern()
+            .unwrap_or_else(|| "unmatched".to_string());
+        telemetry
+            .http_duration
+            .with_label_values(&[&method, &route, response.status().as_str()])
+            .observe(started.elapsed().as_secs_f64());
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_prometheus_text() {
+        let telemetry = Telemetry::new();
+        telemetry
+            .parse_failures
+            .with_label_values(&["unexpected_token"])
+            .inc();
+        telemetry.queue_depth.set(3);
+        telemetry
+            .compile_duration
+            .with_label_values(&["succeeded"])
+            .observe(1.5);
+
+        let text = telemetry.render();
+        assert!(text.contains("# TYPE lambda_parse_failures_total counter"));
+        assert!(text.contains("lambda_parse_failures_total{kind=\"unexpected_token\"} 1"));
+        assert!(text.contains("compile_queue_depth 3"));
+        assert!(
+            text.contains("compile_duration_seconds_bucket{outcome=\"succeeded\",le=\"2.5\"} 1")
+        );
+    }
+}
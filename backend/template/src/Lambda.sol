@@ -8,7 +8,7 @@ contract Lambda is Ownable(msg.sender) {
     address public socket;
 
     //states_here
-    event LambdaCalled();
+    event LambdaCalled(address caller, bytes args);
 
     modifier onlySocket() {
         require(msg.sender == socket, "not socket");
@@ -0,0 +1,1280 @@
+use std::fmt;
+
+use crate::error::ParseError;
+use crate::lexer::{tokenize, Span, Token, TokenKind};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DataLocation {
+    Memory,
+    Storage,
+    Calldata,
+}
+
+impl DataLocation {
+    pub fn from_keyword(keyword: &str) -> Option<Self> {
+        match keyword {
+            "memory" => Some(DataLocation::Memory),
+            "storage" => Some(DataLocation::Storage),
+            "calldata" => Some(DataLocation::Calldata),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for DataLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DataLocation::Memory => write!(f, "memory"),
+            DataLocation::Storage => write!(f, "storage"),
+            DataLocation::Calldata => write!(f, "calldata"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TypeName {
+    Elementary(String),
+    UserDefined(String),
+    Array(Box<TypeName>, Option<Box<Expression>>),
+    Mapping {
+        key: Box<TypeName>,
+        value: Box<TypeName>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VariableDeclaration {
+    pub type_name: TypeName,
+    pub location: Option<DataLocation>,
+    pub name: Option<String>,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expression {
+    Identifier(String),
+    Number(String),
+    HexNumber(String),
+    StringLiteral(String),
+    HexString(String),
+    Bool(bool),
+    ArrayLiteral(Vec<Expression>),
+    Tuple(Vec<Option<Expression>>),
+    New(TypeName),
+    MemberAccess {
+        expression: Box<Expression>,
+        member: String,
+    },
+    Index {
+        base: Box<Expression>,
+        index: Option<Box<Expression>>,
+    },
+    Call {
+        callee: Box<Expression>,
+        arguments: Vec<Expression>,
+        // argument names for f({a: 1, b: 2}) style calls, empty for positional calls
+        names: Vec<String>,
+    },
+    Unary {
+        operator: &'static str,
+        operand: Box<Expression>,
+        prefix: bool,
+    },
+    Binary {
+        operator: &'static str,
+        left: Box<Expression>,
+        right: Box<Expression>,
+    },
+    Assignment {
+        operator: &'static str,
+        left: Box<Expression>,
+        right: Box<Expression>,
+    },
+    Conditional {
+        condition: Box<Expression>,
+        then_expression: Box<Expression>,
+        else_expression: Box<Expression>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Statement {
+    pub kind: StatementKind,
+    pub span: Span,
+    // position just past the statement, after its `;` or `}`
+    pub end: Span,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StatementKind {
+    Block(Vec<Statement>),
+    Unchecked(Vec<Statement>),
+    VariableDeclaration {
+        declaration: VariableDeclaration,
+        value: Option<Expression>,
+    },
+    TupleDeclaration {
+        declarations: Vec<Option<VariableDeclaration>>,
+        value: Expression,
+    },
+    Expression(Expression),
+    If {
+        condition: Expression,
+        then_branch: Box<Statement>,
+        else_branch: Option<Box<Statement>>,
+    },
+    For {
+        init: Option<Box<Statement>>,
+        condition: Option<Expression>,
+        update: Option<Expression>,
+        body: Box<Statement>,
+    },
+    While {
+        condition: Expression,
+        body: Box<Statement>,
+    },
+    DoWhile {
+        body: Box<Statement>,
+        condition: Expression,
+    },
+    Return(Option<Expression>),
+    Emit(Expression),
+    Revert(Expression),
+    Continue,
+    Break,
+    Assembly,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FunctionDefinition {
+    pub name: String,
+    pub span: Span,
+    pub parameters: Vec<VariableDeclaration>,
+    pub returns: Vec<VariableDeclaration>,
+    pub attributes: Vec<String>,
+    pub body: Option<Vec<Statement>>,
+    // position just past the body's opening `{`
+    pub body_start: Option<Span>,
+    // NatSpec comments written above the function, e.g. `/// @custom:lambda`
+    pub doc: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StructDefinition {
+    pub name: String,
+    pub span: Span,
+    pub members: Vec<VariableDeclaration>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EnumDefinition {
+    pub name: String,
+    pub span: Span,
+    pub members: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SourceItem {
+    Function(FunctionDefinition),
+    Struct(StructDefinition),
+    Enum(EnumDefinition),
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SourceUnit {
+    pub items: Vec<SourceItem>,
+}
+
+impl SourceUnit {
+    pub fn functions(&self) -> impl Iterator<Item = &FunctionDefinition> {
+        self.items.iter().filter_map(|item| match item {
+            SourceItem::Function(function) => Some(function),
+            _ => None,
+        })
+    }
+
+    pub fn structs(&self) -> impl Iterator<Item = &StructDefinition> {
+        self.items.iter().filter_map(|item| match item {
+            SourceItem::Struct(definition) => Some(definition),
+            _ => None,
+        })
+    }
+
+    pub fn enums(&self) -> impl Iterator<Item = &EnumDefinition> {
+        self.items.iter().filter_map(|item| match item {
+            SourceItem::Enum(definition) => Some(definition),
+            _ => None,
+        })
+    }
+}
+
+const ELEMENTARY_TYPES: &[&str] = &["address", "bool", "string", "bytes", "int", "uint", "byte"];
+
+const ETHER_UNITS: &[&str] = &[
+    "wei", "gwei", "ether", "seconds", "minutes", "hours", "days", "weeks",
+];
+
+fn is_elementary_type(word: &str) -> bool {
+    if ELEMENTARY_TYPES.contains(&word) {
+        return true;
+    }
+    ["uint", "int", "bytes"].iter().any(|prefix| {
+        word.strip_prefix(prefix)
+            .is_some_and(|bits| !bits.is_empty() && bits.chars().all(|c| c.is_ascii_digit()))
+    })
+}
+
+fn binary_precedence(symbol: &str) -> Option<(u8, bool)> {
+    // (binding power, right associative)
+    let precedence = match symbol {
+        "||" => (6, false),
+        "&&" => (8, false),
+        "==" | "!=" => (10, false),
+        "<" | ">" | "<=" | ">=" => (12, false),
+        "|" => (14, false),
+        "^" => (16, false),
+        "&" => (18, false),
+        "<<" | ">>" | ">>>" => (20, false),
+        "+" | "-" => (22, false),
+        "*" | "/" | "%" => (24, false),
+        "**" => (26, true),
+        _ => return None,
+    };
+    Some(precedence)
+}
+
+fn is_assignment_operator(symbol: &str) -> bool {
+    matches!(
+        symbol,
+        "=" | "+=" | "-=" | "*=" | "/=" | "%=" | "&=" | "|=" | "^=" | "<<=" | ">>=" | ">>>="
+    )
+}
+
+const ASSIGNMENT_PRECEDENCE: u8 = 2;
+const CONDITIONAL_PRECEDENCE: u8 = 4;
+const PREFIX_PRECEDENCE: u8 = 28;
+
+// deeper input is rejected rather than risking a stack overflow while parsing or inferring
+const MAX_NESTING_DEPTH: usize = 64;
+
+pub struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser {
+    pub fn new(tokens: Vec<Token>) -> Self {
+        Self {
+            tokens,
+            pos: 0,
+            depth: 0,
+        }
+    }
+
+    pub fn parse_source_unit(&mut self) -> Result<SourceUnit, ParseError> {
+        let mut unit = SourceUnit::default();
+        while !self.at_eof() {
+            match self.peek_identifier() {
+                Some("function" | "modifier" | "constructor" | "fallback" | "receive") => {
+                    let function = self.parse_function()?;
+                    unit.items.push(SourceItem::Function(function));
+                }
+                Some("struct") => {
+                    let definition = self.parse_struct()?;
+                    unit.items.push(SourceItem::Struct(definition));
+                }
+                Some("enum") => {
+                    let definition = self.parse_enum()?;
+                    unit.items.push(SourceItem::Enum(definition));
+                }
+                _ => self.skip_item()?,
+            }
+        }
+        Ok(unit)
+    }
+
+    // ----- token helpers -----
+
+    fn peek(&self) -> &Token {
+        self.peek_nth(0)
+    }
+
+    fn peek_nth(&self, n: usize) -> &Token {
+        let index = (self.pos + n).min(self.tokens.len() - 1);
+        &self.tokens[index]
+    }
+
+    fn at_eof(&self) -> bool {
+        self.peek().kind == TokenKind::Eof
+    }
+
+    fn span(&self) -> Span {
+        self.peek().span
+    }
+
+    // where the last consumed token ends
+    fn previous_end(&self) -> Span {
+        self.tokens[self.pos.saturating_sub(1)].end
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.peek().clone();
+        if token.kind != TokenKind::Eof {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn peek_symbol(&self) -> Option<&'static str> {
+        match self.peek().kind {
+            TokenKind::Symbol(symbol) => Some(symbol),
+            _ => None,
+        }
+    }
+
+    fn peek_identifier(&self) -> Option<&str> {
+        self.nth_identifier(0)
+    }
+
+    fn nth_identifier(&self, n: usize) -> Option<&str> {
+        match &self.peek_nth(n).kind {
+            TokenKind::Identifier(word) => Some(word),
+            _ => None,
+        }
+    }
+
+    fn check_symbol(&self, symbol: &str) -> bool {
+        self.peek_symbol() == Some(symbol)
+    }
+
+    fn eat_symbol(&mut self, symbol: &str) -> bool {
+        if self.check_symbol(symbol) {
+            self.advance();
+            return true;
+        }
+        false
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if self.peek_identifier() == Some(keyword) {
+            self.advance();
+            return true;
+        }
+        false
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<(), ParseError> {
+        if self.eat_symbol(symbol) {
+            return Ok(());
+        }
+        Err(self.unexpected(&format!("`{}`", symbol)))
+    }
+
+    fn expect_identifier(&mut self) -> Result<String, ParseError> {
+        if let TokenKind::Identifier(word) = &self.peek().kind {
+            let word = word.clone();
+            self.advance();
+            return Ok(word);
+        }
+        Err(self.unexpected("identifier"))
+    }
+
+    fn unexpected(&self, expected: &str) -> ParseError {
+        let token = self.peek();
+        let found = match &token.kind {
+            TokenKind::Identifier(word) => format!("`{}`", word),
+            TokenKind::Number(number) | TokenKind::HexNumber(number) => format!("`{}`", number),
+            TokenKind::StringLiteral(_) | TokenKind::HexString(_) => "string literal".to_string(),
+            TokenKind::Symbol(symbol) => format!("`{}`", symbol),
+            TokenKind::Eof => "end of input".to_string(),
+        };
+        ParseError::UnexpectedToken {
+            expected: expected.to_string(),
+            found,
+            span: token.span,
+        }
+    }
+
+    fn descend(&mut self) -> Result<(), ParseError> {
+        self.depth += 1;
+        if self.depth > MAX_NESTING_DEPTH {
+            return Err(ParseError::NestingTooDeep { span: self.span() });
+        }
+        Ok(())
+    }
+
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<T, ParseError> {
+        let depth = self.depth;
+        let result = self.descend().and_then(|_| parse(self));
+        self.depth = depth;
+        result
+    }
+
+    // skips a top level item we do not model (events, errors, using-for, ...)
+    fn skip_item(&mut self) -> Result<(), ParseError> {
+        let mut depth = 0usize;
+        loop {
+            match self.peek().kind {
+                TokenKind::Eof => return Ok(()),
+                TokenKind::Symbol("(" | "[") => depth += 1,
+                TokenKind::Symbol(")" | "]") => depth = depth.saturating_sub(1),
+                TokenKind::Symbol(";") if depth == 0 => {
+                    self.advance();
+                    return Ok(());
+                }
+                TokenKind::Symbol("{") if depth == 0 => return self.skip_braces(),
+                _ => {}
+            }
+            self.advance();
+        }
+    }
+
+    fn skip_braces(&mut self) -> Result<(), ParseError> {
+        self.expect_symbol("{")?;
+        let mut depth = 1usize;
+        while depth > 0 {
+            match self.advance().kind {
+                TokenKind::Symbol("{") => depth += 1,
+                TokenKind::Symbol("}") => depth -= 1,
+                TokenKind::Eof => return Err(self.unexpected("`}`")),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn skip_parens(&mut self) -> Result<(), ParseError> {
+        self.expect_symbol("(")?;
+        let mut depth = 1usize;
+        while depth > 0 {
+            match self.advance().kind {
+                TokenKind::Symbol("(") => depth += 1,
+                TokenKind::Symbol(")") => depth -= 1,
+                TokenKind::Eof => return Err(self.unexpected("`)`")),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // ----- declarations -----
+
+    fn parse_function(&mut self) -> Result<FunctionDefinition, ParseError> {
+        let span = self.span();
+        let doc = self.peek().doc.clone();
+        let keyword = self.expect_identifier()?;
+        let name = match keyword.as_str() {
+            "function" | "modifier" => self.expect_identifier()?,
+            _ => keyword,
+        };
+
+        let parameters = if self.check_symbol("(") {
+            self.parse_parameter_list()?
+        } else {
+            Vec::new()
+        };
+
+        let mut returns = Vec::new();
+        let mut attributes = Vec::new();
+        loop {
+            if self.eat_keyword("returns") {
+                returns = self.parse_parameter_list()?;
+                continue;
+            }
+            if let Some(word) = self.peek_identifier() {
+                attributes.push(word.to_string());
+                self.advance();
+                if self.check_symbol("(") {
+                    self.skip_parens()?;
+                }
+                continue;
+            }
+            break;
+        }
+
+        let body_start = (!self.check_symbol(";")).then(|| self.peek().end);
+        let body = if self.eat_symbol(";") {
+            None
+        } else {
+            Some(self.parse_block()?)
+        };
+
+        Ok(FunctionDefinition {
+            name,
+            span,
+            parameters,
+            returns,
+            attributes,
+            body,
+            body_start,
+            doc,
+        })
+    }
+
+    fn parse_struct(&mut self) -> Result<StructDefinition, ParseError> {
+        let span = self.span();
+        self.advance();
+        let name = self.expect_identifier()?;
+        self.expect_symbol("{")?;
+        let mut members = Vec::new();
+        while !self.eat_symbol("}") {
+            let member_span = self.span();
+            let type_name = self.parse_type_name()?;
+            let member_name = self.expect_identifier()?;
+            self.expect_symbol(";")?;
+            members.push(VariableDeclaration {
+                type_name,
+                location: None,
+                name: Some(member_name),
+                span: member_span,
+            });
+        }
+        Ok(StructDefinition {
+            name,
+            span,
+            members,
+        })
+    }
+
+    fn parse_enum(&mut self) -> Result<EnumDefinition, ParseError> {
+        let span = self.span();
+        self.advance();
+        let name = self.expect_identifier()?;
+        self.expect_symbol("{")?;
+        let mut members = vec![self.expect_identifier()?];
+        while self.eat_symbol(",") {
+            members.push(self.expect_identifier()?);
+        }
+        self.expect_symbol("}")?;
+        Ok(EnumDefinition {
+            name,
+            span,
+            members,
+        })
+    }
+
+    fn parse_parameter_list(&mut self) -> Result<Vec<VariableDeclaration>, ParseError> {
+        self.expect_symbol("(")?;
+        let mut parameters = Vec::new();
+        if self.eat_symbol(")") {
+            return Ok(parameters);
+        }
+        loop {
+            let span = self.span();
+            let type_name = self.parse_type_name()?;
+            let location = self.parse_data_location();
+            let name = match self.peek_identifier() {
+                Some(_) => Some(self.expect_identifier()?),
+                None => None,
+            };
+            parameters.push(VariableDeclaration {
+                type_name,
+                location,
+                name,
+                span,
+            });
+            if self.eat_symbol(")") {
+                return Ok(parameters);
+            }
+            self.expect_symbol(",")?;
+        }
+    }
+
+    fn parse_data_location(&mut self) -> Option<DataLocation> {
+        let location = DataLocation::from_keyword(self.peek_identifier()?)?;
+        self.advance();
+        Some(location)
+    }
+
+    fn parse_type_name(&mut self) -> Result<TypeName, ParseError> {
+        self.nested(Self::parse_type_name_inner)
+    }
+
+    fn parse_type_name_inner(&mut self) -> Result<TypeName, ParseError> {
+        let word = self.expect_identifier()?;
+        let mut type_name = if word == "mapping" {
+            self.expect_symbol("(")?;
+            let key = self.parse_type_name()?;
+            // named mapping keys: mapping(address owner => uint256 balance)
+            if self.peek_identifier().is_some() {
+                self.advance();
+            }
+            self.expect_symbol("=>")?;
+            let value = self.parse_type_name()?;
+            if self.peek_identifier().is_some() {
+                self.advance();
+            }
+            self.expect_symbol(")")?;
+            TypeName::Mapping {
+                key: Box::new(key),
+                value: Box::new(value),
+            }
+        } else if word == "address" && self.peek_identifier() == Some("payable") {
+            self.advance();
+            TypeName::Elementary("address payable".to_string())
+        } else if is_elementary_type(&word) {
+            TypeName::Elementary(word)
+        } else {
+            let mut path = word;
+            while self.check_symbol(".") && self.nth_identifier(1).is_some() {
+                self.advance();
+                path.push('.');
+                path.push_str(&self.expect_identifier()?);
+            }
+            TypeName::UserDefined(path)
+        };
+
+        while self.check_symbol("[") {
+            self.descend()?;
+            self.advance();
+            let length = if self.check_symbol("]") {
+                None
+            } else {
+                Some(Box::new(self.parse_expression()?))
+            };
+            self.expect_symbol("]")?;
+            type_name = TypeName::Array(Box::new(type_name), length);
+        }
+        Ok(type_name)
+    }
+
+    // ----- statements -----
+
+    fn parse_block(&mut self) -> Result<Vec<Statement>, ParseError> {
+        self.expect_symbol("{")?;
+        let mut statements = Vec::new();
+        while !self.eat_symbol("}") {
+            if self.at_eof() {
+                return Err(self.unexpected("`}`"));
+            }
+            statements.push(self.parse_statement()?);
+        }
+        Ok(statements)
+    }
+
+    fn parse_statement(&mut self) -> Result<Statement, ParseError> {
+        let span = self.span();
+        let kind = self.nested(Self::parse_statement_kind)?;
+        Ok(Statement {
+            kind,
+            span,
+            end: self.previous_end(),
+        })
+    }
+
+    fn parse_statement_kind(&mut self) -> Result<StatementKind, ParseError> {
+        if self.check_symbol("{") {
+            return Ok(StatementKind::Block(self.parse_block()?));
+        }
+
+        match self.peek_identifier() {
+            Some("if") => {
+                self.advance();
+                self.expect_symbol("(")?;
+                let condition = self.parse_expression()?;
+                self.expect_symbol(")")?;
+                let then_branch = Box::new(self.parse_statement()?);
+                let else_branch = if self.eat_keyword("else") {
+                    Some(Box::new(self.parse_statement()?))
+                } else {
+                    None
+                };
+                return Ok(StatementKind::If {
+                    condition,
+                    then_branch,
+                    else_branch,
+                });
+            }
+            Some("for") => {
+                self.advance();
+                self.expect_symbol("(")?;
+                let init = if self.eat_symbol(";") {
+                    None
+                } else {
+                    let span = self.span();
+                    let kind = self.parse_simple_statement()?;
+                    Some(Box::new(Statement {
+                        kind,
+                        span,
+                        end: self.previous_end(),
+                    }))
+                };
+                let condition = if self.check_symbol(";") {
+                    None
+                } else {
+                    Some(self.parse_expression()?)
+                };
+                self.expect_symbol(";")?;
+                let update = if self.check_symbol(")") {
+                    None
+                } else {
+                    Some(self.parse_expression()?)
+                };
+                self.expect_symbol(")")?;
+                let body = Box::new(self.parse_statement()?);
+                return Ok(StatementKind::For {
+                    init,
+                    condition,
+                    update,
+                    body,
+                });
+            }
+            Some("while") => {
+                self.advance();
+                self.expect_symbol("(")?;
+                let condition = self.parse_expression()?;
+                self.expect_symbol(")")?;
+                let body = Box::new(self.parse_statement()?);
+                return Ok(StatementKind::While { condition, body });
+            }
+            Some("do") => {
+                self.advance();
+                let body = Box::new(self.parse_statement()?);
+                if !self.eat_keyword("while") {
+                    return Err(self.unexpected("`while`"));
+                }
+                self.expect_symbol("(")?;
+                let condition = self.parse_expression()?;
+                self.expect_symbol(")")?;
+                self.expect_symbol(";")?;
+                return Ok(StatementKind::DoWhile { body, condition });
+            }
+            Some("return") => {
+                self.advance();
+                let value = if self.check_symbol(";") {
+                    None
+                } else {
+                    Some(self.parse_expression()?)
+                };
+                self.expect_symbol(";")?;
+                return Ok(StatementKind::Return(value));
+            }
+            Some("emit") => {
+                self.advance();
+                let event = self.parse_expression()?;
+                self.expect_symbol(";")?;
+                return Ok(StatementKind::Emit(event));
+            }
+            Some("revert") if !self.nth_is_symbol(1, "(") => {
+                self.advance();
+                let error = self.parse_expression()?;
+                self.expect_symbol(";")?;
+                return Ok(StatementKind::Revert(error));
+            }
+            Some("continue") => {
+                self.advance();
+                self.expect_symbol(";")?;
+                return Ok(StatementKind::Continue);
+            }
+            Some("break") => {
+                self.advance();
+                self.expect_symbol(";")?;
+                return Ok(StatementKind::Break);
+            }
+            Some("unchecked") if self.nth_is_symbol(1, "{") => {
+                self.advance();
+                return Ok(StatementKind::Unchecked(self.parse_block()?));
+            }
+            Some("assembly") => {
+                self.advance();
+                if matches!(self.peek().kind, TokenKind::StringLiteral(_)) {
+                    self.advance();
+                }
+                if self.check_symbol("(") {
+                    self.skip_parens()?;
+                }
+                self.skip_braces()?;
+                return Ok(StatementKind::Assembly);
+            }
+            _ => {}
+        }
+
+        self.parse_simple_statement()
+    }
+
+    fn nth_is_symbol(&self, n: usize, symbol: &str) -> bool {
+        matches!(self.peek_nth(n).kind, TokenKind::Symbol(found) if found == symbol)
+    }
+
+    // variable declarations or expression statements, terminated by `;`
+    fn parse_simple_statement(&mut self) -> Result<StatementKind, ParseError> {
+        if let Some(statement) = self.try_parse_tuple_declaration()? {
+            return Ok(statement);
+        }
+        if let Some(declaration) = self.try_parse_variable_declaration() {
+            let value = if self.eat_symbol("=") {
+                Some(self.parse_expression()?)
+            } else {
+                None
+            };
+            self.expect_symbol(";")?;
+            return Ok(StatementKind::VariableDeclaration { declaration, value });
+        }
+
+        let expression = self.parse_expression()?;
+        self.expect_symbol(";")?;
+        Ok(StatementKind::Expression(expression))
+    }
+
+    fn try_parse_variable_declaration(&mut self) -> Option<VariableDeclaration> {
+        let start = self.pos;
+        let declaration = self.parse_variable_declaration().ok();
+        if declaration.is_none() {
+            self.pos = start;
+        }
+        declaration
+    }
+
+    fn parse_variable_declaration(&mut self) -> Result<VariableDeclaration, ParseError> {
+        let span = self.span();
+        match self.peek_identifier() {
+            Some("delete" | "new" | "type" | "payable") | None => {
+                return Err(self.unexpected("declaration"))
+            }
+            _ => {}
+        }
+        let type_name = self.parse_type_name()?;
+        let location = self.parse_data_location();
+        let name = self.expect_identifier()?;
+        if !matches!(self.peek_symbol(), Some("=" | ";" | "," | ")")) {
+            return Err(self.unexpected("declaration"));
+        }
+        Ok(VariableDeclaration {
+            type_name,
+            location,
+            name: Some(name),
+            span,
+        })
+    }
+
+    fn try_parse_tuple_declaration(&mut self) -> Result<Option<StatementKind>, ParseError> {
+        if !self.check_symbol("(") {
+            return Ok(None);
+        }
+        let start = self.pos;
+        self.advance();
+        let mut declarations = Vec::new();
+        let mut any_declaration = false;
+        loop {
+            if self.check_symbol(",") || self.check_symbol(")") {
+                declarations.push(None);
+            } else if let Some(declaration) = self.try_parse_variable_declaration() {
+                any_declaration = true;
+                declarations.push(Some(declaration));
+            } else {
+                self.pos = start;
+                return Ok(None);
+            }
+            if self.eat_symbol(")") {
+                break;
+            }
+            if !self.eat_symbol(",") {
+                self.pos = start;
+                return Ok(None);
+            }
+        }
+        if !any_declaration || !self.eat_symbol("=") {
+            self.pos = start;
+            return Ok(None);
+        }
+        let value = self.parse_expression()?;
+        self.expect_symbol(";")?;
+        Ok(Some(StatementKind::TupleDeclaration {
+            declarations,
+            value,
+        }))
+    }
+
+    // ----- expressions -----
+
+    pub fn parse_expression(&mut self) -> Result<Expression, ParseError> {
+        self.parse_expression_bp(0)
+    }
+
+    fn parse_expression_bp(&mut self, min_bp: u8) -> Result<Expression, ParseError> {
+        self.nested(|parser| parser.parse_binary(min_bp))
+    }
+
+    fn parse_binary(&mut self, min_bp: u8) -> Result<Expression, ParseError> {
+        let mut left = self.parse_prefix()?;
+
+        while let Some(symbol) = self.peek_symbol() {
+            // every operator wraps `left` one level deeper
+            self.descend()?;
+            if is_assignment_operator(symbol) {
+                if ASSIGNMENT_PRECEDENCE < min_bp {
+                    break;
+                }
+                self.advance();
+                let right = self.parse_expression_bp(ASSIGNMENT_PRECEDENCE)?;
+                left = Expression::Assignment {
+                    operator: symbol,
+                    left: Box::new(left),
+                    right: Box::new(right),
+                };
+                continue;
+            }
+
+            if symbol == "?" {
+                if CONDITIONAL_PRECEDENCE < min_bp {
+                    break;
+                }
+                self.advance();
+                let then_expression = self.parse_expression_bp(0)?;
+                self.expect_symbol(":")?;
+                // like solc, either branch may be an assignment: `f ? a = 1 : a = 2`
+                let else_expression = self.parse_expression_bp(ASSIGNMENT_PRECEDENCE)?;
+                left = Expression::Conditional {
+                    condition: Box::new(left),
+                    then_expression: Box::new(then_expression),
+                    else_expression: Box::new(else_expression),
+                };
+                continue;
+            }
+
+            let Some((bp, right_assoc)) = binary_precedence(symbol) else {
+                break;
+            };
+            if bp < min_bp {
+                break;
+            }
+            self.advance();
+            let next_bp = if right_assoc { bp } else { bp + 1 };
+            let right = self.parse_expression_bp(next_bp)?;
+            left = Expression::Binary {
+                operator: symbol,
+                left: Box::new(left),
+                right: Box::new(right),
+            };
+        }
+
+        Ok(left)
+    }
+
+    fn parse_prefix(&mut self) -> Result<Expression, ParseError> {
+        if let Some(operator @ ("!" | "~" | "-" | "++" | "--")) = self.peek_symbol() {
+            self.advance();
+            let operand = self.parse_expression_bp(PREFIX_PRECEDENCE)?;
+            return Ok(Expression::Unary {
+                operator,
+                operand: Box::new(operand),
+                prefix: true,
+            });
+        }
+        if self.peek_identifier() == Some("delete") {
+            self.advance();
+            let operand = self.parse_expression_bp(PREFIX_PRECEDENCE)?;
+            return Ok(Expression::Unary {
+                operator: "delete",
+                operand: Box::new(operand),
+                prefix: true,
+            });
+        }
+        let primary = self.parse_primary()?;
+        self.parse_postfix(primary)
+    }
+
+    fn parse_postfix(&mut self, mut expression: Expression) -> Result<Expression, ParseError> {
+        loop {
+            self.descend()?;
+            match self.peek_symbol() {
+                Some(".") => {
+                    self.advance();
+                    let member = self.expect_identifier()?;
+                    expression = Expression::MemberAccess {
+                        expression: Box::new(expression),
+                        member,
+                    };
+                }
+                Some("[") => {
+                    self.advance();
+                    let index = if self.check_symbol("]") {
+                        None
+                    } else {
+                        Some(Box::new(self.parse_expression()?))
+                    };
+                    self.expect_symbol("]")?;
+                    expression = Expression::Index {
+                        base: Box::new(expression),
+                        index,
+                    };
+                }
+                Some("(") => {
+                    let (arguments, names) = self.parse_call_arguments()?;
+                    expression = Expression::Call {
+                        callee: Box::new(expression),
+                        arguments,
+                        names,
+                    };
+                }
+                // call options: target.call{value: amount}(data)
+                Some("{")
+                    if self.nth_identifier(1).is_some()
+                        && self.peek_nth(2).kind == TokenKind::Symbol(":") =>
+                {
+                    self.skip_braces()?;
+                }
+                Some(operator @ ("++" | "--")) => {
+                    self.advance();
+                    expression = Expression::Unary {
+                        operator,
+                        operand: Box::new(expression),
+                        prefix: false,
+                    };
+                }
+                _ => return Ok(expression),
+            }
+        }
+    }
+
+    fn parse_call_arguments(&mut self) -> Result<(Vec<Expression>, Vec<String>), ParseError> {
+        self.expect_symbol("(")?;
+        let mut arguments = Vec::new();
+        let mut names = Vec::new();
+        if self.eat_symbol(")") {
+            return Ok((arguments, names));
+        }
+        // named arguments: f({a: 1, b: 2})
+        if self.eat_symbol("{") {
+            while !self.eat_symbol("}") {
+                names.push(self.expect_identifier()?);
+                self.expect_symbol(":")?;
+                arguments.push(self.parse_expression()?);
+                if !self.eat_symbol(",") {
+                    self.expect_symbol("}")?;
+                    break;
+                }
+            }
+            self.expect_symbol(")")?;
+            return Ok((arguments, names));
+        }
+        loop {
+            arguments.push(self.parse_expression()?);
+            if self.eat_symbol(")") {
+                return Ok((arguments, names));
+            }
+            self.expect_symbol(",")?;
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expression, ParseError> {
+        let token = self.peek().clone();
+        match token.kind {
+            TokenKind::Number(number) => {
+                self.advance();
+                if let Some(unit) = self.peek_identifier() {
+                    if ETHER_UNITS.contains(&unit) {
+                        self.advance();
+                    }
+                }
+                Ok(Expression::Number(number))
+            }
+            TokenKind::HexNumber(number) => {
+                self.advance();
+                Ok(Expression::HexNumber(number))
+            }
+            TokenKind::StringLiteral(mut value) => {
+                self.advance();
+                // adjacent string literals are concatenated
+                while let TokenKind::StringLiteral(next) = &self.peek().kind {
+                    value.push_str(next);
+                    self.advance();
+                }
+                Ok(Expression::StringLiteral(value))
+            }
+            TokenKind::HexString(value) => {
+                self.advance();
+                Ok(Expression::HexString(value))
+            }
+            TokenKind::Symbol("(") => {
+                self.advance();
+                let mut elements = Vec::new();
+                loop {
+                    if self.check_symbol(",") || self.check_symbol(")") {
+                        elements.push(None);
+                    } else {
+                        elements.push(Some(self.parse_expression()?));
+                    }
+                    if self.eat_symbol(")") {
+                        break;
+                    }
+                    self.expect_symbol(",")?;
+                }
+                // a single parenthesised expression is not a tuple
+                if let [Some(_)] = elements.as_slice() {
+                    if let Some(Some(inner)) = elements.pop() {
+                        return Ok(inner);
+                    }
+                }
+                Ok(Expression::Tuple(elements))
+            }
+            TokenKind::Symbol("[") => {
+                self.advance();
+                let mut elements = Vec::new();
+                if !self.eat_symbol("]") {
+                    loop {
+                        elements.push(self.parse_expression()?);
+                        if self.eat_symbol("]") {
+                            break;
+                        }
+                        self.expect_symbol(",")?;
+                    }
+                }
+                Ok(Expression::ArrayLiteral(elements))
+            }
+            TokenKind::Identifier(word) => match word.as_str() {
+                "true" | "false" => {
+                    self.advance();
+                    Ok(Expression::Bool(word == "true"))
+                }
+                "new" => {
+                    self.advance();
+                    Ok(Expression::New(self.parse_type_name()?))
+                }
+                _ if is_elementary_type(&word) => {
+                    // elementary type used as an expression: uint256(x), address(0), bytes32[]
+                    let type_name = self.parse_type_name()?;
+                    Ok(Expression::Identifier(type_name_to_string(&type_name)))
+                }
+                _ => {
+                    self.advance();
+                    Ok(Expression::Identifier(word))
+                }
+            },
+            _ => Err(self.unexpected("expression")),
+        }
+    }
+}
+
+pub fn type_name_to_string(type_name: &TypeName) -> String {
+    match type_name {
+        TypeName::Elementary(name) | TypeName::UserDefined(name) => name.clone(),
+        TypeName::Array(inner, Some(length)) => match length.as_ref() {
+            Expression::Number(length) | Expression::Identifier(length) => {
+                format!("{}[{}]", type_name_to_string(inner), length)
+            }
+            _ => format!("{}[]", type_name_to_string(inner)),
+        },
+        TypeName::Array(inner, None) => format!("{}[]", type_name_to_string(inner)),
+        TypeName::Mapping { key, value } => format!(
+            "mapping({}=>{})",
+            type_name_to_string(key),
+            type_name_to_string(value)
+        ),
+    }
+}
+
+pub fn parse_source(source: &str) -> Result<SourceUnit, ParseError> {
+    let tokens = tokenize(source)?;
+    Parser::new(tokens).parse_source_unit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_body(source: &str) -> Vec<StatementKind> {
+        let unit = parse_source(&format!("function f() {{ {} }}", source)).unwrap();
+        let body = unit.functions().next().unwrap().body.clone();
+        body.unwrap()
+            .into_iter()
+            .map(|statement| statement.kind)
+            .collect()
+    }
+
+    #[test]
+    fn test_multiline_assignment() {
+        let body = parse_body("total =\n    a +\n    b;");
+        assert!(matches!(
+            &body[0],
+            StatementKind::Expression(Expression::Assignment { operator: "=", right, .. })
+                if matches!(right.as_ref(), Expression::Binary { operator: "+", .. })
+        ));
+    }
+
+    #[test]
+    fn test_comparison_is_not_assignment() {
+        let body = parse_body("if (a >= b) { c = a == b; }");
+        let StatementKind::If { condition, .. } = &body[0] else {
+            panic!("expected if statement");
+        };
+        assert!(matches!(
+            condition,
+            Expression::Binary { operator: ">=", .. }
+        ));
+    }
+
+    #[test]
+    fn test_variable_declarations() {
+        let body = parse_body(
+            "uint256[] memory values; mapping(address => uint) storage m = x; a[i] = 1;",
+        );
+        assert!(matches!(
+            &body[0],
+            StatementKind::VariableDeclaration { declaration, value: None }
+                if declaration.location == Some(DataLocation::Memory)
+        ));
+        assert!(matches!(
+            &body[1],
+            StatementKind::VariableDeclaration { value: Some(_), .. }
+        ));
+        assert!(matches!(
+            &body[2],
+            StatementKind::Expression(Expression::Assignment { .. })
+        ));
+    }
+
+    #[test]
+    fn test_function_signature() {
+        let unit = parse_source(
+            "event E(uint a);\nfunction lambda(address user, bytes calldata data) external returns (bool) {}",
+        )
+        .unwrap();
+        let function = unit.functions().next().unwrap();
+        assert_eq!(function.name, "lambda");
+        assert_eq!(function.parameters.len(), 2);
+        assert_eq!(
+            function.parameters[1].location,
+            Some(DataLocation::Calldata)
+        );
+        assert_eq!(function.attributes, vec!["external".to_string()]);
+        assert_eq!(function.returns.len(), 1);
+    }
+
+    #[test]
+    fn test_struct_definition() {
+        let unit = parse_source(
+            "struct Order { address maker; uint256[] fills; }\nfunction f() { o = Order({maker: a, fills: b}); }",
+        )
+        .unwrap();
+        let order = unit.structs().next().unwrap();
+        assert_eq!(order.name, "Order");
+        assert_eq!(order.members.len(), 2);
+        assert_eq!(order.members[1].name.as_deref(), Some("fills"));
+
+        let body = unit.functions().next().unwrap().body.clone().unwrap();
+        let StatementKind::Expression(Expression::Assignment { right, .. }) = &body[0].kind else {
+            panic!("expected assignment");
+        };
+        let Expression::Call { names, .. } = right.as_ref() else {
+            panic!("expected call");
+        };
+        assert_eq!(names, &vec!["maker".to_string(), "fills".to_string()]);
+    }
+
+    #[test]
+    fn test_enum_definition() {
+        let unit = parse_source("enum Status { Open, Filled }\nfunction f() {}").unwrap();
+        let status = unit.enums().next().unwrap();
+        assert_eq!(status.name, "Status");
+        assert_eq!(
+            status.members,
+            vec!["Open".to_string(), "Filled".to_string()]
+        );
+        assert!(parse_source("enum Status { Open, }").is_err());
+    }
+}
@@ -1,56 +1,557 @@
-use serde_json::Value;
-use std::fs::File;
-use std::io::Read;
-use std::process::Command;
-use std::{env, fs};
-
-pub fn get_contract_bytecode(
-    dirname: String,
-) -> Result<(String, Value, String, Value), Box<dyn std::error::Error>> {
-    let mut bytecodes = vec![];
-    let file_path = format!("output/{}", dirname);
-    env::set_current_dir(file_path.clone())?;
-    let output2 = Command::new("forge").arg("build").output()?;
-    assert!(output2.status.success());
-    let output3 = Command::new("forge")
-        .arg("inspect")
-        .arg("LambdaAppGateway")
-        .arg("bytecode")
-        .output()?;
-    let bytecode = String::from_utf8(output3.stdout).unwrap();
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::diagnostics::{map_to_lambda_source, parse_compiler_output, Diagnostic, Severity};
+use crate::workspace::Workspace;
+use crate::{LAMBDA_FILE, LAMBDA_TEST_FILE};
+
+#[derive(Debug)]
+pub enum CompileError {
+    // the generated project did not compile, the diagnostics say why
+    Build { diagnostics: Vec<Diagnostic> },
+    Io(io::Error),
+    Artifact(String),
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileError::Build { diagnostics } => {
+                write!(f, "Build failed")?;
+                if let Some(first) = diagnostics
+                    .iter()
+                    .find(|diagnostic| diagnostic.severity == Severity::Error)
+                {
+                    write!(f, ": {}", first)?;
+                }
+                Ok(())
+            }
+            CompileError::Io(e) => write!(f, "{}", e),
+            CompileError::Artifact(message) => write!(f, "Invalid build artifact: {}", message),
+        }
+    }
+}
+
+impl Error for CompileError {}
+
+impl From<io::Error> for CompileError {
+    fn from(e: io::Error) -> Self {
+        CompileError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for CompileError {
+    fn from(e: serde_json::Error) -> Self {
+        CompileError::Artifact(e.to_string())
+    }
+}
+
+// everything forge wrote for one contract in out/<File>.sol/<Contract>.json
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ContractArtifact {
+    pub bytecode: String,
+    pub deployed_bytecode: String,
+    pub abi: Value,
+    // function signature -> 4 byte selector, e.g. "setSocket(address)" -> "0xbb1d9d11"
+    pub method_identifiers: BTreeMap<String, String>,
+    // only present when the project asks forge for it through `extra_output`
+    pub storage_layout: Option<Value>,
+    pub metadata: Option<Value>,
+}
+
+// the artifacts of every contract in a generated lambda project
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CompiledArtifacts {
+    pub lambda: ContractArtifact,
+    pub app_gateway: ContractArtifact,
+    pub deployer: ContractArtifact,
+}
+
+// the subset of forge's artifact format we read
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ForgeArtifact {
+    abi: Value,
+    bytecode: ForgeBytecode,
+    deployed_bytecode: ForgeBytecode,
+    #[serde(default)]
+    method_identifiers: BTreeMap<String, String>,
+    storage_layout: Option<Value>,
+    metadata: Option<Value>,
+}
 
-    let lambda_app_gateway = "out/LambdaAppGateway.sol/LambdaAppGateway.json";
-    let lambda_app_deployer ="out/LambdaDeployer.sol/LambdaDeployer.json";
+#[derive(Deserialize)]
+struct ForgeBytecode {
+    object: String,
+}
+
+impl ContractArtifact {
+    pub fn from_json(json: &str) -> Result<Self, CompileError> {
+        let artifact: ForgeArtifact = serde_json::from_str(json)?;
+        Ok(Self {
+            bytecode: artifact.bytecode.object,
+            deployed_bytecode: artifact.deployed_bytecode.object,
+            abi: artifact.abi,
+            method_identifiers: artifact.method_identifiers,
+            storage_layout: artifact.storage_layout,
+            metadata: artifact.metadata,
+        })
+    }
+
+    fn load(path: &Path) -> Result<Self, CompileError> {
+        let mut file = File::open(path)?;
+        let mut content = String::new();
+        file.read_to_string(&mut content)?;
+        Self::from_json(&content)
+    }
+}
+
+const LAMBDA_ARTIFACT: &str = "out/Lambda.sol/Lambda.json";
+const LAMBDA_APG_ARTIFACT: &str = "out/LambdaAppGateway.sol/LambdaAppGateway.json";
+const LAMBDA_DEPLOYER_ARTIFACT: &str = "out/LambdaDeployer.sol/LambdaDeployer.json";
 
-    let abi_app_gateway = get_abi_from_path(lambda_app_gateway)?;
-    let abi_app_deployer = get_abi_from_path(lambda_app_deployer)?;
+// a way of turning a generated lambda project into forge-style artifacts under out/
+pub trait Compiler: Send + Sync {
+    fn build(&self, project_dir: &Path) -> Result<(), CompileError>;
+}
+
+// `forge build` inside the copied Foundry project
+pub struct ForgeCompiler;
+
+impl Compiler for ForgeCompiler {
+    fn build(&self, project_dir: &Path) -> Result<(), CompileError> {
+        let output = Command::new("forge")
+            .arg("build")
+            .current_dir(project_dir)
+            .output()?;
+        if !output.status.success() {
+            let output = format!(
+                "{}\n{}",
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&output.stderr)
+            );
+            let diagnostics = parse_compiler_output(&output);
+            return Err(build_failure(project_dir, diagnostics, &output));
+        }
+        Ok(())
+    }
+}
 
-    bytecodes.push(bytecode.trim().to_string());
-    let output4 = Command::new("forge")
-        .arg("inspect")
-        .arg("LambdaDeployer")
-        .arg("bytecode")
+// a locally installed solc driven through its standard JSON interface, no Foundry needed
+pub struct SolcCompiler {
+    pub solc: PathBuf,
+    pub remappings: Vec<String>,
+}
+
+// where the template's libraries live, mirroring what forge works out on its own
+const DEFAULT_REMAPPINGS: &[&str] = &[
+    "socket-protocol/=lib/socket-protocol/",
+    "forge-std/=lib/forge-std/src/",
+    "solmate/=lib/socket-protocol/lib/solmate/src/",
+    "openzeppelin-contracts/=lib/socket-protocol/lib/openzeppelin-contracts/",
+];
+
+impl Default for SolcCompiler {
+    fn default() -> Self {
+        Self {
+            solc: PathBuf::from("solc"),
+            remappings: DEFAULT_REMAPPINGS.iter().map(|r| r.to_string()).collect(),
+        }
+    }
+}
+
+impl Compiler for SolcCompiler {
+    fn build(&self, project_dir: &Path) -> Result<(), CompileError> {
+        let mut sources = serde_json::Map::new();
+        for entry in fs::read_dir(project_dir.join("src"))? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == "sol") {
+                let name = format!(
+                    "src/{}",
+                    path.file_name().unwrap_or_default().to_string_lossy()
+                );
+                sources.insert(name, json!({ "content": fs::read_to_string(&path)? }));
+            }
+        }
+        let input = json!({
+            "language": "Solidity",
+            "sources": sources,
+            "settings": {
+                "remappings": self.remappings,
+                "outputSelection": {
+                    "*": {
+                        "*": [
+                            "abi",
+                            "evm.bytecode.object",
+                            "evm.deployedBytecode.object",
+                            "evm.methodIdentifiers",
+                            "storageLayout",
+                            "metadata"
+                        ]
+                    }
+                }
+            }
+        });
+
+        let mut child = Command::new(&self.solc)
+            .arg("--standard-json")
+            .arg("--base-path")
+            .arg(".")
+            .arg("--allow-paths")
+            .arg(".")
+            .current_dir(project_dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(input.to_string().as_bytes())?;
+        }
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+            return Err(build_failure(project_dir, Vec::new(), &stderr));
+        }
+
+        let output: Value = serde_json::from_slice(&output.stdout)?;
+        let diagnostics = solc_diagnostics(&output);
+        if diagnostics
+            .iter()
+            .any(|diagnostic| diagnostic.severity == Severity::Error)
+        {
+            return Err(build_failure(project_dir, diagnostics, ""));
+        }
+        write_solc_artifacts(project_dir, &output, &sources)
+    }
+}
+
+// LAMBDA_COMPILER picks the backend: `forge` (default) or `solc`, the latter using SOLC_PATH
+pub fn compiler_from_env() -> Box<dyn Compiler> {
+    match env::var("LAMBDA_COMPILER").as_deref() {
+        Ok("solc") => Box::new(SolcCompiler {
+            solc: env::var_os("SOLC_PATH").map_or_else(|| PathBuf::from("solc"), PathBuf::from),
+            ..SolcCompiler::default()
+        }),
+        _ => Box::new(ForgeCompiler),
+    }
+}
+
+pub fn compile_artifacts(
+    compiler: &dyn Compiler,
+    workspace: &Workspace,
+    dirname: &str,
+) -> Result<CompiledArtifacts, CompileError> {
+    // every compiler invocation runs inside the lambda's project, the process cwd is left alone
+    let project_dir = workspace.lambda_dir(dirname)?;
+    let artifact_paths = [
+        LAMBDA_ARTIFACT,
+        LAMBDA_APG_ARTIFACT,
+        LAMBDA_DEPLOYER_ARTIFACT,
+    ];
+
+    // output dirs are content addressed, artifacts from an earlier build are still valid
+    if !artifact_paths
+        .iter()
+        .all(|path| project_dir.join(path).exists())
+    {
+        compiler.build(&project_dir)?;
+    }
+    load_artifacts(&project_dir)
+}
+
+// one test function of the generated LambdaTest contract
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TestResult {
+    // e.g. "test_lambda()"
+    pub name: String,
+    pub passed: bool,
+    // the revert reason or failed assertion
+    pub reason: Option<String>,
+}
+
+// the subset of `forge test --json` we read, keyed by suite and then by test
+#[derive(Deserialize)]
+struct ForgeSuite {
+    test_results: BTreeMap<String, ForgeTestResult>,
+}
+
+#[derive(Deserialize)]
+struct ForgeTestResult {
+    status: String,
+    reason: Option<String>,
+}
+
+// runs the generated test/LambdaTest.sol, which needs forge whichever Compiler built the lambda
+pub fn run_tests(workspace: &Workspace, dirname: &str) -> Result<Vec<TestResult>, CompileError> {
+    let project_dir = workspace.lambda_dir(dirname)?;
+    let output = Command::new("forge")
+        .arg("test")
+        .arg("--json")
+        .arg("--match-path")
+        .arg(LAMBDA_TEST_FILE)
+        .current_dir(&project_dir)
         .output()?;
-    let bytecode = String::from_utf8(output4.stdout).unwrap();
-    bytecodes.push(bytecode.trim().to_string());
-    Ok((bytecodes[0].clone(), abi_app_gateway, bytecodes[1].clone(), abi_app_deployer))
-}
-
-fn get_abi_from_path(path: &str) -> std::result::Result<Value, Box<dyn std::error::Error>> {
-    let mut file = File::open(path)?;
-    let mut content = String::new();
-    file.read_to_string(&mut content)?;
-    let json: Value = serde_json::from_str(&content)?;
-    let val = json
-        .get("abi")
-        .ok_or_else(|| "ABI field not found in JSON".into())
-        .map(|abi| abi.clone());
-    val
-}
-
-// #[test]
-// fn test_get_contract_bytecode() {
-//     let lambda_hash = 3515848652055917867;
-//     let result = get_contract_bytecode(lambda_hash);
-//     println!("result: {:?}", result);
-// }
+    // failing tests make forge exit non-zero too, only output without results is an error
+    match parse_test_output(&String::from_utf8_lossy(&output.stdout)) {
+        Err(_) if !output.status.success() => {
+            let output = format!(
+                "{}\n{}",
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&output.stderr)
+            );
+            Err(build_failure(
+                &project_dir,
+                parse_compiler_output(&output),
+                &output,
+            ))
+        }
+        result => result,
+    }
+}
+
+fn parse_test_output(output: &str) -> Result<Vec<TestResult>, CompileError> {
+    // anything forge prints before the report, such as compiler progress, is skipped
+    let start = output
+        .find('{')
+        .ok_or_else(|| CompileError::Artifact("no test report in forge output".to_string()))?;
+    let suites: BTreeMap<String, ForgeSuite> = serde_json::from_str(&output[start..])?;
+    Ok(suites
+        .into_values()
+        .flat_map(|suite| suite.test_results)
+        .map(|(name, result)| TestResult {
+            name,
+            passed: result.status == "Success",
+            reason: result.reason,
+        })
+        .collect())
+}
+
+// solc reports problems in `errors`, each with a formatted message in the usual human form.
+// the formatted text only supplies the position: an entry it can't be parsed from is still
+// kept, so an error always fails the build
+fn solc_diagnostics(output: &Value) -> Vec<Diagnostic> {
+    let Some(errors) = output["errors"].as_array() else {
+        return Vec::new();
+    };
+    errors
+        .iter()
+        .map(|error| {
+            let formatted = error["formattedMessage"].as_str().unwrap_or_default();
+            let position = parse_compiler_output(formatted).into_iter().next();
+            Diagnostic {
+                severity: match error["severity"].as_str() {
+                    Some("warning") => Severity::Warning,
+                    Some("info") => Severity::Info,
+                    _ => Severity::Error,
+                },
+                code: error["errorCode"].as_str().map(str::to_string),
+                message: error["message"]
+                    .as_str()
+                    .unwrap_or(formatted.trim())
+                    .to_string(),
+                file: position
+                    .as_ref()
+                    .and_then(|position| position.file.clone())
+                    .or_else(|| error["sourceLocation"]["file"].as_str().map(str::to_string)),
+                line: position.as_ref().and_then(|position| position.line),
+                column: position.as_ref().and_then(|position| position.column),
+                lambda_line: None,
+                lambda_column: None,
+            }
+        })
+        .collect()
+}
+
+// lays the contracts of our own sources out the way forge would, so load_artifacts reads both
+fn write_solc_artifacts(
+    project_dir: &Path,
+    output: &Value,
+    sources: &serde_json::Map<String, Value>,
+) -> Result<(), CompileError> {
+    for source in sources.keys() {
+        let Some(contracts) = output["contracts"][source].as_object() else {
+            continue;
+        };
+        let file_name = Path::new(source).file_name().unwrap_or_default();
+        let artifact_dir = project_dir.join("out").join(file_name);
+        fs::create_dir_all(&artifact_dir)?;
+        for (name, contract) in contracts {
+            let hex = |object: &Value| format!("0x{}", object.as_str().unwrap_or_default());
+            let metadata = contract["metadata"]
+                .as_str()
+                .and_then(|metadata| serde_json::from_str::<Value>(metadata).ok());
+            let artifact = json!({
+                "abi": contract["abi"],
+                "bytecode": { "object": hex(&contract["evm"]["bytecode"]["object"]) },
+                "deployedBytecode": { "object": hex(&contract["evm"]["deployedBytecode"]["object"]) },
+                "methodIdentifiers": contract["evm"]["methodIdentifiers"],
+                "storageLayout": contract["storageLayout"],
+                "metadata": metadata,
+            });
+            fs::write(
+                artifact_dir.join(format!("{}.json", name)),
+                artifact.to_string(),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+pub fn load_artifacts(project_dir: &Path) -> Result<CompiledArtifacts, CompileError> {
+    Ok(CompiledArtifacts {
+        lambda: ContractArtifact::load(&project_dir.join(LAMBDA_ARTIFACT))?,
+        app_gateway: ContractArtifact::load(&project_dir.join(LAMBDA_APG_ARTIFACT))?,
+        deployer: ContractArtifact::load(&project_dir.join(LAMBDA_DEPLOYER_ARTIFACT))?,
+    })
+}
+
+fn build_failure(
+    project_dir: &Path,
+    mut diagnostics: Vec<Diagnostic>,
+    output: &str,
+) -> CompileError {
+    if let Ok(lambda_sol) = fs::read_to_string(project_dir.join(LAMBDA_FILE)) {
+        map_to_lambda_source(&mut diagnostics, LAMBDA_FILE, &lambda_sol);
+    }
+    // the compiler failed without anything solc-shaped on its output, pass the raw text on
+    if !diagnostics
+        .iter()
+        .any(|diagnostic| diagnostic.severity == Severity::Error)
+    {
+        diagnostics.push(Diagnostic {
+            severity: Severity::Error,
+            code: None,
+            message: output.trim().to_string(),
+            file: None,
+            line: None,
+            column: None,
+            lambda_line: None,
+            lambda_column: None,
+        });
+    }
+    CompileError::Build { diagnostics }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contract_artifact_from_json() {
+        let json = r#"{
+            "abi": [{"type": "function", "name": "getSocket", "inputs": [], "outputs": []}],
+            "bytecode": {"object": "0x6080", "sourceMap": "", "linkReferences": {}},
+            "deployedBytecode": {"object": "0x6081", "sourceMap": "", "linkReferences": {}},
+            "methodIdentifiers": {"getSocket()": "b1bb1d28"},
+            "storageLayout": {"storage": [], "types": {}},
+            "id": 3
+        }"#;
+        let artifact = ContractArtifact::from_json(json).unwrap();
+        assert_eq!(artifact.bytecode, "0x6080");
+        assert_eq!(artifact.deployed_bytecode, "0x6081");
+        assert_eq!(artifact.abi[0]["name"], "getSocket");
+        assert_eq!(artifact.method_identifiers["getSocket()"], "b1bb1d28");
+        assert!(artifact.storage_layout.is_some());
+        assert!(artifact.metadata.is_none());
+
+        assert!(matches!(
+            ContractArtifact::from_json(r#"{"abi": []}"#),
+            Err(CompileError::Artifact(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_forge_test_output() {
+        let output = r#"Compiling 1 files with Solc 0.8.28
+{"test/LambdaTest.sol:LambdaTest":{"duration":"1ms","test_results":{
+            "test_deposit()":{"status":"Failure","reason":"assertion failed: 0 != 1","decoded_logs":[]},
+            "test_lambda()":{"status":"Success","reason":null,"decoded_logs":[]}
+        },"warnings":[]}}"#;
+        assert_eq!(
+            parse_test_output(output).unwrap(),
+            vec![
+                TestResult {
+                    name: "test_deposit()".to_string(),
+                    passed: false,
+                    reason: Some("assertion failed: 0 != 1".to_string()),
+                },
+                TestResult {
+                    name: "test_lambda()".to_string(),
+                    passed: true,
+                    reason: None,
+                },
+            ]
+        );
+        assert!(matches!(
+            parse_test_output("Error: compilation failed"),
+            Err(CompileError::Artifact(_))
+        ));
+    }
+
+    #[test]
+    fn test_solc_output_becomes_forge_artifacts() {
+        let output = json!({
+            "errors": [{
+                "severity": "warning",
+                "errorCode": "2072",
+                "message": "Unused local variable.",
+                "formattedMessage": "Warning: Unused local variable.\n --> src/Lambda.sol:12:5:\n",
+            }],
+            "contracts": {
+                "src/Lambda.sol": {
+                    "Lambda": {
+                        "abi": [],
+                        "evm": {
+                            "bytecode": {"object": "6080"},
+                            "deployedBytecode": {"object": "6081"},
+                            "methodIdentifiers": {"getSocket()": "b1bb1d28"},
+                        },
+                        "storageLayout": {"storage": [], "types": null},
+                        "metadata": "{\"version\":1}",
+                    }
+                }
+            }
+        });
+
+        let diagnostics = solc_diagnostics(&output);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!(diagnostics[0].code.as_deref(), Some("2072"));
+        assert_eq!(diagnostics[0].line, Some(12));
+
+        // an error without a position in its formatted text still counts
+        let failed = json!({
+            "errors": [{
+                "severity": "error",
+                "type": "CompilerError",
+                "message": "Stack too deep.",
+                "formattedMessage": "CompilerError: Stack too deep.\n",
+            }]
+        });
+        let diagnostics = solc_diagnostics(&failed);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(diagnostics[0].message, "Stack too deep.");
+        assert_eq!(diagnostics[0].line, None);
+
+        let project_dir = env::temp_dir().join(format!("solc_artifacts_{}", std::process::id()));
+        let mut sources = serde_json::Map::new();
+        sources.insert("src/Lambda.sol".to_string(), json!({"content": ""}));
+        write_solc_artifacts(&project_dir, &output, &sources).unwrap();
+        let artifact = ContractArtifact::load(&project_dir.join(LAMBDA_ARTIFACT)).unwrap();
+        fs::remove_dir_all(&project_dir).unwrap();
+
+        assert_eq!(artifact.bytecode, "0x6080");
+        assert_eq!(artifact.deployed_bytecode, "0x6081");
+        assert_eq!(artifact.method_identifiers["getSocket()"], "b1bb1d28");
+        assert_eq!(artifact.metadata, Some(json!({"version": 1})));
+    }
+}
@@ -0,0 +1,286 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::LazyLock;
+
+use crate::lexer::Span;
+
+// comments placed around the submitted source in Lambda.sol, so compiler positions can be
+// translated back into positions inside the lambda
+pub const LAMBDA_SOURCE_BEGIN: &str = "//lambda_source_begin";
+pub const LAMBDA_SOURCE_END: &str = "//lambda_source_end";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Info => write!(f, "info"),
+        }
+    }
+}
+
+// one compiler message, with its position in the generated project and, when it points
+// into the user's code, in the submitted lambda source
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Option<String>,
+    pub message: String,
+    pub file: Option<String>,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub lambda_line: Option<usize>,
+    pub lambda_column: Option<usize>,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.severity, self.message)?;
+        if let (Some(file), Some(line), Some(column)) = (&self.file, self.line, self.column) {
+            write!(f, " at {}:{}:{}", file, line, column)?;
+        }
+        Ok(())
+    }
+}
+
+// compiled once, compile output is parsed for every build
+static ANSI: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\x1b\[[0-9;]*m").unwrap());
+static HEADER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(\w*Error|Warning|Info)(?: \((\d+)\))?: (.+)$").unwrap());
+static LOCATION: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s*--> (.+?):(\d+):(\d+):?\s*$").unwrap());
+
+// parses the human readable solc output forge prints, e.g.
+//
+//   Error (7576): Undeclared identifier.
+//     --> src/Lambda.sol:25:9:
+pub fn parse_compiler_output(output: &str) -> Vec<Diagnostic> {
+    let output = ANSI.replace_all(output, "");
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+    for line in output.lines() {
+        if let Some(captures) = HEADER.captures(line.trim_start()) {
+            let message = captures[3].trim().to_string();
+            // forge's own summary line, the real errors follow it
+            if message == "Compiler run failed:" {
+                continue;
+            }
+            let severity = match &captures[1] {
+                "Warning" => Severity::Warning,
+                "Info" => Severity::Info,
+                _ => Severity::Error,
+            };
+            diagnostics.push(Diagnostic {
+                severity,
+                code: captures.get(2).map(|code| code.as_str().to_string()),
+                message,
+                file: None,
+                line: None,
+                column: None,
+                lambda_line: None,
+                lambda_column: None,
+            });
+        } else if let Some(captures) = LOCATION.captures(line) {
+            let Some(diagnostic) = diagnostics.last_mut() else {
+                continue;
+            };
+            if diagnostic.file.is_none() {
+                diagnostic.file = Some(captures[1].to_string());
+                diagnostic.line = captures[2].parse().ok();
+                diagnostic.column = captures[3].parse().ok();
+            }
+        }
+    }
+    diagnostics
+}
+
+// the begin marker, listing the text injected into the lambda source as `line:column+length`:
+// `3:14+41` for 41 characters inserted before line 3, column 14 of the submitted source
+pub fn lambda_source_begin(injections: &[(Span, String)]) -> String {
+    let mut offsets = injections
+        .iter()
+        .map(|(span, text)| (span.line, span.column, text.chars().count()))
+        .collect::<Vec<(usize, usize, usize)>>();
+    // the order `events::inject` inserts them in
+    offsets.sort_by_key(|(line, column, _)| (*line, *column));
+    offsets
+        .iter()
+        .map(|(line, column, length)| format!(" {}:{}+{}", line, column, length))
+        .fold(LAMBDA_SOURCE_BEGIN.to_string(), |marker, offset| {
+            marker + &offset
+        })
+}
+
+fn parse_offsets(marker: &str) -> Vec<(usize, usize, usize)> {
+    marker
+        .split_whitespace()
+        .skip(1)
+        .filter_map(|offset| {
+            let (line, rest) = offset.split_once(':')?;
+            let (column, length) = rest.split_once('+')?;
+            Some((
+                line.parse().ok()?,
+                column.parse().ok()?,
+                length.parse().ok()?,
+            ))
+        })
+        .collect()
+}
+
+// the submitted column of `column` on `line` of the injected source. A position inside injected
+// text maps to where it was inserted.
+fn original_column(offsets: &[(usize, usize, usize)], line: usize, column: usize) -> usize {
+    let mut shift = 0;
+    for &(_, start, length) in offsets
+        .iter()
+        .filter(|(offset_line, ..)| *offset_line == line)
+    {
+        let injected_at = start + shift;
+        if column < injected_at {
+            break;
+        }
+        if column < injected_at + length {
+            return start;
+        }
+        shift += length;
+    }
+    column - shift
+}
+
+// fills lambda_line/lambda_column for diagnostics that point between the source markers of
+// `lambda_sol`, the generated Lambda.sol stored at `lambda_file`
+pub fn map_to_lambda_source(diagnostics: &mut [Diagnostic], lambda_file: &str, lambda_sol: &str) {
+    let marker_line = |marker: &str| {
+        lambda_sol
+            .lines()
+            .enumerate()
+            .find(|(_, line)| line.split_whitespace().next() == Some(marker))
+            .map(|(index, line)| (index + 1, line.trim()))
+    };
+    let (Some((begin, begin_marker)), Some((end, _))) = (
+        marker_line(LAMBDA_SOURCE_BEGIN),
+        marker_line(LAMBDA_SOURCE_END),
+    ) else {
+        return;
+    };
+    let offsets = parse_offsets(begin_marker);
+
+    for diagnostic in diagnostics {
+        let in_lambda_file = diagnostic
+            .file
+            .as_deref()
+            .is_some_and(|file| file.ends_with(lambda_file));
+        match diagnostic.line {
+            Some(line) if in_lambda_file && line > begin && line < end => {
+                diagnostic.lambda_line = Some(line - begin);
+                diagnostic.lambda_column = diagnostic
+                    .column
+                    .map(|column| original_column(&offsets, line - begin, column));
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORGE_OUTPUT: &str = "\
+[\u{1b}[32m⠊\u{1b}[0m] Compiling...
+Error: Compiler run failed:
+Error (7576): Undeclared identifier.
+  --> src/Lambda.sol:8:9:
+   |
+ 8 |         missing = 1;
+   |         ^^^^^^^
+
+Warning (2072): Unused local variable.
+ --> src/LambdaAppGateway.sol:30:13:
+   |
+30 |             uint256 x;
+   |             ^^^^^^^^^
+";
+
+    #[test]
+    fn test_parse_compiler_output() {
+        let diagnostics = parse_compiler_output(FORGE_OUTPUT);
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(diagnostics[0].code.as_deref(), Some("7576"));
+        assert_eq!(diagnostics[0].message, "Undeclared identifier.");
+        assert_eq!(diagnostics[0].file.as_deref(), Some("src/Lambda.sol"));
+        assert_eq!(
+            (diagnostics[0].line, diagnostics[0].column),
+            (Some(8), Some(9))
+        );
+        assert_eq!(diagnostics[1].severity, Severity::Warning);
+        assert_eq!(
+            diagnostics[1].to_string(),
+            "warning: Unused local variable. at src/LambdaAppGateway.sol:30:13"
+        );
+    }
+
+    #[test]
+    fn test_map_to_lambda_source() {
+        let lambda_sol = "contract Lambda {\n    uint256 public a;\n\n    //lambda_source_begin\nfunction lambda() {\n    a = 1;\n        missing = 1;\n}\n//lambda_source_end\n}\n";
+        let mut diagnostics = parse_compiler_output(FORGE_OUTPUT);
+        diagnostics[0].line = Some(7);
+        map_to_lambda_source(&mut diagnostics, "src/Lambda.sol", lambda_sol);
+        assert_eq!(diagnostics[0].lambda_line, Some(3));
+        assert_eq!(diagnostics[0].lambda_column, Some(9));
+        // the gateway is generated code, it has no lambda position
+        assert_eq!(diagnostics[1].lambda_line, None);
+    }
+
+    #[test]
+    fn test_map_columns_around_injected_text() {
+        let source = "function lambda() {\n    a = 1; missing = 1;\n}";
+        let injections = vec![
+            (
+                Span {
+                    line: 1,
+                    column: 20,
+                },
+                " emit X();".to_string(),
+            ),
+            (
+                Span {
+                    line: 2,
+                    column: 11,
+                },
+                " emit A(a);".to_string(),
+            ),
+        ];
+        let lambda_sol = format!(
+            "contract Lambda {{\n{}\n{}\n{}\n}}\n",
+            lambda_source_begin(&injections),
+            crate::events::inject(source, injections),
+            LAMBDA_SOURCE_END
+        );
+        assert!(lambda_sol.contains("a = 1; emit A(a); missing = 1;"));
+
+        let mut diagnostics = parse_compiler_output(FORGE_OUTPUT);
+        // `missing` on line 2 of the lambda, after the injected emit
+        diagnostics[0].line = Some(4);
+        diagnostics[0].column = Some(23);
+        diagnostics.push(Diagnostic {
+            line: Some(4),
+            column: Some(14),
+            ..diagnostics[0].clone()
+        });
+        map_to_lambda_source(&mut diagnostics, "src/Lambda.sol", &lambda_sol);
+        assert_eq!(diagnostics[0].lambda_line, Some(2));
+        assert_eq!(diagnostics[0].lambda_column, Some(12));
+        // inside the emit itself, reported where it was injected
+        assert_eq!(diagnostics[2].lambda_column, Some(11));
+    }
+}
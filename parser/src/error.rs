@@ -0,0 +1,138 @@
+use std::{error::Error, fmt};
+
+use crate::lexer::Span;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    UnterminatedString {
+        span: Span,
+    },
+    UnterminatedComment {
+        span: Span,
+    },
+    UnexpectedCharacter {
+        character: char,
+        span: Span,
+    },
+    UnexpectedToken {
+        expected: String,
+        found: String,
+        span: Span,
+    },
+    UnsupportedType {
+        type_name: String,
+        span: Span,
+    },
+    UnknownVariable {
+        name: String,
+        span: Span,
+    },
+    CannotInferType {
+        expression: String,
+        span: Span,
+    },
+    InvalidAssignmentTarget {
+        span: Span,
+    },
+    UnknownMember {
+        type_name: String,
+        member: String,
+        span: Span,
+    },
+    NestingTooDeep {
+        span: Span,
+    },
+    InvalidGuard {
+        guard: String,
+        reason: String,
+        span: Span,
+    },
+    // raised by the string based helpers on PrimitiveSolidityType, which have no source position
+    InvalidFunctionDeclaration {
+        declaration: String,
+    },
+    InvalidAssignment {
+        assignment: String,
+    },
+}
+
+impl ParseError {
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            ParseError::UnterminatedString { span }
+            | ParseError::UnterminatedComment { span }
+            | ParseError::UnexpectedCharacter { span, .. }
+            | ParseError::UnexpectedToken { span, .. }
+            | ParseError::UnsupportedType { span, .. }
+            | ParseError::UnknownVariable { span, .. }
+            | ParseError::CannotInferType { span, .. }
+            | ParseError::InvalidAssignmentTarget { span }
+            | ParseError::UnknownMember { span, .. }
+            | ParseError::NestingTooDeep { span }
+            | ParseError::InvalidGuard { span, .. } => Some(*span),
+            ParseError::InvalidFunctionDeclaration { .. }
+            | ParseError::InvalidAssignment { .. } => None,
+        }
+    }
+
+    // stable identifier for the failure, used when reporting errors over the API
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ParseError::UnterminatedString { .. } => "unterminated_string",
+            ParseError::UnterminatedComment { .. } => "unterminated_comment",
+            ParseError::UnexpectedCharacter { .. } => "unexpected_character",
+            ParseError::UnexpectedToken { .. } => "unexpected_token",
+            ParseError::UnsupportedType { .. } => "unsupported_type",
+            ParseError::UnknownVariable { .. } => "unknown_variable",
+            ParseError::CannotInferType { .. } => "cannot_infer_type",
+            ParseError::InvalidAssignmentTarget { .. } => "invalid_assignment_target",
+            ParseError::UnknownMember { .. } => "unknown_member",
+            ParseError::NestingTooDeep { .. } => "nesting_too_deep",
+            ParseError::InvalidGuard { .. } => "invalid_guard",
+            ParseError::InvalidFunctionDeclaration { .. } => "invalid_function_declaration",
+            ParseError::InvalidAssignment { .. } => "invalid_assignment",
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnterminatedString { .. } => write!(f, "Unterminated string literal")?,
+            ParseError::UnterminatedComment { .. } => write!(f, "Unterminated block comment")?,
+            ParseError::UnexpectedCharacter { character, .. } => {
+                write!(f, "Unexpected character {:?}", character)?
+            }
+            ParseError::UnexpectedToken {
+                expected, found, ..
+            } => write!(f, "Expected {} but found {}", expected, found)?,
+            ParseError::UnsupportedType { type_name, .. } => {
+                write!(f, "Unsupported type `{}`", type_name)?
+            }
+            ParseError::UnknownVariable { name, .. } => write!(f, "Unknown variable `{}`", name)?,
+            ParseError::CannotInferType { expression, .. } => {
+                write!(f, "Cannot infer type of {}", expression)?
+            }
+            ParseError::InvalidAssignmentTarget { .. } => write!(f, "Invalid assignment target")?,
+            ParseError::UnknownMember {
+                type_name, member, ..
+            } => write!(f, "`{}` has no member `{}`", type_name, member)?,
+            ParseError::NestingTooDeep { .. } => write!(f, "Nesting too deep")?,
+            ParseError::InvalidGuard { guard, reason, .. } => {
+                write!(f, "Invalid guard `{}`: {}", guard, reason)?
+            }
+            ParseError::InvalidFunctionDeclaration { declaration } => {
+                write!(f, "Invalid function declaration: {}", declaration)?
+            }
+            ParseError::InvalidAssignment { assignment } => {
+                write!(f, "Invalid assignment: {}", assignment)?
+            }
+        }
+        if let Some(span) = self.span() {
+            write!(f, " at line {}, column {}", span.line, span.column)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ParseError {}
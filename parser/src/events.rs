@@ -0,0 +1,201 @@
+use crate::ast::Expression;
+use crate::capitalize;
+use crate::lexer::Span;
+use crate::types::PrimitiveSolidityType;
+
+// emitted first thing in every entrypoint, indexers decode it as `LambdaCalled(address,bytes)`
+pub(crate) const LAMBDA_CALLED_EMIT: &str = "emit LambdaCalled(msg.sender, msg.data);";
+
+// solidity allows at most three indexed event parameters
+const MAX_INDEXED: usize = 3;
+
+// a statement writing to a storage variable, which gets the variable's event emitted after it
+#[derive(Clone, Debug)]
+pub(crate) struct StateWrite {
+    pub name: String,
+    // source of the mapping keys written through: `user` for `balances[user] = 1`
+    pub keys: Vec<String>,
+    pub start: Span,
+    pub end: Span,
+    // false for the single statement body of an if or loop, which needs braces around the emit
+    pub braced: bool,
+}
+
+// `balances` changes are announced by `BalancesUpdated`
+pub(crate) fn event_name(variable: &str) -> String {
+    format!("{}Updated", capitalize(variable))
+}
+
+// the key types of a mapping chain and the value at its end:
+// mapping(address=>mapping(uint256=>bool)) is keyed by (address, uint256) and holds a bool
+fn unwrap_mappings(
+    var_type: &PrimitiveSolidityType,
+) -> (Vec<&PrimitiveSolidityType>, &PrimitiveSolidityType) {
+    let mut keys = Vec::new();
+    let mut value = var_type;
+    while let PrimitiveSolidityType::Mapping { key, value: inner } = value {
+        keys.push(key.as_ref());
+        value = inner;
+    }
+    (keys, value)
+}
+
+// `event BalancesUpdated(address indexed key0, uint256 value);`, values holding a mapping are left out
+pub(crate) fn event_declaration(name: &str, var_type: &PrimitiveSolidityType) -> String {
+    let (keys, value) = unwrap_mappings(var_type);
+    let mut parameters = keys
+        .iter()
+        .enumerate()
+        .map(|(index, key)| {
+            let indexed = if index < MAX_INDEXED { " indexed" } else { "" };
+            format!("{}{} key{}", key, indexed, index)
+        })
+        .collect::<Vec<String>>();
+    if !value.contains_mapping() {
+        parameters.push(format!("{} value", value));
+    }
+    format!("event {}({});\n", event_name(name), parameters.join(", "))
+}
+
+// the emit following `write`, None when its keys don't reach the end of the mapping chain
+pub(crate) fn state_write_emit(
+    write: &StateWrite,
+    var_type: &PrimitiveSolidityType,
+) -> Option<String> {
+    let (keys, value) = unwrap_mappings(var_type);
+    if keys.len() != write.keys.len() {
+        return None;
+    }
+    let mut arguments = write.keys.clone();
+    if !value.contains_mapping() {
+        arguments.push(format!(
+            "{}{}",
+            write.name,
+            write
+                .keys
+                .iter()
+                .map(|key| format!("[{}]", key))
+                .collect::<String>()
+        ));
+    }
+    Some(format!(
+        "emit {}({});",
+        event_name(&write.name),
+        arguments.join(", ")
+    ))
+}
+
+// the indices applied to the variable itself: `user` for `positions[user].fills[i]`, where `i`
+// indexes a struct field instead
+pub(crate) fn root_keys(target: &Expression) -> Option<Vec<&Expression>> {
+    fn walk(expression: &Expression) -> Option<(Vec<&Expression>, bool)> {
+        match expression {
+            Expression::Identifier(_) => Some((Vec::new(), false)),
+            Expression::Index {
+                base,
+                index: Some(key),
+            } => {
+                let (mut keys, in_member) = walk(base)?;
+                if !in_member {
+                    keys.push(key);
+                }
+                Some((keys, in_member))
+            }
+            Expression::MemberAccess { expression, .. } => {
+                let (keys, _) = walk(expression)?;
+                Some((keys, true))
+            }
+            _ => None,
+        }
+    }
+    walk(target).map(|(keys, _)| keys)
+}
+
+// source of a key that can be evaluated again in the emit. Keys with side effects, such as calls
+// or increments, return None and their write goes without an event.
+pub(crate) fn key_source(expression: &Expression) -> Option<String> {
+    match expression {
+        Expression::Identifier(value)
+        | Expression::Number(value)
+        | Expression::HexNumber(value) => Some(value.clone()),
+        Expression::Bool(value) => Some(value.to_string()),
+        Expression::StringLiteral(value) if !value.contains('"') => Some(format!("\"{}\"", value)),
+        Expression::MemberAccess { expression, member } => {
+            Some(format!("{}.{}", key_source(expression)?, member))
+        }
+        Expression::Index {
+            base,
+            index: Some(index),
+        } => Some(format!("{}[{}]", key_source(base)?, key_source(index)?)),
+        _ => None,
+    }
+}
+
+// inserts each text at its position in `source`, texts at the same position keep their order.
+// Nothing inserted contains a newline, so lines of the lambda keep their numbers.
+pub(crate) fn inject(source: &str, mut injections: Vec<(Span, String)>) -> String {
+    injections.sort_by_key(|(span, _)| (span.line, span.column));
+    let mut pending = injections.into_iter().peekable();
+    let mut output = String::with_capacity(source.len());
+    let mut position = Span { line: 1, column: 1 };
+    for c in source.chars() {
+        while let Some((_, text)) = pending
+            .next_if(|(span, _)| (span.line, span.column) <= (position.line, position.column))
+        {
+            output.push_str(&text);
+        }
+        output.push(c);
+        if c == '\n' {
+            position.line += 1;
+            position.column = 1;
+        } else {
+            position.column += 1;
+        }
+    }
+    for (_, text) in pending {
+        output.push_str(&text);
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{parse_source, StatementKind};
+
+    #[test]
+    fn test_root_keys_stop_at_members() {
+        let unit = parse_source("function f() { positions[user][id].fills[i] = 1; }").unwrap();
+        let body = unit.functions().next().unwrap().body.clone().unwrap();
+        let StatementKind::Expression(Expression::Assignment { left, .. }) = &body[0].kind else {
+            panic!("expected assignment");
+        };
+        let keys = root_keys(left)
+            .unwrap()
+            .into_iter()
+            .map(key_source)
+            .collect::<Option<Vec<String>>>();
+        assert_eq!(keys, Some(vec!["user".to_string(), "id".to_string()]));
+        assert_eq!(
+            body[0].end,
+            Span {
+                line: 1,
+                column: 49
+            }
+        );
+    }
+
+    #[test]
+    fn test_inject_keeps_lines() {
+        let source = "a;\nb;";
+        let injected = inject(
+            source,
+            vec![
+                (Span { line: 2, column: 3 }, " c;".to_string()),
+                (Span { line: 1, column: 1 }, "{ ".to_string()),
+                (Span { line: 1, column: 3 }, " }".to_string()),
+            ],
+        );
+        assert_eq!(injected, "{ a; }\nb; c;");
+    }
+}
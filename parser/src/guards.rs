@@ -0,0 +1,363 @@
+use sha3::{Digest, Keccak256};
+
+use crate::error::ParseError;
+use crate::lexer::Span;
+use crate::types::{Parameter, PrimitiveSolidityType};
+
+// NatSpec tag declaring a gateway guard on an entrypoint, e.g. `/// @custom:guard min-fee 1000`
+const GUARD_TAG: &str = "@custom:guard";
+
+// a check the gateway runs before forwarding a call, only for the entrypoint declaring it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Guard {
+    // `allow 0xab.. 0xcd..`: only these callers may use the gateway, kept checksummed
+    Allowlist(Vec<String>),
+    // `min-fee 1000`: calls aren't scheduled with a smaller fee budget
+    MinFee(String),
+    // `pausable`: the deployer can stop the gateway with `setPaused`
+    Pausable,
+    // `bound amount 1 1000`: the argument has to fall within the inclusive range
+    Bound {
+        argument: String,
+        min: String,
+        max: String,
+    },
+}
+
+// the guards declared in an entrypoint's doc comments, `inputs` are its parameters
+pub(crate) fn parse_guards(
+    doc: &[String],
+    inputs: &[Parameter],
+    span: Span,
+) -> Result<Vec<Guard>, ParseError> {
+    let mut guards = Vec::new();
+    for line in doc.iter().flat_map(|comment| comment.lines()) {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some(GUARD_TAG) => {}
+            // a misspelt or misplaced guard must not silently go unchecked
+            _ if line.contains(GUARD_TAG) => {
+                return Err(ParseError::InvalidGuard {
+                    guard: line.trim().to_string(),
+                    reason: format!("expected a line starting with {}", GUARD_TAG),
+                    span,
+                })
+            }
+            _ => continue,
+        }
+        let words = words.collect::<Vec<&str>>();
+        let invalid = |reason: &str| ParseError::InvalidGuard {
+            guard: words.join(" "),
+            reason: reason.to_string(),
+            span,
+        };
+        let guard = match words.as_slice() {
+            ["allow", addresses @ ..] if !addresses.is_empty() => Guard::Allowlist(
+                addresses
+                    .iter()
+                    .map(|address| {
+                        checksum_address(address).ok_or_else(|| invalid("expected addresses"))
+                    })
+                    .collect::<Result<Vec<String>, ParseError>>()?,
+            ),
+            ["min-fee", fee] if is_integer(fee, false) => Guard::MinFee(fee.to_string()),
+            ["pausable"] => Guard::Pausable,
+            ["bound", argument, min, max] => {
+                let (signed, bits) = match inputs.iter().find(|input| input.name == *argument) {
+                    Some(Parameter {
+                        var_type: PrimitiveSolidityType::Int(bits),
+                        ..
+                    }) => (true, *bits),
+                    Some(Parameter {
+                        var_type: PrimitiveSolidityType::Uint(bits),
+                        ..
+                    }) => (false, *bits),
+                    Some(_) => return Err(invalid("only integer arguments can be bounded")),
+                    None => return Err(invalid("unknown argument")),
+                };
+                if !is_integer(min, signed) || !is_integer(max, signed) {
+                    return Err(invalid("expected integer bounds"));
+                }
+                if !fits(min, signed, bits) || !fits(max, signed, bits) {
+                    return Err(invalid("the bounds don't fit the argument's type"));
+                }
+                if signed_integer_gt(min, max) {
+                    return Err(invalid("the lower bound is above the upper one"));
+                }
+                Guard::Bound {
+                    argument: argument.to_string(),
+                    min: min.to_string(),
+                    max: max.to_string(),
+                }
+            }
+            _ => return Err(invalid("expected allow, min-fee, pausable or bound")),
+        };
+        guards.push(guard);
+    }
+    Ok(guards)
+}
+
+// the `require`s an entrypoint's forwarder runs before calling the lambda
+pub(crate) fn forwarder_checks(guards: &[Guard]) -> Vec<String> {
+    let mut allowed: Vec<&String> = Vec::new();
+    let mut min_fee: Option<&str> = None;
+    let mut pausable = false;
+    let mut bounds = Vec::new();
+    for guard in guards {
+        match guard {
+            Guard::Allowlist(addresses) => {
+                for address in addresses {
+                    if !allowed.contains(&address) {
+                        allowed.push(address);
+                    }
+                }
+            }
+            // several declarations may ask for a minimum, the highest one is enforced
+            Guard::MinFee(fee) => {
+                if min_fee.is_none_or(|current| integer_gt(fee, current)) {
+                    min_fee = Some(fee);
+                }
+            }
+            Guard::Pausable => pausable = true,
+            Guard::Bound { argument, min, max } => bounds.push(format!(
+                "require({0} >= {1} && {0} <= {2}, \"{0} out of bounds\");",
+                argument, min, max
+            )),
+        }
+    }
+
+    let mut checks = Vec::new();
+    if !allowed.is_empty() {
+        let callers = allowed
+            .iter()
+            .map(|address| format!("msg.sender == {}", address))
+            .collect::<Vec<String>>()
+            .join(" || ");
+        checks.push(format!("require({}, \"caller not allowed\");", callers));
+    }
+    if let Some(fee) = min_fee {
+        checks.push(format!(
+            "require(feesData.maxFees >= {}, \"fees below minimum\");",
+            fee
+        ));
+    }
+    if pausable {
+        checks.push("require(!paused, \"gateway paused\");".to_string());
+    }
+    checks.extend(bounds);
+    checks
+}
+
+// state and setter backing the pause switch, None unless a `pausable` guard was declared
+pub(crate) fn pause_switch<'a>(mut guards: impl Iterator<Item = &'a Guard>) -> Option<String> {
+    guards.any(|guard| *guard == Guard::Pausable).then(|| {
+        "bool public paused;
+    address public immutable pauser = msg.sender;
+
+    function setPaused(bool paused_) external {
+        require(msg.sender == pauser, \"not pauser\");
+        paused = paused_;
+    }"
+        .to_string()
+    })
+}
+
+fn is_integer(value: &str, signed: bool) -> bool {
+    let digits = match value.strip_prefix('-') {
+        Some(digits) if signed => digits,
+        Some(_) => return false,
+        None => value,
+    };
+    !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+}
+
+// whether the decimal `value` is in range of an int<bits> (`signed`) or uint<bits>
+fn fits(value: &str, signed: bool, bits: u16) -> bool {
+    let (negative, digits) = match value.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, value),
+    };
+    // the magnitude has to stay below 2^bits, 2^(bits-1) for signed types
+    let limit = power_of_two(if signed { bits.saturating_sub(1) } else { bits });
+    // -2^(bits-1) itself is the smallest signed value
+    integer_gt(&limit, digits) || (negative && !integer_gt(digits, &limit))
+}
+
+// 2^exponent in decimal, int256 bounds don't fit any native integer
+fn power_of_two(exponent: u16) -> String {
+    // little endian decimal digits
+    let mut digits = vec![1u8];
+    for _ in 0..exponent {
+        let mut carry = 0;
+        for digit in digits.iter_mut() {
+            let doubled = *digit * 2 + carry;
+            *digit = doubled % 10;
+            carry = doubled / 10;
+        }
+        if carry > 0 {
+            digits.push(carry);
+        }
+    }
+    digits
+        .iter()
+        .rev()
+        .map(|digit| char::from(b'0' + digit))
+        .collect()
+}
+
+// compares non-negative decimal strings that may not fit any integer type
+fn integer_gt(left: &str, right: &str) -> bool {
+    let left = left.trim_start_matches('0');
+    let right = right.trim_start_matches('0');
+    (left.len(), left) > (right.len(), right)
+}
+
+// like integer_gt, for decimal strings that may start with a `-`
+fn signed_integer_gt(left: &str, right: &str) -> bool {
+    match (left.strip_prefix('-'), right.strip_prefix('-')) {
+        (None, None) => integer_gt(left, right),
+        (Some(left), Some(right)) => integer_gt(right, left),
+        // -0 isn't below 0
+        (None, Some(right)) => integer_gt(left, "0") || integer_gt(right, "0"),
+        (Some(_), None) => false,
+    }
+}
+
+// EIP-55 spelling of an address, solc rejects address literals with a wrong checksum
+fn checksum_address(address: &str) -> Option<String> {
+    let hex = address.strip_prefix("0x")?.to_ascii_lowercase();
+    if hex.len() != 40 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let hash = Keccak256::digest(hex.as_bytes());
+    let checksummed = hex
+        .chars()
+        .enumerate()
+        .map(|(index, c)| {
+            let nibble = (hash[index / 2] >> (4 * (1 - index % 2))) & 0x0f;
+            if nibble >= 8 {
+                c.to_ascii_uppercase()
+            } else {
+                c
+            }
+        })
+        .collect::<String>();
+    Some(format!("0x{}", checksummed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parameter(name: &str, var_type: PrimitiveSolidityType) -> Parameter {
+        Parameter {
+            name: name.to_string(),
+            var_type,
+            location: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_guards() {
+        let inputs = [
+            parameter("amount", PrimitiveSolidityType::Uint(256)),
+            parameter("user", PrimitiveSolidityType::Address),
+        ];
+        let doc = [
+            "@custom:lambda".to_string(),
+            "@custom:guard allow 0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed".to_string(),
+            "@custom:guard min-fee 1000\n@custom:guard bound amount 1 500".to_string(),
+        ];
+        assert_eq!(
+            parse_guards(&doc, &inputs, Span::default()).unwrap(),
+            vec![
+                Guard::Allowlist(vec![
+                    "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed".to_string()
+                ]),
+                Guard::MinFee("1000".to_string()),
+                Guard::Bound {
+                    argument: "amount".to_string(),
+                    min: "1".to_string(),
+                    max: "500".to_string(),
+                },
+            ]
+        );
+
+        let invalid = |line: &str| {
+            parse_guards(&[line.to_string()], &inputs, Span::default())
+                .unwrap_err()
+                .to_string()
+        };
+        assert!(invalid("@custom:guard bound user 1 2").contains("only integer arguments"));
+        assert!(invalid("@custom:guard bound amount -1 2").contains("expected integer bounds"));
+        assert!(invalid("@custom:guard allow 0x12").contains("expected addresses"));
+        assert!(invalid("@custom:guard sometimes").contains("expected allow"));
+        // bounds beyond i128 are still ordered
+        let (low, high) = (
+            "170141183460469231731687303715884105728",
+            "170141183460469231731687303715884105729",
+        );
+        let bound = |min: &str, max: &str| format!("@custom:guard bound amount {} {}", min, max);
+        assert!(invalid(&bound(high, low)).contains("lower bound is above"));
+        assert!(parse_guards(&[bound(low, high)], &inputs, Span::default()).is_ok());
+        assert!(invalid("@custom:guards pausable").contains("expected a line starting"));
+        assert!(invalid("@dev @custom:guard pausable").contains("expected a line starting"));
+    }
+
+    #[test]
+    fn test_forwarder_checks() {
+        let guards = [
+            Guard::MinFee("900".to_string()),
+            Guard::Bound {
+                argument: "amount".to_string(),
+                min: "1".to_string(),
+                max: "10".to_string(),
+            },
+            Guard::Pausable,
+            Guard::MinFee("1000".to_string()),
+        ];
+        assert_eq!(
+            forwarder_checks(&guards),
+            vec![
+                "require(feesData.maxFees >= 1000, \"fees below minimum\");".to_string(),
+                "require(!paused, \"gateway paused\");".to_string(),
+                "require(amount >= 1 && amount <= 10, \"amount out of bounds\");".to_string(),
+            ]
+        );
+        assert!(pause_switch(guards.iter()).is_some());
+        assert!(pause_switch([].iter()).is_none());
+    }
+
+    #[test]
+    fn test_bounds_fit_the_argument_type() {
+        assert!(fits("255", false, 8));
+        assert!(!fits("256", false, 8));
+        assert!(fits("-128", true, 8));
+        assert!(!fits("-129", true, 8));
+        assert!(!fits("128", true, 8));
+        assert_eq!(
+            power_of_two(256),
+            "115792089237316195423570985008687907853269984665640564039457584007913129639936"
+        );
+        assert!(fits(
+            "115792089237316195423570985008687907853269984665640564039457584007913129639935",
+            false,
+            256
+        ));
+
+        let inputs = [parameter("small", PrimitiveSolidityType::Uint(8))];
+        let error = parse_guards(
+            &["@custom:guard bound small 1 1000".to_string()],
+            &inputs,
+            Span::default(),
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("don't fit"));
+
+        assert!(signed_integer_gt("-1", "-2"));
+        assert!(signed_integer_gt("0", "-1"));
+        assert!(!signed_integer_gt("-0", "0"));
+        assert!(!signed_integer_gt("0", "-0"));
+        assert!(!signed_integer_gt("-5", "3"));
+    }
+}
@@ -0,0 +1,493 @@
+use std::collections::HashSet;
+
+use crate::ast::{Expression, FunctionDefinition, Statement, StatementKind};
+use crate::types::{Parameter, PrimitiveSolidityType};
+use crate::write_targets;
+
+// attributes that aren't modifiers, or are modifiers known not to touch storage
+const PLAIN_ATTRIBUTES: &[&str] = &[
+    "public",
+    "external",
+    "internal",
+    "private",
+    "payable",
+    "view",
+    "pure",
+    "virtual",
+    "override",
+    "onlySocket",
+];
+
+// global functions that don't write to storage, calls to anything else may
+const PURE_BUILTINS: &[&str] = &[
+    "require",
+    "assert",
+    "revert",
+    "keccak256",
+    "sha256",
+    "ripemd160",
+    "ecrecover",
+    "addmod",
+    "mulmod",
+    "gasleft",
+    "blockhash",
+    "payable",
+];
+
+// how one entrypoint is exercised by the generated test: the arguments it is called with and
+// what the state has to look like afterwards
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct EntrypointTest {
+    // `lambda(uint256,address)`, overloads can't be told apart by their name
+    signature: String,
+    arguments: Vec<String>,
+    expectations: Vec<Expectation>,
+}
+
+// `balances[user] = amount` becomes the check `lambda.balances(<user>) == <amount>`
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Expectation {
+    variable: String,
+    keys: Vec<String>,
+    value: String,
+}
+
+// None when the entrypoint can't be called from a test contract or takes an argument we
+// can't make up a value for
+pub(crate) fn plan_test(
+    function: &FunctionDefinition,
+    inputs: &[Parameter],
+) -> Option<EntrypointTest> {
+    let callable = function
+        .attributes
+        .iter()
+        .any(|attribute| attribute == "public" || attribute == "external");
+    if !callable || inputs.len() != function.parameters.len() {
+        return None;
+    }
+    let arguments = inputs
+        .iter()
+        .map(|input| sample_argument(&input.var_type))
+        .collect::<Option<Vec<String>>>()?;
+
+    // a modifier we know nothing about may write anything
+    let modified = function
+        .attributes
+        .iter()
+        .any(|attribute| !PLAIN_ATTRIBUTES.contains(&attribute.as_str()));
+    let expectations = match &function.body {
+        Some(body) if !modified => expected_writes(body, inputs, &arguments),
+        _ => Vec::new(),
+    };
+    let signature = format!(
+        "{}({})",
+        function.name,
+        inputs
+            .iter()
+            .map(|input| abi_type(&input.var_type))
+            .collect::<Vec<String>>()
+            .join(",")
+    );
+    Some(EntrypointTest {
+        signature,
+        arguments,
+        expectations,
+    })
+}
+
+// the source of `LambdaTest.sol`. `state_type` gives the type of a public storage variable,
+// only those can be read back through their getter.
+pub(crate) fn render_test<'a>(
+    tests: impl Iterator<Item = (&'a str, &'a EntrypointTest, bool)>,
+    state_type: impl Fn(&str) -> Option<&'a PrimitiveSolidityType>,
+) -> String {
+    let tests = tests.collect::<Vec<_>>();
+    let mut functions = String::new();
+    for (index, (name, test, emits)) in tests.iter().enumerate() {
+        let arguments = test.arguments.join(", ");
+        // `Lambda.lambda` doesn't name a single function when it is overloaded
+        let overloads = tests.iter().filter(|(other, ..)| other == name).count();
+        let (test_name, calldata) = if overloads > 1 {
+            let overload = tests[..index]
+                .iter()
+                .filter(|(other, ..)| other == name)
+                .count();
+            (
+                format!("{}_{}", name, overload),
+                format!(
+                    "abi.encodeWithSignature(\"{}\"{})",
+                    test.signature,
+                    test.arguments
+                        .iter()
+                        .map(|argument| format!(", {}", argument))
+                        .collect::<String>()
+                ),
+            )
+        } else {
+            (
+                name.to_string(),
+                format!("abi.encodeCall(Lambda.{}, ({}))", name, arguments),
+            )
+        };
+        functions.push_str(&format!("\n    function test_{}() public {{\n", test_name));
+        if *emits {
+            functions.push_str(&format!(
+                "        vm.expectEmit(address(lambda));\n        emit LambdaCalled(address(this), {});\n",
+                calldata
+            ));
+        }
+        functions.push_str(&format!("        lambda.{}({});\n", name, arguments));
+        for expectation in &test.expectations {
+            let Some(cast) = state_type(&expectation.variable)
+                .and_then(|var_type| stored_type(var_type, expectation.keys.len()))
+                .and_then(value_cast)
+            else {
+                continue;
+            };
+            functions.push_str(&format!(
+                "        assertEq({}, {});\n",
+                convert(
+                    cast,
+                    &format!(
+                        "lambda.{}({})",
+                        expectation.variable,
+                        expectation.keys.join(", ")
+                    )
+                ),
+                convert(cast, &expectation.value)
+            ));
+        }
+        functions.push_str("    }\n");
+    }
+
+    format!(
+        "// SPDX-License-Identifier: GPL-3.0
+pragma solidity >=0.7.0 <0.9.0;
+
+import \"forge-std/Test.sol\";
+import {{Lambda}} from \"../src/Lambda.sol\";
+
+// generated with the lambda, calls every entrypoint once with sample arguments
+contract LambdaTest is Test {{
+    event LambdaCalled(address caller, bytes args);
+
+    Lambda lambda;
+
+    function setUp() public {{
+        lambda = new Lambda();
+        lambda.setSocket(address(this));
+    }}
+{}}}
+",
+        functions
+    )
+}
+
+// a value of the type to call an entrypoint with, None for types without an obvious one
+fn sample_argument(var_type: &PrimitiveSolidityType) -> Option<String> {
+    match var_type {
+        PrimitiveSolidityType::Bool => Some("true".to_string()),
+        PrimitiveSolidityType::Uint(bits) => Some(format!("uint{}(1)", bits)),
+        PrimitiveSolidityType::Int(bits) => Some(format!("int{}(1)", bits)),
+        PrimitiveSolidityType::Address => Some("address(0xBEEF)".to_string()),
+        PrimitiveSolidityType::AddressPayable => Some("payable(address(0xBEEF))".to_string()),
+        PrimitiveSolidityType::FixedBytes(size) => {
+            Some(format!("bytes{}(uint{}(1))", size, *size as u16 * 8))
+        }
+        PrimitiveSolidityType::String => Some("\"lambda\"".to_string()),
+        PrimitiveSolidityType::Bytes => Some("hex\"01\"".to_string()),
+        // types declared in the lambda source end up inside the Lambda contract
+        PrimitiveSolidityType::Enum { name, members } => members
+            .first()
+            .map(|member| format!("Lambda.{}.{}", name, member)),
+        _ => None,
+    }
+}
+
+// how the type is spelled in a function signature
+fn abi_type(var_type: &PrimitiveSolidityType) -> String {
+    match var_type {
+        PrimitiveSolidityType::AddressPayable => "address".to_string(),
+        PrimitiveSolidityType::Enum { .. } => "uint8".to_string(),
+        _ => var_type.to_string(),
+    }
+}
+
+// the type found after looking up `keys` keys: a uint256 for one key into mapping(address=>uint256)
+fn stored_type(var_type: &PrimitiveSolidityType, keys: usize) -> Option<&PrimitiveSolidityType> {
+    match (var_type, keys) {
+        (_, 0) => Some(var_type),
+        (PrimitiveSolidityType::Mapping { value, .. }, _) => stored_type(value, keys - 1),
+        _ => None,
+    }
+}
+
+// the conversion both sides of an assertEq go through, so the call picks a single overload
+fn value_cast(var_type: &PrimitiveSolidityType) -> Option<&'static str> {
+    match var_type {
+        PrimitiveSolidityType::Bool => Some("bool"),
+        PrimitiveSolidityType::Uint(_) | PrimitiveSolidityType::Enum { .. } => Some("uint256"),
+        PrimitiveSolidityType::Int(_) => Some("int256"),
+        PrimitiveSolidityType::Address | PrimitiveSolidityType::AddressPayable => Some("address"),
+        PrimitiveSolidityType::FixedBytes(32) => Some("bytes32"),
+        // strings only have the one overload, they go without a conversion
+        PrimitiveSolidityType::String => Some(""),
+        _ => None,
+    }
+}
+
+// `value` converted to `cast`, unless it is already spelled as that conversion
+fn convert(cast: &str, value: &str) -> String {
+    if cast.is_empty() || value.starts_with(&format!("{}(", cast)) {
+        value.to_string()
+    } else {
+        format!("{}({})", cast, value)
+    }
+}
+
+// the writes of the top level statements whose outcome is known without running anything: a
+// parameter or literal stored under parameter or literal keys. Anything later that may write
+// the same variable again drops the expectation.
+fn expected_writes(
+    body: &[Statement],
+    inputs: &[Parameter],
+    arguments: &[String],
+) -> Vec<Expectation> {
+    // parameters the lambda assigns to no longer hold their sample argument
+    let mut reassigned: HashSet<&str> = HashSet::new();
+    let source = |expression: &Expression, reassigned: &HashSet<&str>| -> Option<String> {
+        match expression {
+            Expression::Identifier(name) if reassigned.contains(name.as_str()) => None,
+            Expression::Identifier(name) => inputs
+                .iter()
+                .position(|input| &input.name == name)
+                .map(|index| arguments[index].clone()),
+            Expression::Number(value) | Expression::HexNumber(value) => Some(value.clone()),
+            Expression::Bool(value) => Some(value.to_string()),
+            Expression::StringLiteral(value) if !value.contains('"') => {
+                Some(format!("\"{}\"", value))
+            }
+            _ => None,
+        }
+    };
+
+    let mut locals = inputs
+        .iter()
+        .map(|input| input.name.clone())
+        .collect::<HashSet<String>>();
+    let mut expectations: Vec<Expectation> = Vec::new();
+    // after a conditional return later writes may not happen, so they only drop expectations
+    let mut certain = true;
+    for statement in body {
+        if let StatementKind::VariableDeclaration { declaration, .. } = &statement.kind {
+            locals.extend(declaration.name.clone());
+        }
+
+        let simple_write = match &statement.kind {
+            StatementKind::Expression(Expression::Assignment {
+                operator: "=",
+                left,
+                right,
+            }) => simple_target(left)
+                .filter(|(variable, _)| !locals.contains(*variable))
+                .and_then(|(variable, keys)| {
+                    Some(Expectation {
+                        variable: variable.to_string(),
+                        keys: keys
+                            .into_iter()
+                            .map(|key| source(key, &reassigned))
+                            .collect::<Option<Vec<String>>>()?,
+                        value: source(right, &reassigned)?,
+                    })
+                }),
+            _ => None,
+        };
+        if let Some(expectation) = simple_write {
+            // different keys may still name the same slot, only the last write is checked
+            expectations.retain(|known| known.variable != expectation.variable);
+            if certain {
+                expectations.push(expectation);
+            }
+            continue;
+        }
+
+        let mut statements = Vec::new();
+        let mut expressions = Vec::new();
+        walk_statement(statement, &mut statements, &mut expressions);
+        if expressions
+            .iter()
+            .any(|expression| calls_unknown_function(expression))
+        {
+            expectations.clear();
+        }
+        let mut targets = Vec::new();
+        for expression in expressions {
+            write_targets(expression, &mut targets);
+        }
+        for target in targets {
+            if let Some(root) = root_name(target) {
+                expectations.retain(|known| known.variable != root);
+                if inputs.iter().any(|input| input.name == root) {
+                    reassigned.insert(root);
+                }
+            }
+        }
+        if statements.iter().any(|nested| {
+            matches!(
+                nested.kind,
+                StatementKind::Return(_) | StatementKind::Revert(_)
+            )
+        }) {
+            certain = false;
+        }
+    }
+    expectations
+}
+
+// `balances[user][id]` as ("balances", [user, id]), None for struct members and the like
+fn simple_target(target: &Expression) -> Option<(&str, Vec<&Expression>)> {
+    match target {
+        Expression::Identifier(name) => Some((name, Vec::new())),
+        Expression::Index {
+            base,
+            index: Some(key),
+        } => {
+            let (name, mut keys) = simple_target(base)?;
+            keys.push(key);
+            Some((name, keys))
+        }
+        _ => None,
+    }
+}
+
+fn root_name(target: &Expression) -> Option<&str> {
+    match target {
+        Expression::Identifier(name) => Some(name),
+        Expression::Index { base, .. } => root_name(base),
+        Expression::MemberAccess { expression, .. } => root_name(expression),
+        _ => None,
+    }
+}
+
+// internal functions and calls through `this` may write any storage variable
+fn calls_unknown_function(expression: &Expression) -> bool {
+    let Expression::Call { callee, .. } = expression else {
+        return false;
+    };
+    match callee.as_ref() {
+        Expression::Identifier(name) => {
+            !PURE_BUILTINS.contains(&name.as_str())
+                && PrimitiveSolidityType::from_string(name).is_none()
+                && !name.starts_with(|c: char| c.is_ascii_uppercase())
+        }
+        Expression::MemberAccess { expression, .. } => {
+            matches!(expression.as_ref(), Expression::Identifier(base) if base == "this")
+        }
+        _ => false,
+    }
+}
+
+// collects `statement` with everything nested in it
+fn walk_statement<'a>(
+    statement: &'a Statement,
+    statements: &mut Vec<&'a Statement>,
+    expressions: &mut Vec<&'a Expression>,
+) {
+    statements.push(statement);
+    match &statement.kind {
+        StatementKind::Block(nested) | StatementKind::Unchecked(nested) => {
+            for nested in nested {
+                walk_statement(nested, statements, expressions);
+            }
+        }
+        StatementKind::VariableDeclaration { value, .. } => {
+            if let Some(value) = value {
+                walk_expression(value, expressions);
+            }
+        }
+        StatementKind::TupleDeclaration { value, .. }
+        | StatementKind::Expression(value)
+        | StatementKind::Return(Some(value))
+        | StatementKind::Emit(value)
+        | StatementKind::Revert(value) => walk_expression(value, expressions),
+        StatementKind::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            walk_expression(condition, expressions);
+            walk_statement(then_branch, statements, expressions);
+            if let Some(else_branch) = else_branch {
+                walk_statement(else_branch, statements, expressions);
+            }
+        }
+        StatementKind::For {
+            init,
+            condition,
+            update,
+            body,
+        } => {
+            if let Some(init) = init {
+                walk_statement(init, statements, expressions);
+            }
+            for expression in condition.iter().chain(update) {
+                walk_expression(expression, expressions);
+            }
+            walk_statement(body, statements, expressions);
+        }
+        StatementKind::While { condition, body } | StatementKind::DoWhile { body, condition } => {
+            walk_expression(condition, expressions);
+            walk_statement(body, statements, expressions);
+        }
+        StatementKind::Return(None)
+        | StatementKind::Continue
+        | StatementKind::Break
+        | StatementKind::Assembly => {}
+    }
+}
+
+fn walk_expression<'a>(expression: &'a Expression, expressions: &mut Vec<&'a Expression>) {
+    expressions.push(expression);
+    match expression {
+        Expression::ArrayLiteral(elements) => {
+            for element in elements {
+                walk_expression(element, expressions);
+            }
+        }
+        Expression::Tuple(elements) => {
+            for element in elements.iter().flatten() {
+                walk_expression(element, expressions);
+            }
+        }
+        Expression::MemberAccess { expression, .. } => walk_expression(expression, expressions),
+        Expression::Index { base, index } => {
+            walk_expression(base, expressions);
+            if let Some(index) = index {
+                walk_expression(index, expressions);
+            }
+        }
+        Expression::Call {
+            callee, arguments, ..
+        } => {
+            walk_expression(callee, expressions);
+            for argument in arguments {
+                walk_expression(argument, expressions);
+            }
+        }
+        Expression::Unary { operand, .. } => walk_expression(operand, expressions),
+        Expression::Binary { left, right, .. } | Expression::Assignment { left, right, .. } => {
+            walk_expression(left, expressions);
+            walk_expression(right, expressions);
+        }
+        Expression::Conditional {
+            condition,
+            then_expression,
+            else_expression,
+        } => {
+            walk_expression(condition, expressions);
+            walk_expression(then_expression, expressions);
+            walk_expression(else_expression, expressions);
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,311 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::types::PrimitiveSolidityType;
+
+// closes the fields of a struct behind a mapping, which unlike an inline struct may grow
+const OPEN_STRUCT_END: &str = "..}";
+
+// one storage variable of the generated Lambda contract. Variables are kept in declaration
+// order, which is the order solidity assigns their slots in.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateVariable {
+    pub name: String,
+    pub var_type: String,
+}
+
+impl StateVariable {
+    pub fn new(name: &str, var_type: &PrimitiveSolidityType) -> Self {
+        Self {
+            name: name.to_string(),
+            var_type: layout_type(var_type),
+        }
+    }
+}
+
+// a difference between two layouts that would corrupt storage if the contract were upgraded
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "change", rename_all = "lowercase")]
+pub enum LayoutChange {
+    Removed {
+        name: String,
+        var_type: String,
+    },
+    Retyped {
+        name: String,
+        from: String,
+        to: String,
+    },
+    Reordered {
+        name: String,
+        from: usize,
+        to: usize,
+    },
+}
+
+impl fmt::Display for LayoutChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LayoutChange::Removed { name, var_type } => {
+                write!(f, "`{} {}` was removed", var_type, name)
+            }
+            LayoutChange::Retyped { name, from, to } => {
+                write!(f, "`{}` changed type from `{}` to `{}`", name, from, to)
+            }
+            LayoutChange::Reordered { name, from, to } => {
+                write!(f, "`{}` moved from position {} to {}", name, from, to)
+            }
+        }
+    }
+}
+
+// the changes that break `previous` storage when moving to `next`. Variables appended after the
+// previous ones and fields appended to structs behind mappings are fine, everything else that
+// shifts, disappears or changes type is reported.
+pub fn compare_layouts(previous: &[StateVariable], next: &[StateVariable]) -> Vec<LayoutChange> {
+    let mut changes = Vec::new();
+    for (position, variable) in previous.iter().enumerate() {
+        let Some((new_position, new_variable)) = next
+            .iter()
+            .enumerate()
+            .find(|(_, candidate)| candidate.name == variable.name)
+        else {
+            changes.push(LayoutChange::Removed {
+                name: variable.name.clone(),
+                var_type: variable.var_type.clone(),
+            });
+            continue;
+        };
+        if !extends(&variable.var_type, &new_variable.var_type) {
+            changes.push(LayoutChange::Retyped {
+                name: variable.name.clone(),
+                from: variable.var_type.clone(),
+                to: new_variable.var_type.clone(),
+            });
+        } else if new_position != position {
+            changes.push(LayoutChange::Reordered {
+                name: variable.name.clone(),
+                from: position,
+                to: new_position,
+            });
+        }
+    }
+    changes
+}
+
+// whether `next` is `previous` with at most fields appended to its structs behind mappings
+fn extends(previous: &str, next: &str) -> bool {
+    let mut rest = next;
+    let mut segments = previous.split(OPEN_STRUCT_END).peekable();
+    while let Some(segment) = segments.next() {
+        let Some(after) = rest.strip_prefix(segment) else {
+            return false;
+        };
+        if segments.peek().is_none() {
+            return after.is_empty();
+        }
+        let Some(end) = open_struct_end(after) else {
+            return false;
+        };
+        rest = &after[end..];
+    }
+    rest.is_empty()
+}
+
+// the end of the open struct `fields` are the last part of, skipping any structs among them
+fn open_struct_end(fields: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (index, c) in fields.char_indices() {
+        match c {
+            '.' if depth == 0 && fields[index..].starts_with(OPEN_STRUCT_END) => {
+                return Some(index + OPEN_STRUCT_END.len())
+            }
+            '{' => depth += 1,
+            '}' if depth == 0 => return None,
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+// like the type's Display, but spells out the fields of structs stored inline, since adding one
+// moves every slot after it. A struct reached through a mapping starts at its own hashed slot, so
+// fields may be appended to it, which its closing `..}` marks.
+fn layout_type(var_type: &PrimitiveSolidityType) -> String {
+    match var_type {
+        PrimitiveSolidityType::Struct { name, fields } => format!(
+            "{}{{{}}}",
+            name,
+            fields
+                .iter()
+                .map(|(field, field_type)| format!("{} {}", layout_type(field_type), field))
+                .collect::<Vec<String>>()
+                .join(",")
+        ),
+        PrimitiveSolidityType::Array(inner_type, size) => {
+            format!("{}[{}]", layout_type(inner_type), size)
+        }
+        // elements are stored back to back, so a struct element is inline as well
+        PrimitiveSolidityType::DynamicArray(inner_type) => format!("{}[]", layout_type(inner_type)),
+        PrimitiveSolidityType::Mapping { key, value } => {
+            format!("mapping({}=>{})", layout_type(key), mapped_type(value))
+        }
+        _ => var_type.to_string(),
+    }
+}
+
+// the layout of a mapping value, which isn't inline itself
+fn mapped_type(var_type: &PrimitiveSolidityType) -> String {
+    match var_type {
+        PrimitiveSolidityType::Struct { name, fields } => format!(
+            "{}{{{}{}",
+            name,
+            fields
+                .iter()
+                .map(|(field, field_type)| format!("{} {},", layout_type(field_type), field))
+                .collect::<String>(),
+            OPEN_STRUCT_END
+        ),
+        PrimitiveSolidityType::Mapping { key, value } => {
+            format!("mapping({}=>{})", layout_type(key), mapped_type(value))
+        }
+        _ => layout_type(var_type),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(variables: &[(&str, &str)]) -> Vec<StateVariable> {
+        variables
+            .iter()
+            .map(|(name, var_type)| StateVariable {
+                name: name.to_string(),
+                var_type: var_type.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_compare_layouts() {
+        let previous = layout(&[("owner", "address"), ("count", "uint256"), ("fee", "uint8")]);
+
+        let appended = layout(&[
+            ("owner", "address"),
+            ("count", "uint256"),
+            ("fee", "uint8"),
+            ("paused", "bool"),
+        ]);
+        assert!(compare_layouts(&previous, &appended).is_empty());
+
+        let next = layout(&[
+            ("count", "uint256"),
+            ("owner", "address"),
+            ("fee", "uint16"),
+        ]);
+        assert_eq!(
+            compare_layouts(&previous, &next),
+            vec![
+                LayoutChange::Reordered {
+                    name: "owner".to_string(),
+                    from: 0,
+                    to: 1
+                },
+                LayoutChange::Reordered {
+                    name: "count".to_string(),
+                    from: 1,
+                    to: 0
+                },
+                LayoutChange::Retyped {
+                    name: "fee".to_string(),
+                    from: "uint8".to_string(),
+                    to: "uint16".to_string()
+                },
+            ]
+        );
+
+        let removed = compare_layouts(&previous, &layout(&[("owner", "address")]));
+        assert_eq!(removed.len(), 2);
+        assert_eq!(removed[0].to_string(), "`uint256 count` was removed");
+    }
+
+    #[test]
+    fn test_struct_fields_are_part_of_the_layout() {
+        let order = |fields: &[&str]| PrimitiveSolidityType::Struct {
+            name: "Order".to_string(),
+            fields: fields
+                .iter()
+                .map(|field| (field.to_string(), PrimitiveSolidityType::Uint(256)))
+                .collect(),
+        };
+        assert_eq!(
+            StateVariable::new("order", &order(&["id"])).var_type,
+            "Order{uint256 id}"
+        );
+        let inline = |fields: &[&str]| {
+            vec![
+                StateVariable::new("order", &order(fields)),
+                StateVariable::new("count", &PrimitiveSolidityType::Uint(256)),
+            ]
+        };
+        assert_eq!(
+            compare_layouts(&inline(&["id"]), &inline(&["id", "amount"])).len(),
+            1
+        );
+
+        // a struct behind a mapping keeps its slots when a field is appended
+        let mapped = |fields: &[&str]| {
+            vec![StateVariable::new(
+                "orders",
+                &PrimitiveSolidityType::Mapping {
+                    key: Box::new(PrimitiveSolidityType::Uint(256)),
+                    value: Box::new(order(fields)),
+                },
+            )]
+        };
+        assert_eq!(
+            mapped(&["id"])[0].var_type,
+            "mapping(uint256=>Order{uint256 id,..})"
+        );
+        assert!(compare_layouts(&mapped(&["id"]), &mapped(&["id", "amount"])).is_empty());
+        // but existing fields must keep their place
+        assert_eq!(
+            compare_layouts(&mapped(&["id", "amount"]), &mapped(&["amount", "id"])),
+            vec![LayoutChange::Retyped {
+                name: "orders".to_string(),
+                from: "mapping(uint256=>Order{uint256 id,uint256 amount,..})".to_string(),
+                to: "mapping(uint256=>Order{uint256 amount,uint256 id,..})".to_string(),
+            }]
+        );
+        assert_eq!(
+            compare_layouts(&mapped(&["id", "amount"]), &mapped(&["id"])).len(),
+            1
+        );
+
+        // the same holds for a struct behind a mapping inside one
+        let nested = |fields: &str| {
+            layout(&[(
+                "books",
+                &format!(
+                    "mapping(address=>Book{{mapping(uint256=>Order{{{}..}}) orders,..}})",
+                    fields
+                ),
+            )])
+        };
+        assert!(compare_layouts(
+            &nested("uint256 id,"),
+            &nested("uint256 id,uint256 amount,")
+        )
+        .is_empty());
+        assert_eq!(
+            compare_layouts(
+                &nested("uint256 id,"),
+                &nested("uint256 amount,uint256 id,")
+            )
+            .len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,321 @@
+use crate::error::ParseError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TokenKind {
+    Identifier(String),
+    Number(String),
+    HexNumber(String),
+    StringLiteral(String),
+    HexString(String),
+    Symbol(&'static str),
+    Eof,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+    // position just past the token's last character
+    pub end: Span,
+    // NatSpec (`///` or `/** */`) comments directly preceding the token, one entry per comment
+    pub doc: Vec<String>,
+}
+
+// ordered longest first so that `>>=` wins over `>>` and `>`
+const SYMBOLS: &[&str] = &[
+    ">>>=", "<<=", ">>=", ">>>", "**", "==", "!=", "<=", ">=", "&&", "||", "++", "--", "+=", "-=",
+    "*=", "/=", "%=", "&=", "|=", "^=", "<<", ">>", "=>", "->", ":=", "+", "-", "*", "/", "%", "=",
+    "<", ">", "!", "&", "|", "^", "~", "?", ":", ";", ",", ".", "(", ")", "[", "]", "{", "}",
+];
+
+pub struct Lexer {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+    column: usize,
+}
+
+impl Lexer {
+    pub fn new(source: &str) -> Self {
+        Self {
+            chars: source.chars().collect(),
+            pos: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+
+    pub fn tokenize(mut self) -> Result<Vec<Token>, ParseError> {
+        let mut tokens = Vec::new();
+        loop {
+            let doc = self.skip_trivia()?;
+            let span = self.span();
+            let Some(c) = self.peek(0) else {
+                tokens.push(Token {
+                    kind: TokenKind::Eof,
+                    span,
+                    end: span,
+                    doc,
+                });
+                return Ok(tokens);
+            };
+
+            let kind = if c == '"' || c == '\'' {
+                TokenKind::StringLiteral(self.read_string(c)?)
+            } else if c.is_ascii_digit()
+                || (c == '.' && self.peek(1).is_some_and(|n| n.is_ascii_digit()))
+            {
+                self.read_number()
+            } else if is_identifier_start(c) {
+                let word = self.read_while(is_identifier_part);
+                match (word.as_str(), self.peek(0)) {
+                    ("hex", Some(q @ ('"' | '\''))) => TokenKind::HexString(self.read_string(q)?),
+                    ("unicode", Some(q @ ('"' | '\''))) => {
+                        TokenKind::StringLiteral(self.read_string(q)?)
+                    }
+                    _ => TokenKind::Identifier(word),
+                }
+            } else {
+                TokenKind::Symbol(self.read_symbol()?)
+            };
+            let end = self.span();
+            tokens.push(Token {
+                kind,
+                span,
+                end,
+                doc,
+            });
+        }
+    }
+
+    fn span(&self) -> Span {
+        Span {
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    fn peek(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek(0)?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn read_while(&mut self, pred: impl Fn(char) -> bool) -> String {
+        let mut out = String::new();
+        while let Some(c) = self.peek(0) {
+            if !pred(c) {
+                break;
+            }
+            out.push(c);
+            self.bump();
+        }
+        out
+    }
+
+    fn skip_trivia(&mut self) -> Result<Vec<String>, ParseError> {
+        let mut doc = Vec::new();
+        loop {
+            match (self.peek(0), self.peek(1)) {
+                (Some(c), _) if c.is_whitespace() => {
+                    self.bump();
+                }
+                (Some('/'), Some('/')) => {
+                    let comment = self.read_while(|c| c != '\n');
+                    if let Some(text) = comment.strip_prefix("///") {
+                        doc.push(text.trim().to_string());
+                    }
+                }
+                (Some('/'), Some('*')) => {
+                    let span = self.span();
+                    self.bump();
+                    self.bump();
+                    let mut comment = String::new();
+                    loop {
+                        match (self.peek(0), self.peek(1)) {
+                            (Some('*'), Some('/')) => {
+                                self.bump();
+                                self.bump();
+                                break;
+                            }
+                            (Some(c), _) => {
+                                comment.push(c);
+                                self.bump();
+                            }
+                            (None, _) => return Err(ParseError::UnterminatedComment { span }),
+                        }
+                    }
+                    if let Some(text) = comment.strip_prefix('*') {
+                        // lines of a block usually start with ` * `, which isn't part of the text
+                        let lines = text
+                            .lines()
+                            .map(|line| {
+                                let line = line.trim();
+                                line.strip_prefix('*').unwrap_or(line).trim()
+                            })
+                            .collect::<Vec<&str>>();
+                        doc.push(lines.join("\n").trim().to_string());
+                    }
+                }
+                _ => return Ok(doc),
+            }
+        }
+    }
+
+    fn read_string(&mut self, quote: char) -> Result<String, ParseError> {
+        let span = self.span();
+        self.bump();
+        let mut out = String::new();
+        loop {
+            match self.bump() {
+                Some('\\') => {
+                    out.push('\\');
+                    if let Some(escaped) = self.bump() {
+                        out.push(escaped);
+                    }
+                }
+                Some(c) if c == quote => return Ok(out),
+                Some('\n') | None => return Err(ParseError::UnterminatedString { span }),
+                Some(c) => out.push(c),
+            }
+        }
+    }
+
+    fn read_number(&mut self) -> TokenKind {
+        if self.peek(0) == Some('0') && matches!(self.peek(1), Some('x' | 'X')) {
+            self.bump();
+            self.bump();
+            let digits = self.read_while(|c| c.is_ascii_hexdigit() || c == '_');
+            return TokenKind::HexNumber(format!("0x{}", digits));
+        }
+        let mut number = self.read_while(|c| c.is_ascii_digit() || c == '_');
+        if self.peek(0) == Some('.') && self.peek(1).is_some_and(|c| c.is_ascii_digit()) {
+            self.bump();
+            number.push('.');
+            number.push_str(&self.read_while(|c| c.is_ascii_digit() || c == '_'));
+        }
+        if matches!(self.peek(0), Some('e' | 'E')) {
+            let sign = usize::from(self.peek(1) == Some('-'));
+            if self.peek(1 + sign).is_some_and(|c| c.is_ascii_digit()) {
+                number.push('e');
+                self.bump();
+                if sign == 1 {
+                    number.push('-');
+                    self.bump();
+                }
+                number.push_str(&self.read_while(|c| c.is_ascii_digit()));
+            }
+        }
+        TokenKind::Number(number)
+    }
+
+    fn read_symbol(&mut self) -> Result<&'static str, ParseError> {
+        let matches = |symbol: &&&str| {
+            symbol
+                .chars()
+                .enumerate()
+                .all(|(offset, c)| self.peek(offset) == Some(c))
+        };
+        if let Some(symbol) = SYMBOLS.iter().find(matches) {
+            for _ in 0..symbol.len() {
+                self.bump();
+            }
+            return Ok(symbol);
+        }
+        Err(ParseError::UnexpectedCharacter {
+            character: self.peek(0).unwrap_or_default(),
+            span: self.span(),
+        })
+    }
+}
+
+fn is_identifier_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == '$'
+}
+
+fn is_identifier_part(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '$'
+}
+
+pub fn tokenize(source: &str) -> Result<Vec<Token>, ParseError> {
+    Lexer::new(source).tokenize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(source: &str) -> Vec<TokenKind> {
+        tokenize(source)
+            .unwrap()
+            .into_iter()
+            .map(|token| token.kind)
+            .collect()
+    }
+
+    #[test]
+    fn test_operators_and_comments() {
+        assert_eq!(
+            kinds("a >= b; // c = d\nx += 1 /* == */;"),
+            vec![
+                TokenKind::Identifier("a".to_string()),
+                TokenKind::Symbol(">="),
+                TokenKind::Identifier("b".to_string()),
+                TokenKind::Symbol(";"),
+                TokenKind::Identifier("x".to_string()),
+                TokenKind::Symbol("+="),
+                TokenKind::Number("1".to_string()),
+                TokenKind::Symbol(";"),
+                TokenKind::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_string_literals_keep_symbols() {
+        assert_eq!(
+            kinds(r#"name = "a = b // c";"#),
+            vec![
+                TokenKind::Identifier("name".to_string()),
+                TokenKind::Symbol("="),
+                TokenKind::StringLiteral("a = b // c".to_string()),
+                TokenKind::Symbol(";"),
+                TokenKind::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_natspec_is_attached_to_next_token() {
+        let tokens =
+            tokenize("// plain\n/// @custom:lambda\nfunction f /** @notice x */ () {}").unwrap();
+        assert_eq!(tokens[0].doc, vec!["@custom:lambda".to_string()]);
+        assert_eq!(tokens[2].doc, vec!["@notice x".to_string()]);
+        assert!(tokens[3].doc.is_empty());
+
+        let tokens = tokenize("/**\n * @notice x\n * @custom:lambda\n */\nfunction").unwrap();
+        assert_eq!(tokens[0].doc, vec!["@notice x\n@custom:lambda".to_string()]);
+    }
+
+    #[test]
+    fn test_spans() {
+        let tokens = tokenize("a\n  b").unwrap();
+        assert_eq!(tokens[1].span, Span { line: 2, column: 3 });
+    }
+}
@@ -1,17 +1,75 @@
-use core::hash;
+use indexmap::IndexMap;
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     error::Error,
-    fs::{self, File, OpenOptions},
-    hash::{DefaultHasher, Hash, Hasher},
-    io::{Read, Write},
-    path::Path, time::{SystemTime, UNIX_EPOCH},
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+    process,
+    time::{SystemTime, UNIX_EPOCH},
 };
-mod types;
+pub mod ast;
+pub mod error;
+pub mod lexer;
+pub mod types;
 mod utils;
-use utils::copy_dir_recursively;
+pub mod workspace;
+use utils::{copy_dir_recursively, normalize_source, sha256_hex};
 pub mod compile;
-use crate::types::PrimitiveSolidityType;
+pub mod diagnostics;
+mod events;
+mod guards;
+mod lambda_test;
+pub mod layout;
+use crate::ast::DataLocation;
+use crate::ast::{
+    parse_source, Expression, FunctionDefinition, SourceUnit, Statement, StatementKind,
+    VariableDeclaration,
+};
+use crate::diagnostics::{lambda_source_begin, LAMBDA_SOURCE_END};
+use crate::error::ParseError;
+use crate::events::{
+    event_declaration, inject, key_source, root_keys, state_write_emit, StateWrite,
+    LAMBDA_CALLED_EMIT,
+};
+use crate::guards::{forwarder_checks, parse_guards, pause_switch, Guard};
+use crate::lambda_test::{plan_test, render_test, EntrypointTest};
+use crate::layout::StateVariable;
+use crate::lexer::Span;
+use crate::types::{Parameter, PrimitiveSolidityType};
+use crate::workspace::Workspace;
+// bumping the version gives every lambda a fresh artifact directory
+const GENERATOR_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// templates compiled into the crate for the in-memory API, `write_*` read the on-disk copy instead
+const LAMBDA_TEMPLATE: &str = include_str!("../template/src/Lambda.sol");
+const LAMBDA_APG_TEMPLATE: &str = include_str!("../template/src/LambdaAppGateway.sol");
+const LAMBDA_DEPLOYER_TEMPLATE: &str = include_str!("../template/src/LambdaDeployer.sol");
+
+// NatSpec tag marking a function as a gateway entrypoint
+const LAMBDA_TAG: &str = "@custom:lambda";
+
+// the generated contracts of one lambda, as produced by `ContractGenerator::generate`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GeneratedSources {
+    pub lambda: String,
+    pub app_gateway: String,
+    pub deployer: String,
+    pub test: String,
+}
+
+impl GeneratedSources {
+    // keyed by the path each file takes inside the foundry project
+    pub fn files(&self) -> BTreeMap<String, String> {
+        BTreeMap::from([
+            (LAMBDA_FILE.to_string(), self.lambda.clone()),
+            (LAMBDA_APG_FILE.to_string(), self.app_gateway.clone()),
+            (LAMBDA_DEPLOYER_FILE.to_string(), self.deployer.clone()),
+            (LAMBDA_TEST_FILE.to_string(), self.test.clone()),
+        ])
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum Visbility {
     Public,
@@ -19,12 +77,46 @@ pub enum Visbility {
     Immutable,
 }
 
+// a struct known to the generator, either declared in the lambda source or inferred from usage
+#[derive(Clone, Debug)]
+struct UserStruct {
+    name: String,
+    fields: Vec<(String, PrimitiveSolidityType)>,
+    declared: bool,
+}
+
+// a function exposed through the gateway, with the inputs its forwarder has to accept
+#[derive(Clone, Debug)]
+struct LambdaEntrypoint {
+    name: String,
+    inputs: Vec<Parameter>,
+    // where LambdaCalled is emitted, None for view and pure functions
+    body_start: Option<Span>,
+    guards: Vec<Guard>,
+    // None when the generated test can't call the entrypoint
+    test: Option<EntrypointTest>,
+}
+
+// an enum known to the generator, inferred ones collect members from `Status.Filled` uses
+#[derive(Clone, Debug)]
+struct UserEnum {
+    name: String,
+    members: Vec<String>,
+    declared: bool,
+}
+
 #[derive(Clone, Debug)]
 pub struct ContractGenerator {
-    global_states: HashMap<String, PrimitiveSolidityType>,
+    // kept in first-use order, which becomes the storage order of the generated contract
+    global_states: IndexMap<String, PrimitiveSolidityType>,
     gloabl_visibilities: HashMap<String, Visbility>,
     local_state: HashMap<String, PrimitiveSolidityType>,
-    lambda_func_inputs: HashMap<String, PrimitiveSolidityType>,
+    lambda_entrypoints: Vec<LambdaEntrypoint>,
+    structs: Vec<UserStruct>,
+    enums: Vec<UserEnum>,
+    state_writes: Vec<StateWrite>,
+    // position of the statement being processed, attached to inference errors
+    span: Span,
 }
 
 impl Default for ContractGenerator {
@@ -33,235 +125,1021 @@ impl Default for ContractGenerator {
         local_state.insert("msg.sender".to_string(), PrimitiveSolidityType::Address);
         local_state.insert("msg.value".to_string(), PrimitiveSolidityType::Uint(256));
         local_state.insert("msg.data".to_string(), PrimitiveSolidityType::Bytes);
+        local_state.insert("tx.origin".to_string(), PrimitiveSolidityType::Address);
+        local_state.insert(
+            "block.timestamp".to_string(),
+            PrimitiveSolidityType::Uint(256),
+        );
+        local_state.insert("block.number".to_string(), PrimitiveSolidityType::Uint(256));
+        local_state.insert(
+            "block.chainid".to_string(),
+            PrimitiveSolidityType::Uint(256),
+        );
         Self {
-            global_states: HashMap::new(),
+            global_states: IndexMap::new(),
             local_state,
-            lambda_func_inputs: HashMap::new(),
+            lambda_entrypoints: Vec::new(),
             gloabl_visibilities: HashMap::new(),
+            structs: Vec::new(),
+            enums: Vec::new(),
+            state_writes: Vec::new(),
+            span: Span::default(),
         }
     }
 }
 
 impl ContractGenerator {
-    pub fn process_lambda(&mut self, function: String) -> Result<(), Box<dyn Error>> {
-        for line in function.lines() {
-            let line = line.split("//").next().unwrap().trim();
-            if line.is_empty() || line.starts_with("//") {
-                continue;
-            }
+    pub fn process_lambda(&mut self, function: String) -> Result<(), ParseError> {
+        let source = parse_source(&function)?;
+        self.register_enums(&source);
+        self.register_structs(&source)?;
+        for definition in source.functions() {
+            self.process_function(definition)?;
+        }
+        self.refresh_struct_fields();
+        Ok(())
+    }
 
-            if is_lambda(line) {
-                self.process_lambda_declaration(line)?;
-                continue;
+    fn register_structs(&mut self, source: &SourceUnit) -> Result<(), ParseError> {
+        for definition in source.structs() {
+            let mut fields = Vec::new();
+            for member in &definition.members {
+                let name = member.name.clone().unwrap_or_default();
+                fields.push((name, self.resolve_type(member)?));
             }
+            self.structs
+                .retain(|existing| existing.name != definition.name);
+            self.structs.push(UserStruct {
+                name: definition.name.clone(),
+                fields,
+                declared: true,
+            });
+        }
+        Ok(())
+    }
 
-            if line.contains("=") {
-                self.process_assignment(line)?;
-            }
+    fn register_enums(&mut self, source: &SourceUnit) {
+        for definition in source.enums() {
+            self.enums
+                .retain(|existing| existing.name != definition.name);
+            self.enums.push(UserEnum {
+                name: definition.name.clone(),
+                members: definition.members.clone(),
+                declared: true,
+            });
         }
+    }
+
+    fn enum_type(&self, name: &str) -> Option<PrimitiveSolidityType> {
+        self.enums
+            .iter()
+            .find(|user_enum| user_enum.name == name)
+            .map(|user_enum| PrimitiveSolidityType::Enum {
+                name: user_enum.name.clone(),
+                members: user_enum.members.clone(),
+            })
+    }
+
+    // inferred enums grow a member on first use, declared ones must already have it
+    fn add_enum_member(&mut self, name: &str, member: &str) -> Result<(), ParseError> {
+        let span = self.span;
+        let Some(user_enum) = self.enums.iter_mut().find(|e| e.name == name) else {
+            self.enums.push(UserEnum {
+                name: name.to_string(),
+                members: vec![member.to_string()],
+                declared: false,
+            });
+            return Ok(());
+        };
+        if user_enum.members.iter().any(|existing| existing == member) {
+            return Ok(());
+        }
+        if user_enum.declared {
+            return Err(ParseError::UnknownMember {
+                type_name: name.to_string(),
+                member: member.to_string(),
+                span,
+            });
+        }
+        user_enum.members.push(member.to_string());
         Ok(())
     }
 
-    fn process_lambda_declaration(&mut self, line: &str) -> Result<(), Box<dyn Error>> {
-        let func_args = PrimitiveSolidityType::parse_function_declaration(line);
-        self.lambda_func_inputs = func_args;
+    // user defined type names used in declarations resolve to structs first, then enums
+    fn user_type(&self, name: &str) -> Option<PrimitiveSolidityType> {
+        self.struct_type(name).or_else(|| self.enum_type(name))
+    }
+
+    fn struct_type(&self, name: &str) -> Option<PrimitiveSolidityType> {
+        self.structs
+            .iter()
+            .find(|user_struct| user_struct.name == name)
+            .map(|user_struct| PrimitiveSolidityType::Struct {
+                name: user_struct.name.clone(),
+                fields: user_struct.fields.clone(),
+            })
+    }
+
+    fn struct_field_type(&self, name: &str, member: &str) -> Option<PrimitiveSolidityType> {
+        let user_struct = self
+            .structs
+            .iter()
+            .find(|user_struct| user_struct.name == name)?;
+        user_struct
+            .fields
+            .iter()
+            .find(|(field, _)| field == member)
+            .map(|(_, field_type)| field_type.clone())
+    }
+
+    // inferred structs grow a member on first write, declared ones must already have it
+    fn add_struct_member(
+        &mut self,
+        name: &str,
+        member: &str,
+        member_type: PrimitiveSolidityType,
+    ) -> Result<(), ParseError> {
+        let span = self.span;
+        let Some(user_struct) = self.structs.iter_mut().find(|s| s.name == name) else {
+            return Err(ParseError::UnsupportedType {
+                type_name: name.to_string(),
+                span,
+            });
+        };
+        if user_struct.fields.iter().any(|(field, _)| field == member) {
+            return Ok(());
+        }
+        if user_struct.declared {
+            return Err(ParseError::UnknownMember {
+                type_name: name.to_string(),
+                member: member.to_string(),
+                span,
+            });
+        }
+        user_struct.fields.push((member.to_string(), member_type));
         Ok(())
     }
 
-    fn process_assignment(&mut self, line: &str) -> Result<(), Box<dyn Error>> {
-        let words: Vec<&str> = line.split_whitespace().collect();
+    // Order({maker: a, amount: b}) or Order(maker, amount) for a struct the source never declares
+    fn infer_struct_construction(
+        &mut self,
+        name: &str,
+        arguments: &[Expression],
+        names: &[String],
+    ) -> Result<(), ParseError> {
+        let mut fields = Vec::new();
+        for (index, argument) in arguments.iter().enumerate() {
+            let field = match names.get(index) {
+                Some(field) => field.clone(),
+                None => last_member_name(argument)
+                    .ok_or_else(|| self.cannot_infer(&format!("field {} of `{}`", index, name)))?
+                    .to_string(),
+            };
+            fields.push((field, self.infer_type_from_value(argument)?));
+        }
+        self.structs.push(UserStruct {
+            name: name.to_string(),
+            fields,
+            declared: false,
+        });
+        Ok(())
+    }
 
-        // Check if this is a new variable declaration
-        if let Some(primitive_type) = PrimitiveSolidityType::from_string(words[0]) {
-            if words.len() < 2 {
-                return Err("Invalid variable declaration".into());
+    // struct types stored in state carry a snapshot of their fields, bring them up to date
+    fn refresh_struct_fields(&mut self) {
+        let mut global_states = std::mem::take(&mut self.global_states);
+        for var_type in global_states.values_mut() {
+            self.refresh_type(var_type);
+        }
+        self.global_states = global_states;
+    }
+
+    fn refresh_type(&self, var_type: &mut PrimitiveSolidityType) {
+        match var_type {
+            PrimitiveSolidityType::Struct { name, .. }
+            | PrimitiveSolidityType::Enum { name, .. } => {
+                if let Some(current) = self.user_type(name) {
+                    *var_type = current;
+                }
             }
-            let name = words[1].trim_end_matches(';').to_string();
-            self.local_state.insert(name, primitive_type);
-            return Ok(());
+            PrimitiveSolidityType::Array(inner, _) | PrimitiveSolidityType::DynamicArray(inner) => {
+                self.refresh_type(inner)
+            }
+            PrimitiveSolidityType::Mapping { value, .. } => self.refresh_type(value),
+            _ => {}
         }
+    }
 
-        // Handle existing variable assignment
-        let (var_name, var_type) = self.parse_assignment(line)?;
-        self.global_states.insert(var_name, var_type);
+    fn process_function(&mut self, function: &FunctionDefinition) -> Result<(), ParseError> {
+        // locals and parameters only live for the duration of the function
+        let outer_scope = self.local_state.clone();
 
+        if is_lambda(function) {
+            self.process_lambda_declaration(function)?;
+        }
+        for parameter in &function.parameters {
+            self.declare_local(parameter)?;
+        }
+        for named_return in function.returns.iter().filter(|ret| ret.name.is_some()) {
+            self.declare_local(named_return)?;
+        }
+        if let Some(body) = &function.body {
+            self.process_block(body)?;
+        }
+
+        self.local_state = outer_scope;
         Ok(())
     }
 
-    fn parse_assignment(
+    fn process_lambda_declaration(
+        &mut self,
+        function: &FunctionDefinition,
+    ) -> Result<(), ParseError> {
+        let mut func_args = Vec::new();
+        for parameter in &function.parameters {
+            let Some(name) = &parameter.name else {
+                continue;
+            };
+            func_args.push(Parameter {
+                name: name.clone(),
+                var_type: self.resolve_type(parameter)?,
+                location: parameter.location.clone(),
+            });
+        }
+        // overloads are separate entrypoints, only a redefinition replaces one
+        self.lambda_entrypoints.retain(|entrypoint| {
+            entrypoint.name != function.name
+                || !entrypoint
+                    .inputs
+                    .iter()
+                    .map(|input| &input.var_type)
+                    .eq(func_args.iter().map(|input| &input.var_type))
+        });
+        let guards = parse_guards(&function.doc, &func_args, function.span)?;
+        let test = plan_test(function, &func_args);
+        let read_only = function
+            .attributes
+            .iter()
+            .any(|attribute| attribute == "view" || attribute == "pure");
+        self.lambda_entrypoints.push(LambdaEntrypoint {
+            name: function.name.clone(),
+            inputs: func_args,
+            body_start: function.body_start.filter(|_| !read_only),
+            guards,
+            test,
+        });
+        Ok(())
+    }
+
+    fn declare_local(&mut self, declaration: &VariableDeclaration) -> Result<(), ParseError> {
+        if let Some(name) = &declaration.name {
+            let local_type = self.resolve_type(declaration)?;
+            self.local_state.insert(name.clone(), local_type);
+        }
+        Ok(())
+    }
+
+    fn resolve_type(
         &self,
-        line: &str,
-    ) -> Result<(String, PrimitiveSolidityType), Box<dyn Error>> {
-        let parts: Vec<&str> = line.split('=').collect();
-        if parts.len() != 2 {
-            return Err("Invalid assignment".into());
+        declaration: &VariableDeclaration,
+    ) -> Result<PrimitiveSolidityType, ParseError> {
+        PrimitiveSolidityType::from_type_name_with(&declaration.type_name, &|name| {
+            self.user_type(name)
+        })
+        .ok_or_else(|| ParseError::UnsupportedType {
+            type_name: ast::type_name_to_string(&declaration.type_name),
+            span: declaration.span,
+        })
+    }
+
+    fn process_block(&mut self, statements: &[Statement]) -> Result<(), ParseError> {
+        let outer_scope = self.local_state.clone();
+        for statement in statements {
+            self.process_statement(statement)?;
+            self.record_state_write(statement, true);
         }
+        self.local_state = outer_scope;
+        Ok(())
+    }
 
-        let left_side = parts[0].trim();
-        let right_side = parts[1].trim().trim_end_matches(';');
+    // the body of an if or a loop, which unlike a block may be a single unbraced statement
+    fn process_body(&mut self, body: &Statement) -> Result<(), ParseError> {
+        let outer_scope = self.local_state.clone();
+        self.process_statement(body)?;
+        self.record_state_write(body, false);
+        self.local_state = outer_scope;
+        Ok(())
+    }
 
-        // Handle mapping assignment: map[key] = value
-        if let Some((base_name, key_str)) = extract_mapping_parts(left_side) {
-            if key_str.len() > 1 || key_str.is_empty() {
-                return Ok((base_name.to_string(), PrimitiveSolidityType::Nested));
+    // storage writes are followed by an emit of the variable's `<Name>Updated` event. A write
+    // inside a return statement can't be followed by anything and goes without an event.
+    fn record_state_write(&mut self, statement: &Statement, braced: bool) {
+        let StatementKind::Expression(expression) = &statement.kind else {
+            return;
+        };
+        let mut targets = Vec::new();
+        write_targets(expression, &mut targets);
+        for target in targets {
+            // variables are only known once inferred, which may happen in a later function, so
+            // writes to anything that isn't local are kept and matched against the state on render
+            let Some(name) = root_identifier(target) else {
+                continue;
+            };
+            if self.is_local(name) {
+                continue;
+            }
+            let Some(keys) = root_keys(target).and_then(|keys| {
+                keys.into_iter()
+                    .map(key_source)
+                    .collect::<Option<Vec<String>>>()
+            }) else {
+                continue;
+            };
+            let duplicate = self.state_writes.iter().any(|write| {
+                write.start == statement.span && write.name == name && write.keys == keys
+            });
+            if !duplicate {
+                self.state_writes.push(StateWrite {
+                    name: name.to_string(),
+                    keys,
+                    start: statement.span,
+                    end: statement.end,
+                    braced,
+                });
             }
-            let key_type = self.infer_type_from_value(key_str[0])?;
-            let value_type = self.infer_type_from_value(right_side)?;
+        }
+    }
 
-            return Ok((
-                base_name.to_string(),
-                PrimitiveSolidityType::Mapping {
-                    key: Box::new(key_type),
-                    value: Box::new(value_type),
-                },
-            ));
+    fn process_statement(&mut self, statement: &Statement) -> Result<(), ParseError> {
+        self.span = statement.span;
+        match &statement.kind {
+            StatementKind::Block(statements) | StatementKind::Unchecked(statements) => {
+                self.process_block(statements)?;
+            }
+            StatementKind::VariableDeclaration { declaration, value } => {
+                if let Some(value) = value {
+                    self.process_expression(value)?;
+                }
+                self.declare_local(declaration)?;
+            }
+            StatementKind::TupleDeclaration {
+                declarations,
+                value,
+            } => {
+                self.process_expression(value)?;
+                for declaration in declarations.iter().flatten() {
+                    self.declare_local(declaration)?;
+                }
+            }
+            StatementKind::Expression(expression) | StatementKind::Return(Some(expression)) => {
+                self.process_expression(expression)?;
+            }
+            StatementKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.process_expression(condition)?;
+                self.process_body(then_branch)?;
+                if let Some(else_branch) = else_branch {
+                    self.process_body(else_branch)?;
+                }
+            }
+            StatementKind::For {
+                init,
+                condition,
+                update,
+                body,
+            } => {
+                let outer_scope = self.local_state.clone();
+                if let Some(init) = init {
+                    self.process_statement(init)?;
+                }
+                if let Some(condition) = condition {
+                    self.process_expression(condition)?;
+                }
+                if let Some(update) = update {
+                    self.process_expression(update)?;
+                }
+                self.process_body(body)?;
+                self.local_state = outer_scope;
+            }
+            StatementKind::While { condition, body }
+            | StatementKind::DoWhile { body, condition } => {
+                self.process_expression(condition)?;
+                self.process_body(body)?;
+            }
+            StatementKind::Return(None)
+            | StatementKind::Emit(_)
+            | StatementKind::Revert(_)
+            | StatementKind::Continue
+            | StatementKind::Break
+            | StatementKind::Assembly => {}
         }
+        Ok(())
+    }
 
-        // Handle array access or array assignment
-        if let Some((base_name, index)) = extract_array_parts(left_side) {
-            let element_type = if let Some(var_type) = self.get_variable_type(right_side) {
-                var_type
-            } else {
-                self.infer_type_from_value(right_side)?
-            };
+    fn process_expression(&mut self, expression: &Expression) -> Result<(), ParseError> {
+        match expression {
+            Expression::Assignment { left, right, .. } => {
+                self.process_expression(right)?;
+                self.process_assignment(left, right)?;
+            }
+            // counters default to uint256 unless we already know better
+            Expression::Unary {
+                operator: "++" | "--",
+                operand,
+                ..
+            } if !self.is_known_variable(operand) => {
+                self.process_assignment(operand, &Expression::Number("1".to_string()))?;
+            }
+            Expression::Unary { operand, .. } => self.process_expression(operand)?,
+            Expression::Binary { left, right, .. } => {
+                self.process_expression(left)?;
+                self.process_expression(right)?;
+            }
+            Expression::Conditional {
+                condition,
+                then_expression,
+                else_expression,
+            } => {
+                self.process_expression(condition)?;
+                self.process_expression(then_expression)?;
+                self.process_expression(else_expression)?;
+            }
+            Expression::Call {
+                callee,
+                arguments,
+                names,
+            } => {
+                // `Library.function(...)` is a call, not an enum value
+                match callee.as_ref() {
+                    Expression::MemberAccess { expression, .. } => {
+                        self.process_expression(expression)?
+                    }
+                    _ => self.process_expression(callee)?,
+                }
+                for argument in arguments {
+                    self.process_expression(argument)?;
+                }
+                if let Expression::MemberAccess { expression, member } = callee.as_ref() {
+                    if member == "push" || member == "pop" {
+                        self.process_array_write(expression, arguments)?;
+                    }
+                }
+                // an unknown capitalised callee with named or several arguments builds a struct
+                if let Expression::Identifier(name) = callee.as_ref() {
+                    if is_type_name(name)
+                        && self.user_type(name).is_none()
+                        && (!names.is_empty() || arguments.len() > 1)
+                    {
+                        self.infer_struct_construction(name, arguments, names)?;
+                    }
+                }
+            }
+            Expression::Index { base, index } => {
+                self.process_expression(base)?;
+                if let Some(index) = index {
+                    self.process_expression(index)?;
+                }
+            }
+            // Status.Filled on a capitalised name that is not a variable or struct is an enum value
+            Expression::MemberAccess { expression, member } => match expression.as_ref() {
+                Expression::Identifier(name)
+                    if is_type_name(name)
+                        && !self.is_known_variable(expression)
+                        && self.struct_type(name).is_none() =>
+                {
+                    self.add_enum_member(name, member)?
+                }
+                _ => self.process_expression(expression)?,
+            },
+            Expression::Tuple(elements) => {
+                for element in elements.iter().flatten() {
+                    self.process_expression(element)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
 
-            // If index is numeric and <= 32, create static array
-            if let Ok(size) = index.parse::<usize>() {
-                if size <= 32 {
-                    return Ok((
-                        base_name.to_string(),
-                        PrimitiveSolidityType::Array(Box::new(element_type), size),
-                    ));
+    fn process_assignment(
+        &mut self,
+        left: &Expression,
+        right: &Expression,
+    ) -> Result<(), ParseError> {
+        // `(x, total) = (1, 2)` assigns each component, types come from the matching value
+        if let Expression::Tuple(targets) = left {
+            let values = match right {
+                Expression::Tuple(values) if values.len() == targets.len() => Some(values),
+                _ => None,
+            };
+            for (index, target) in targets.iter().enumerate() {
+                let Some(target) = target else {
+                    continue;
+                };
+                match values.and_then(|values| values[index].as_ref()) {
+                    Some(value) => self.process_assignment(target, value)?,
+                    None if self.is_known_variable(target) => {}
+                    None => return Err(self.cannot_infer("component of a tuple assignment")),
                 }
             }
-            return Ok((
-                base_name.to_string(),
-                PrimitiveSolidityType::DynamicArray(Box::new(element_type)),
-            ));
+            return Ok(());
+        }
+
+        // writes to locals or parameters never touch storage
+        let Some(root) = root_identifier(left) else {
+            return Ok(());
+        };
+        if self.is_local(root) {
+            return Ok(());
         }
 
-        // Handle simple assignment
-        let assigned_type = if let Some(var_type) = self.get_variable_type(right_side) {
-            var_type
-        } else {
-            self.infer_type_from_value(right_side)?
+        let (var_name, var_type) = self.parse_assignment(left, right)?;
+        self.global_states.insert(var_name, var_type);
+        Ok(())
+    }
+
+    // `queue.push(x)` on storage we don't know yet declares a dynamic array of x
+    fn process_array_write(
+        &mut self,
+        array: &Expression,
+        arguments: &[Expression],
+    ) -> Result<(), ParseError> {
+        let Some(root) = root_identifier(array) else {
+            return Ok(());
+        };
+        if self.is_local(root) || self.infer_type_from_value(array).is_ok() {
+            return Ok(());
+        }
+        let Some(element) = arguments.first() else {
+            return Err(self.cannot_infer(&format!("element type of `{}`", root)));
         };
-        Ok((left_side.to_string(), assigned_type))
+        let element_type = self.infer_type_from_value(element)?;
+        let (var_name, var_type) = self.resolve_assignment_target(
+            array,
+            PrimitiveSolidityType::DynamicArray(Box::new(element_type)),
+        )?;
+        self.global_states.insert(var_name, var_type);
+        Ok(())
+    }
+
+    fn parse_assignment(
+        &mut self,
+        left: &Expression,
+        right: &Expression,
+    ) -> Result<(String, PrimitiveSolidityType), ParseError> {
+        let value_type = self.infer_type_from_value(right)?;
+        self.resolve_assignment_target(left, value_type)
+    }
+
+    // walks the target outwards, wrapping the value type in one mapping per key:
+    // allowances[owner][spender] = true -> mapping(address=>mapping(address=>bool))
+    fn resolve_assignment_target(
+        &mut self,
+        target: &Expression,
+        value_type: PrimitiveSolidityType,
+    ) -> Result<(String, PrimitiveSolidityType), ParseError> {
+        match target {
+            // Handle simple assignment
+            Expression::Identifier(name) => Ok((name.clone(), value_type)),
+
+            // Handle mapping assignment: map[key] = value
+            Expression::Index {
+                base,
+                index: Some(key),
+            } => {
+                let key_type = self.infer_type_from_value(key)?;
+                self.resolve_assignment_target(
+                    base,
+                    PrimitiveSolidityType::Mapping {
+                        key: Box::new(key_type),
+                        value: Box::new(value_type),
+                    },
+                )
+            }
+
+            // Handle struct field assignment: positions[user].amount = value
+            Expression::MemberAccess {
+                expression: base,
+                member,
+            } => {
+                let struct_name = match self.infer_type_from_value(base) {
+                    Ok(PrimitiveSolidityType::Struct { name, .. }) => name,
+                    Ok(other) => {
+                        return Err(
+                            self.cannot_infer(&format!("member `{}` of `{}`", member, other))
+                        )
+                    }
+                    // first write through an unknown variable, name the struct after it
+                    Err(_) => {
+                        let root = root_identifier(base)
+                            .ok_or(ParseError::InvalidAssignmentTarget { span: self.span })?;
+                        let name = capitalize(root);
+                        if self.struct_type(&name).is_none() {
+                            self.structs.push(UserStruct {
+                                name: name.clone(),
+                                fields: Vec::new(),
+                                declared: false,
+                            });
+                        }
+                        name
+                    }
+                };
+                self.add_struct_member(&struct_name, member, value_type)?;
+                let struct_type =
+                    self.struct_type(&struct_name)
+                        .ok_or(ParseError::UnsupportedType {
+                            type_name: struct_name,
+                            span: self.span,
+                        })?;
+                self.resolve_assignment_target(base, struct_type)
+            }
+            _ => Err(ParseError::InvalidAssignmentTarget { span: self.span }),
+        }
+    }
+
+    fn is_local(&self, var_name: &str) -> bool {
+        self.local_state.contains_key(var_name)
+    }
+
+    fn is_known_variable(&self, expression: &Expression) -> bool {
+        root_identifier(expression)
+            .is_some_and(|root| self.is_local(root) || self.global_states.contains_key(root))
     }
 
     fn get_variable_type(&self, var_name: &str) -> Option<PrimitiveSolidityType> {
         self.local_state
             .get(var_name)
-            .or_else(|| self.lambda_func_inputs.get(var_name))
             .or_else(|| self.global_states.get(var_name))
             .cloned()
     }
 
-    fn infer_type_from_value(&self, value: &str) -> Result<PrimitiveSolidityType, Box<dyn Error>> {
-        // Boolean values
-        if value == "true" || value == "false" {
-            return Ok(PrimitiveSolidityType::Bool);
-        }
-
-        // Address values
-        if value.starts_with("0x") && value.len() == 42 {
-            return Ok(PrimitiveSolidityType::Address);
-        }
+    fn infer_type_from_value(
+        &self,
+        value: &Expression,
+    ) -> Result<PrimitiveSolidityType, ParseError> {
+        match value {
+            // Boolean values
+            Expression::Bool(_) => Ok(PrimitiveSolidityType::Bool),
+
+            // Address and bytes values
+            Expression::HexNumber(hex) => {
+                if hex.len() == 42 {
+                    return Ok(PrimitiveSolidityType::Address);
+                }
+                let byte_length = (hex.len() - 2) / 2;
+                if (1..=32).contains(&byte_length) {
+                    return Ok(PrimitiveSolidityType::FixedBytes(byte_length as u8));
+                }
+                Ok(PrimitiveSolidityType::Bytes)
+            }
+            Expression::HexString(_) => Ok(PrimitiveSolidityType::Bytes),
+
+            // String values
+            Expression::StringLiteral(_) => Ok(PrimitiveSolidityType::String),
+
+            // Numeric values
+            Expression::Number(_) => Ok(PrimitiveSolidityType::Uint(256)),
+            Expression::Unary {
+                operator: "-",
+                operand,
+                ..
+            } => match operand.as_ref() {
+                Expression::Number(_) => Ok(PrimitiveSolidityType::Int(256)),
+                operand => self.infer_type_from_value(operand),
+            },
+            Expression::Unary { operator: "!", .. } => Ok(PrimitiveSolidityType::Bool),
+            Expression::Unary { operand, .. } => self.infer_type_from_value(operand),
+
+            // Array literals
+            Expression::ArrayLiteral(elements) => {
+                let first_element = elements
+                    .first()
+                    .ok_or_else(|| self.cannot_infer("empty array"))?;
+                let element_type = self.infer_type_from_value(first_element)?;
+                if elements.len() <= 32 {
+                    return Ok(PrimitiveSolidityType::Array(
+                        Box::new(element_type),
+                        elements.len(),
+                    ));
+                }
+                Ok(PrimitiveSolidityType::DynamicArray(Box::new(element_type)))
+            }
 
-        // String values
-        if value.starts_with("\"") && value.ends_with("\"") {
-            return Ok(PrimitiveSolidityType::String);
-        }
+            Expression::Binary {
+                operator,
+                left,
+                right,
+                ..
+            } => match *operator {
+                "==" | "!=" | "<" | ">" | "<=" | ">=" | "&&" | "||" => {
+                    Ok(PrimitiveSolidityType::Bool)
+                }
+                _ => self
+                    .infer_type_from_value(left)
+                    .or_else(|_| self.infer_type_from_value(right)),
+            },
+            Expression::Conditional {
+                then_expression,
+                else_expression,
+                ..
+            } => self
+                .infer_type_from_value(then_expression)
+                .or_else(|_| self.infer_type_from_value(else_expression)),
+
+            Expression::Index { base, .. } => match self.infer_type_from_value(base)? {
+                PrimitiveSolidityType::Mapping { value, .. } => Ok(*value),
+                PrimitiveSolidityType::Array(inner, _)
+                | PrimitiveSolidityType::DynamicArray(inner) => Ok(*inner),
+                PrimitiveSolidityType::Bytes | PrimitiveSolidityType::FixedBytes(_) => {
+                    Ok(PrimitiveSolidityType::FixedBytes(1))
+                }
+                other => Err(self.cannot_infer(&format!("index into `{}`", other))),
+            },
+            Expression::MemberAccess { member, .. } if member == "length" => {
+                Ok(PrimitiveSolidityType::Uint(256))
+            }
+            Expression::MemberAccess { member, .. } if member == "balance" => {
+                Ok(PrimitiveSolidityType::Uint(256))
+            }
+            Expression::MemberAccess { expression, member } => {
+                let path = member_path(value);
+                if let Some(var_type) = path.as_deref().and_then(|p| self.get_variable_type(p)) {
+                    return Ok(var_type);
+                }
+                if let Expression::Identifier(name) = expression.as_ref() {
+                    if let Some(enum_type) = self.enum_type(name) {
+                        return Ok(enum_type);
+                    }
+                }
+                match self.infer_type_from_value(expression) {
+                    Ok(PrimitiveSolidityType::Struct { name, .. }) => self
+                        .struct_field_type(&name, member)
+                        .ok_or(ParseError::UnknownMember {
+                            type_name: name,
+                            member: member.clone(),
+                            span: self.span,
+                        }),
+                    Ok(_) => Err(self.cannot_infer(&format!("member `{}`", member))),
+                    Err(err) => Err(path.map_or(err, |name| ParseError::UnknownVariable {
+                        name,
+                        span: self.span,
+                    })),
+                }
+            }
 
-        // Bytes values
-        if value.starts_with("0x") {
-            let byte_length = (value.len() - 2) / 2;
-            if byte_length <= 32 {
-                return Ok(PrimitiveSolidityType::FixedBytes(byte_length as u8));
+            Expression::Call { callee, .. } => self.infer_call_type(callee),
+            Expression::New(type_name) => PrimitiveSolidityType::from_type_name(type_name).ok_or(
+                ParseError::UnsupportedType {
+                    type_name: ast::type_name_to_string(type_name),
+                    span: self.span,
+                },
+            ),
+
+            Expression::Identifier(name) if name == "this" => Ok(PrimitiveSolidityType::Address),
+            Expression::Identifier(name) => {
+                self.get_variable_type(name)
+                    .ok_or_else(|| ParseError::UnknownVariable {
+                        name: name.clone(),
+                        span: self.span,
+                    })
             }
-            return Ok(PrimitiveSolidityType::Bytes);
+
+            Expression::Tuple(_) => Err(self.cannot_infer("tuple")),
+            Expression::Assignment { .. } => Err(self.cannot_infer("nested assignment")),
         }
+    }
 
-        // Array literals
-        if value.starts_with('[') && value.ends_with(']') {
-            let inner = value[1..value.len() - 1].trim();
-            if let Some(first_element) = inner.split(',').next() {
-                let element_type = self.infer_type_from_value(first_element.trim())?;
-                let size = inner.split(',').count();
-                if size <= 32 {
-                    return Ok(PrimitiveSolidityType::Array(Box::new(element_type), size));
-                } else {
-                    return Ok(PrimitiveSolidityType::DynamicArray(Box::new(element_type)));
+    fn infer_call_type(&self, callee: &Expression) -> Result<PrimitiveSolidityType, ParseError> {
+        let name = match callee {
+            Expression::Identifier(name) => name.as_str(),
+            Expression::MemberAccess { expression, member } => {
+                if matches!(expression.as_ref(), Expression::Identifier(base) if base == "abi")
+                    && member.starts_with("encode")
+                {
+                    return Ok(PrimitiveSolidityType::Bytes);
                 }
+                return Err(self.cannot_infer(&format!("call to `{}`", member)));
             }
-        }
+            _ => return Err(self.cannot_infer("call")),
+        };
 
-        // Numeric values
-        if let Ok(num) = value.parse::<i64>() {
-            if num < 0 {
-                return Ok(PrimitiveSolidityType::Int(256));
-            }
-            return Ok(PrimitiveSolidityType::Uint(256));
+        // type conversions: uint128(x), address(0), bytes32(y)
+        if let Some(primitive_type) = PrimitiveSolidityType::from_string(name) {
+            return Ok(primitive_type);
+        }
+        // struct construction Order(maker, amount) or enum conversion Status(1)
+        if let Some(user_type) = self.user_type(name) {
+            return Ok(user_type);
         }
+        match name {
+            "payable" => Ok(PrimitiveSolidityType::AddressPayable),
+            "keccak256" | "sha256" | "blockhash" => Ok(PrimitiveSolidityType::FixedBytes(32)),
+            "ripemd160" => Ok(PrimitiveSolidityType::FixedBytes(20)),
+            "ecrecover" => Ok(PrimitiveSolidityType::Address),
+            "gasleft" | "addmod" | "mulmod" => Ok(PrimitiveSolidityType::Uint(256)),
+            _ => Err(self.cannot_infer(&format!("call to `{}`", name))),
+        }
+    }
 
-        return Ok(self.get_variable_type(value).unwrap());
+    fn cannot_infer(&self, expression: &str) -> ParseError {
+        ParseError::CannotInferType {
+            expression: expression.to_string(),
+            span: self.span,
+        }
     }
 
     pub fn write_lambda(
         &self,
+        workspace: &Workspace,
         function: String,
-        lambda_name: String,
     ) -> Result<(String, String), Box<dyn Error>> {
-        // Generate timestamp
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)?
-            .as_secs();
-        
-        // Create hash from lambda name and timestamp
-        let mut hasher = DefaultHasher::new();
-        lambda_name.hash(&mut hasher);
-        timestamp.hash(&mut hasher);
-        let hash = hasher.finish();
-        
-        // Create unique filename with timestamp
-        let filename = format!("lambda_{}_{}", timestamp, hash);
-        let final_path = format!("output/{}", filename);
-        
-        // Copy template and process file
-        copy_dir_recursively(Path::new("template"), Path::new(&final_path))?;
-        let file_path = lambda_file(&final_path);
-        let mut file = File::open(file_path.clone())?;
-        let mut content = String::new();
-        file.read_to_string(&mut content)?;
-        
-        // Replace placeholders
-        let comment = "//lambda_here";
-        let new_content = content.replace(comment, &function);
-        let state_comment = "//states_here";
         let state_content = self.global_state_string();
-        let new_content = new_content.replace(state_comment, &state_content);
-        
-        // Write to file
-        fs::write(&file_path, new_content)?;
-        
+
+        // the directory is named after the content, so a re-submission finds its earlier output
+        let filename = format!("lambda_{}", artifact_id(&function));
+        let final_path = workspace.lambda_dir(&filename)?;
+        if final_path.exists() {
+            return Ok((state_content, filename));
+        }
+
+        // build in a scratch directory and move it into place once complete, so a concurrent
+        // submission of the same source never sees a half written directory
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+        let scratch_path =
+            workspace
+                .output_dir()
+                .join(format!(".{}_{}_{}", filename, process::id(), nanos));
+        copy_dir_recursively(&workspace.template_dir(), &scratch_path)?;
+        let written = self
+            .fill_lambda_template(&scratch_path, &function)
+            .and_then(|_| self.fill_apg_template(&scratch_path))
+            .and_then(|_| self.write_test(&scratch_path))
+            .and_then(|_| fs::rename(&scratch_path, &final_path).map_err(Into::into));
+        if let Err(e) = written {
+            let _ = fs::remove_dir_all(&scratch_path);
+            // another request may have published the same content in the meantime
+            if !final_path.exists() {
+                return Err(e);
+            }
+        }
+
         // Return both state content and filename
         Ok((state_content, filename))
     }
 
-    pub fn write_apg(&self, dir: String) -> Result<(), Box<dyn Error>> {
-        let final_path = format!("output/{}", dir);
-        let file_path = lambda_apg_file(&final_path);
-        let mut file = File::open(file_path.clone())?;
+    fn fill_lambda_template(
+        &self,
+        final_path: &Path,
+        function: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let file_path = lambda_file(final_path);
+        let mut file = File::open(&file_path)?;
         let mut content = String::new();
         file.read_to_string(&mut content)?;
-        let comment = "//lambda_here";
 
-        let new_content = content.replace(comment, &self.lambda_apg_content());
+        // Write to file
+        fs::write(&file_path, self.render_lambda(&content, function))?;
+        Ok(())
+    }
+
+    fn write_test(&self, final_path: &Path) -> Result<(), Box<dyn Error>> {
+        let file_path = lambda_test_file(final_path);
+        if let Some(test_dir) = file_path.parent() {
+            fs::create_dir_all(test_dir)?;
+        }
+        fs::write(file_path, self.render_test())?;
+        Ok(())
+    }
+
+    fn fill_apg_template(&self, final_path: &Path) -> Result<(), Box<dyn Error>> {
+        let file_path = lambda_apg_file(final_path);
+        let mut file = File::open(&file_path)?;
+        let mut content = String::new();
+        file.read_to_string(&mut content)?;
 
-        fs::write(file_path, new_content)?;
+        fs::write(file_path, self.render_apg(&content))?;
         Ok(())
     }
 
+    // same output as write_lambda, without touching the filesystem
+    pub fn generate(&self, function: &str) -> GeneratedSources {
+        GeneratedSources {
+            lambda: self.render_lambda(LAMBDA_TEMPLATE, function),
+            app_gateway: self.render_apg(LAMBDA_APG_TEMPLATE),
+            deployer: LAMBDA_DEPLOYER_TEMPLATE.to_string(),
+            test: self.render_test(),
+        }
+    }
+
+    fn render_lambda(&self, template: &str, function: &str) -> String {
+        // Replace placeholders
+        let comment = "//lambda_here";
+        // the begin marker records the injections, so diagnostics can be mapped back
+        let injections = self.event_injections();
+        let new_content = template.replace(
+            comment,
+            &format!(
+                "{}\n{}\n{}",
+                lambda_source_begin(&injections),
+                inject(function, injections),
+                LAMBDA_SOURCE_END
+            ),
+        );
+        let state_comment = "//states_here";
+        new_content.replace(
+            state_comment,
+            &format!(
+                "{}{}{}",
+                self.type_definitions_string(),
+                self.global_state_string(),
+                self.state_events_string()
+            ),
+        )
+    }
+
+    // the LambdaCalled emit opening each entrypoint and the emits following storage writes
+    fn event_injections(&self) -> Vec<(Span, String)> {
+        let mut injections = self
+            .lambda_entrypoints
+            .iter()
+            .filter_map(|entrypoint| entrypoint.body_start)
+            .map(|start| (start, format!(" {}", LAMBDA_CALLED_EMIT)))
+            .collect::<Vec<(Span, String)>>();
+        // a statement may write several variables, its emits share one pair of braces
+        let mut statements: Vec<(&StateWrite, String)> = Vec::new();
+        for write in &self.state_writes {
+            let Some(emit) = self
+                .global_states
+                .get(&write.name)
+                .and_then(|var_type| state_write_emit(write, var_type))
+            else {
+                continue;
+            };
+            match statements
+                .iter_mut()
+                .find(|(statement, _)| statement.start == write.start)
+            {
+                Some((_, emits)) => emits.push_str(&format!(" {}", emit)),
+                None => statements.push((write, format!(" {}", emit))),
+            }
+        }
+        for (write, emits) in statements {
+            if write.braced {
+                injections.push((write.end, emits));
+            } else {
+                injections.push((write.start, "{ ".to_string()));
+                injections.push((write.end, format!("{} }}", emits)));
+            }
+        }
+        injections
+    }
+
+    // one `<Name>Updated` event per storage variable the lambda writes to
+    pub fn state_events_string(&self) -> String {
+        self.global_states
+            .iter()
+            .filter(|(var_name, var_type)| {
+                self.state_writes.iter().any(|write| {
+                    &write.name == *var_name && state_write_emit(write, var_type).is_some()
+                })
+            })
+            .map(|(var_name, var_type)| event_declaration(var_name, var_type))
+            .collect()
+    }
+
+    // a foundry test calling each entrypoint and checking the state it leaves behind
+    fn render_test(&self) -> String {
+        let tests = self.lambda_entrypoints.iter().filter_map(|entrypoint| {
+            let test = entrypoint.test.as_ref()?;
+            Some((
+                entrypoint.name.as_str(),
+                test,
+                entrypoint.body_start.is_some(),
+            ))
+        });
+        render_test(tests, |var_name| {
+            match self.gloabl_visibilities.get(var_name) {
+                Some(Visbility::Public) | None => self.global_states.get(var_name),
+                Some(_) => None,
+            }
+        })
+    }
+
+    // guards are checked by the forwarder of the entrypoint declaring them, not a shared modifier
+    fn render_apg(&self, template: &str) -> String {
+        let comment = "//lambda_here";
+        template.replace(comment, &self.lambda_apg_content())
+    }
+
+    fn guards(&self) -> impl Iterator<Item = &Guard> {
+        self.lambda_entrypoints
+            .iter()
+            .flat_map(|entrypoint| &entrypoint.guards)
+    }
+
     pub fn global_state_string(&self) -> String {
         let mut state = String::new();
         for (var_name, var_type) in &self.global_states {
@@ -271,103 +1149,245 @@ impl ContractGenerator {
                 Some(Visbility::Immutable) => "immutable",
                 None => "public",
             };
-            self.gloabl_visibilities.get(var_name);
-            state.push_str(&format!(
-                "{} {} {};\n",
-                var_type.to_string(),
-                visibility,
-                var_name
-            ));
+            state.push_str(&format!("{} {} {};\n", var_type, visibility, var_name));
         }
         state
     }
 
+    // storage variables in slot order, for comparing against a previous version of the lambda
+    pub fn state_layout(&self) -> Vec<StateVariable> {
+        self.global_states
+            .iter()
+            .map(|(var_name, var_type)| StateVariable::new(var_name, var_type))
+            .collect()
+    }
+
+    // declared types are already part of the lambda source, only inferred ones need emitting
+    pub fn type_definitions_string(&self) -> String {
+        let mut definitions = String::new();
+        for user_enum in self.enums.iter().filter(|e| !e.declared) {
+            definitions.push_str(&format!(
+                "enum {} {{ {} }}\n",
+                user_enum.name,
+                user_enum.members.join(", ")
+            ));
+        }
+        for user_struct in self.structs.iter().filter(|s| !s.declared) {
+            definitions.push_str(&format!("struct {} {{\n", user_struct.name));
+            for (field, field_type) in &user_struct.fields {
+                definitions.push_str(&format!("    {} {};\n", field_type, field));
+            }
+            definitions.push_str("}\n");
+        }
+        definitions
+    }
+
     pub fn set_visibility(&mut self, variable: &str, visibility: Visbility) {
         self.gloabl_visibilities
             .insert(variable.to_string(), visibility);
     }
 
     fn lambda_apg_content(&self) -> String {
-        let function_arguments = self
-            .lambda_func_inputs
-            .iter()
-            .map(|(name, var_type)| format!("{} {}", var_type.to_string(), name))
-            .collect::<Vec<String>>()
-            .join(", ");
-
-        let variable_names = self
-            .lambda_func_inputs
-            .iter()
-            .map(|(name, _)| name.to_string())
+        pause_switch(self.guards())
+            .into_iter()
+            .chain(self.lambda_entrypoints.iter().map(lambda_forwarder))
             .collect::<Vec<String>>()
-            .join(", ");
-        let function_arguments = if function_arguments.is_empty() {
-            "".to_string()
-        } else {
-            format!(", {}", function_arguments)
-        };
-        let content = format!(
-            "function callLambda( 
-            address lambdaAddress
-            {} 
-        ) public preExecutionChecks async {{ 
-            Lambda lambda = Lambda(lambdaAddress); 
-            lambda.lambda({}); 
-        }}",
-            function_arguments, variable_names
-        );
-        content
+            .join("\n\n    ")
     }
 
     pub fn clear(&mut self) {
         self.global_states.clear();
         self.gloabl_visibilities.clear();
         self.local_state.clear();
-        self.lambda_func_inputs.clear();
+        self.lambda_entrypoints.clear();
+        self.structs.clear();
+        self.enums.clear();
+        self.state_writes.clear();
     }
 }
 
-fn lambda_file(dir: &str) -> String {
-    format!("{}/{}", dir, "src/Lambda.sol")
+// stable id of a lambda's generated artifacts: SHA-256 of the normalized source and generator version
+pub fn artifact_id(source: &str) -> String {
+    sha256_hex(&[GENERATOR_VERSION, &normalize_source(source)])
+}
+
+pub(crate) const LAMBDA_FILE: &str = "src/Lambda.sol";
+const LAMBDA_APG_FILE: &str = "src/LambdaAppGateway.sol";
+const LAMBDA_DEPLOYER_FILE: &str = "src/LambdaDeployer.sol";
+pub(crate) const LAMBDA_TEST_FILE: &str = "test/LambdaTest.sol";
+
+fn lambda_file(dir: &Path) -> PathBuf {
+    dir.join(LAMBDA_FILE)
+}
+fn lambda_apg_file(dir: &Path) -> PathBuf {
+    dir.join(LAMBDA_APG_FILE)
 }
-fn lambda_apg_file(dir: &str) -> String {
-    format!("{}/{}", dir, "src/LambdaAppGateway.sol")
+
+fn lambda_test_file(dir: &Path) -> PathBuf {
+    dir.join(LAMBDA_TEST_FILE)
 }
 
-fn lambda_test_file(dir: &str) -> String {
-    format!("{}/{}", dir, "test/LambdaTest.sol")
+// entrypoints are the function named `lambda` plus any function annotated `/// @custom:lambda`
+fn is_lambda(function: &FunctionDefinition) -> bool {
+    function.name == "lambda"
+        || function
+            .doc
+            .iter()
+            .any(|comment| comment.split_whitespace().any(|tag| tag == LAMBDA_TAG))
 }
 
-fn is_lambda(line: &str) -> bool {
-    let words: Vec<&str> = line.split_whitespace().collect();
-    if words.len() < 2 {
-        return false;
+// the forwarder is public, so reference types must live in memory or calldata
+fn gateway_parameter(parameter: &Parameter) -> String {
+    let location = match &parameter.location {
+        Some(DataLocation::Calldata) => Some(DataLocation::Calldata),
+        _ if parameter.var_type.is_reference_type() => Some(DataLocation::Memory),
+        _ => None,
+    };
+    match location {
+        Some(location) => format!(
+            "{} {} {}",
+            gateway_type(&parameter.var_type),
+            location,
+            parameter.name
+        ),
+        None => format!("{} {}", gateway_type(&parameter.var_type), parameter.name),
     }
-    let val = words[1].split('(').next().unwrap();
-    words.len() >= 2 && words[0] == "function" && val == "lambda"
 }
 
-fn extract_mapping_parts(expr: &str) -> Option<(&str, Vec<&str>)> {
-    if let Some(first_bracket_start) = expr.find('[') {
-        if let Some(last_bracket_end) = expr.rfind(']') {
-            let base_name = expr[..first_bracket_start].trim();
-            let key = expr[first_bracket_start + 1..last_bracket_end].trim();
-            let keys = key.split("][").collect::<Vec<&str>>();
-            return Some((base_name, keys));
+// structs and enums declared in the lambda source live inside the Lambda contract
+fn gateway_type(var_type: &PrimitiveSolidityType) -> String {
+    match var_type {
+        PrimitiveSolidityType::Struct { name, .. } | PrimitiveSolidityType::Enum { name, .. } => {
+            format!("Lambda.{}", name)
         }
+        PrimitiveSolidityType::Array(inner_type, size) => {
+            format!("{}[{}]", gateway_type(inner_type), size)
+        }
+        PrimitiveSolidityType::DynamicArray(inner_type) => {
+            format!("{}[]", gateway_type(inner_type))
+        }
+        _ => var_type.to_string(),
+    }
+}
+
+// gateway function forwarding to one entrypoint: `deposit` is reached through `callDeposit`
+fn lambda_forwarder(entrypoint: &LambdaEntrypoint) -> String {
+    let function_arguments = entrypoint
+        .inputs
+        .iter()
+        .map(gateway_parameter)
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    let variable_names = entrypoint
+        .inputs
+        .iter()
+        .map(|parameter| parameter.name.as_str())
+        .collect::<Vec<&str>>()
+        .join(", ");
+    let function_arguments = if function_arguments.is_empty() {
+        "".to_string()
+    } else {
+        format!(", {}", function_arguments)
+    };
+    let checks = forwarder_checks(&entrypoint.guards)
+        .into_iter()
+        .map(|check| format!("{}\n            ", check))
+        .collect::<String>();
+    format!(
+        "function call{}( 
+            address lambdaAddress
+            {} 
+        ) public async {{ 
+            {}Lambda lambda = Lambda(lambdaAddress); 
+            lambda.{}({}); 
+        }}",
+        capitalize(&entrypoint.name),
+        function_arguments,
+        checks,
+        entrypoint.name,
+        variable_names
+    )
+}
+
+// the variable an assignment target is rooted at: `a` for `a[k].b[i]`
+fn root_identifier(expression: &Expression) -> Option<&str> {
+    match expression {
+        Expression::Identifier(name) => Some(name),
+        Expression::Index { base, .. } => root_identifier(base),
+        Expression::MemberAccess { expression, .. } => root_identifier(expression),
+        _ => None,
     }
-    None
 }
 
-fn extract_array_parts(expr: &str) -> Option<(&str, &str)> {
-    if let Some(bracket_start) = expr.find('[') {
-        if let Some(bracket_end) = expr.find(']') {
-            let base_name = expr[..bracket_start].trim();
-            let index = expr[bracket_start + 1..bracket_end].trim();
-            return Some((base_name, index));
+// the storage locations `expression` writes to: both branches of `f ? a = 1 : b = 2`, `b` as
+// well as `a` in `a = b = 1`, the components of `(a, b) = ...` and `a` in `a.push(1)`
+pub(crate) fn write_targets<'a>(expression: &'a Expression, targets: &mut Vec<&'a Expression>) {
+    match expression {
+        Expression::Assignment { left, right, .. } => {
+            match left.as_ref() {
+                Expression::Tuple(components) => targets.extend(components.iter().flatten()),
+                _ => targets.push(left),
+            }
+            write_targets(right, targets);
         }
+        // `queue.push(x)` and `queue.pop()` change the array itself
+        Expression::Call { callee, .. } => {
+            if let Expression::MemberAccess { expression, member } = callee.as_ref() {
+                if member == "push" || member == "pop" {
+                    targets.push(expression);
+                }
+            }
+        }
+        Expression::Unary {
+            operator: "++" | "--" | "delete",
+            operand,
+            ..
+        } => targets.push(operand),
+        Expression::Conditional {
+            then_expression,
+            else_expression,
+            ..
+        } => {
+            write_targets(then_expression, targets);
+            write_targets(else_expression, targets);
+        }
+        _ => {}
+    }
+}
+
+// the name a field takes when a struct is built from positional arguments: `amount` for `order.amount`
+fn last_member_name(expression: &Expression) -> Option<&str> {
+    match expression {
+        Expression::Identifier(name) => Some(name),
+        Expression::MemberAccess { member, .. } => Some(member),
+        _ => None,
+    }
+}
+
+// Solidity type names are capitalised by convention, which is what tells `Status.Filled` from `order.amount`
+fn is_type_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_uppercase())
+}
+
+// `positions[user].amount = x` without a declaration stores a `Positions` struct
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+        None => String::new(),
+    }
+}
+
+// dotted path of a member access such as `msg.sender`
+fn member_path(expression: &Expression) -> Option<String> {
+    match expression {
+        Expression::Identifier(name) => Some(name.clone()),
+        Expression::MemberAccess { expression, member } => {
+            Some(format!("{}.{}", member_path(expression)?, member))
+        }
+        _ => None,
     }
-    None
 }
 
 #[cfg(test)]
@@ -408,28 +1428,33 @@ mod tests {
 
         generator.process_lambda(function)?;
 
-
         // Check mapping types
-        if let Some(PrimitiveSolidityType::Mapping { key, value }) =
+        let Some(PrimitiveSolidityType::Mapping { key, value }) =
             generator.global_states.get("balances")
-        {
-            assert!(matches!(**key, PrimitiveSolidityType::Address));
-            assert!(matches!(**value, PrimitiveSolidityType::Uint(256)));
-        }
+        else {
+            panic!("balances should be a mapping");
+        };
+        assert!(matches!(**key, PrimitiveSolidityType::Address));
+        assert!(matches!(**value, PrimitiveSolidityType::Uint(256)));
 
-        if let Some(PrimitiveSolidityType::Mapping { key, value }) =
+        let Some(PrimitiveSolidityType::Mapping { key, value }) =
             generator.global_states.get("approvals")
-        {
-            assert!(matches!(**key, PrimitiveSolidityType::Address));
-            assert!(matches!(**value, PrimitiveSolidityType::Bool));
-        }
+        else {
+            panic!("approvals should be a mapping");
+        };
+        assert!(matches!(**key, PrimitiveSolidityType::Address));
+        assert!(matches!(**value, PrimitiveSolidityType::Bool));
 
-        if let Some(PrimitiveSolidityType::Mapping { key, value }) =
+        let Some(PrimitiveSolidityType::Mapping { key, value }) =
             generator.global_states.get("owners")
-        {
-            assert!(matches!(**key, PrimitiveSolidityType::Uint(256)));
-            assert!(matches!(**value, PrimitiveSolidityType::Address));
-        }
+        else {
+            panic!("owners should be a mapping");
+        };
+        assert!(matches!(**key, PrimitiveSolidityType::Uint(256)));
+        assert!(matches!(**value, PrimitiveSolidityType::Address));
+
+        // locally declared arrays are not contract state
+        assert!(!generator.global_states.contains_key("values"));
 
         Ok(())
     }
@@ -441,17 +1466,212 @@ mod tests {
         let function = r#"
             function lambda(address owner, address spender) returns (bool) {
                 allowances[owner][spender] = true;    // mapping(address => mapping(address => bool))
+                approvals[owner][spender][1] = spender;
                 return true;
             }
-        "#.to_string();
+        "#
+        .to_string();
+
+        generator.process_lambda(function)?;
+
+        assert_eq!(
+            generator
+                .global_states
+                .get("allowances")
+                .unwrap()
+                .to_string(),
+            "mapping(address=>mapping(address=>bool))"
+        );
+        assert_eq!(
+            generator
+                .global_states
+                .get("approvals")
+                .unwrap()
+                .to_string(),
+            "mapping(address=>mapping(address=>mapping(uint256=>address)))"
+        );
+        assert!(generator
+            .global_state_string()
+            .contains("mapping(address=>mapping(address=>bool)) public allowances;"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_struct_assignments() -> Result<(), Box<dyn Error>> {
+        let mut generator = ContractGenerator::default();
+
+        let function = r#"
+            struct Order {
+                address maker;
+                uint256 amount;
+            }
+
+            function lambda(uint256 id, address user, uint256 x) {
+                orders[id] = Order(user, x);
+                positions[user].amount = x;
+                positions[user].owner = msg.sender;
+                totals[user] = positions[user].amount + orders[id].amount;
+            }
+        "#
+        .to_string();
 
         generator.process_lambda(function)?;
 
         assert_eq!(
-            &PrimitiveSolidityType::Nested,
-            generator.global_states.get("allowances").unwrap()
+            generator.global_states.get("orders").unwrap().to_string(),
+            "mapping(uint256=>Order)"
+        );
+        assert_eq!(
+            generator.global_states.get("positions").unwrap(),
+            &PrimitiveSolidityType::Mapping {
+                key: Box::new(PrimitiveSolidityType::Address),
+                value: Box::new(PrimitiveSolidityType::Struct {
+                    name: "Positions".to_string(),
+                    fields: vec![
+                        ("amount".to_string(), PrimitiveSolidityType::Uint(256)),
+                        ("owner".to_string(), PrimitiveSolidityType::Address),
+                    ],
+                }),
+            }
+        );
+        assert_eq!(
+            generator.global_states.get("totals").unwrap().to_string(),
+            "mapping(address=>uint256)"
+        );
+        // Order is declared in the lambda source, so only Positions is emitted
+        assert_eq!(
+            generator.type_definitions_string(),
+            "struct Positions {\n    uint256 amount;\n    address owner;\n}\n"
         );
 
+        let err = ContractGenerator::default()
+            .process_lambda(
+                "struct Order { uint256 amount; }\nfunction lambda(uint256 id) {\n  orders[id] = Order(1);\n  orders[id].price = 1;\n}"
+                    .to_string(),
+            )
+            .unwrap_err();
+        assert_eq!(err.kind(), "unknown_member");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_enum_assignments() -> Result<(), Box<dyn Error>> {
+        let mut generator = ContractGenerator::default();
+
+        let function = r#"
+            enum Side { Buy, Sell }
+
+            function lambda(uint256 id) {
+                status[id] = Status.Open;
+                if (status[id] == Status.Open) {
+                    status[id] = Status.Filled;
+                }
+                sides[id] = Side.Sell;
+                lastSide = Side(1);
+            }
+        "#
+        .to_string();
+
+        generator.process_lambda(function)?;
+
+        assert_eq!(
+            generator.global_states.get("status").unwrap(),
+            &PrimitiveSolidityType::Mapping {
+                key: Box::new(PrimitiveSolidityType::Uint(256)),
+                value: Box::new(PrimitiveSolidityType::Enum {
+                    name: "Status".to_string(),
+                    members: vec!["Open".to_string(), "Filled".to_string()],
+                }),
+            }
+        );
+        assert_eq!(
+            generator.global_states.get("sides").unwrap().to_string(),
+            "mapping(uint256=>Side)"
+        );
+        assert_eq!(
+            generator.global_states.get("lastSide").unwrap().to_string(),
+            "Side"
+        );
+        // Side is declared in the lambda source, so only Status is emitted
+        assert_eq!(
+            generator.type_definitions_string(),
+            "enum Status { Open, Filled }\n"
+        );
+
+        let err = ContractGenerator::default()
+            .process_lambda(
+                "enum Side { Buy, Sell }\nfunction lambda() {\n  side = Side.Hold;\n}".to_string(),
+            )
+            .unwrap_err();
+        assert_eq!(err.kind(), "unknown_member");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_multiple_entrypoints() -> Result<(), Box<dyn Error>> {
+        let mut generator = ContractGenerator::default();
+
+        let function = r#"
+            /// @notice add funds
+            /// @custom:lambda
+            function deposit(uint256 amount) external {
+                balances[msg.sender] += amount;
+            }
+
+            /** @custom:lambda */
+            function withdraw(uint256 amount) external {
+                balances[msg.sender] -= amount;
+            }
+
+            function helper(uint256 amount) internal {
+                total = amount;
+            }
+        "#
+        .to_string();
+
+        generator.process_lambda(function)?;
+
+        let names = generator
+            .lambda_entrypoints
+            .iter()
+            .map(|entrypoint| entrypoint.name.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(names, vec!["deposit", "withdraw"]);
+
+        let content = generator.lambda_apg_content();
+        assert!(content.contains("function callDeposit("));
+        assert!(content.contains("lambda.deposit(amount);"));
+        assert!(content.contains("function callWithdraw("));
+        assert!(content.contains("lambda.withdraw(amount);"));
+        assert!(!content.contains("callHelper"));
+        // parameters are scoped to their function, not stored as state
+        assert!(!generator.global_states.contains_key("amount"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_forwarder_keeps_declared_order() -> Result<(), Box<dyn Error>> {
+        let function = r#"
+            function lambda(uint256 zeta, address alpha, string calldata memo, bytes data, bool mid) {
+                last[alpha] = zeta;
+            }
+        "#;
+
+        // the same source has to produce the same gateway every time
+        for _ in 0..8 {
+            let mut generator = ContractGenerator::default();
+            generator.process_lambda(function.to_string())?;
+            let content = generator.lambda_apg_content();
+            assert!(content.contains(
+                ", uint256 zeta, address alpha, string calldata memo, bytes memory data, bool mid"
+            ));
+            assert!(content.contains("lambda.lambda(zeta, alpha, memo, data, mid);"));
+        }
+
         Ok(())
     }
 
@@ -488,9 +1708,483 @@ mod tests {
         .to_string();
 
         generator.process_lambda(function.clone()).unwrap();
-        generator
-            .write_lambda(function, "test_lambda".to_string())
+        let workspace = Workspace::new(env!("CARGO_MANIFEST_DIR"));
+        let (_, dirname) = generator
+            .write_lambda(&workspace, function.clone())
+            .unwrap();
+        assert_eq!(dirname, format!("lambda_{}", artifact_id(&function)));
+        let lambda_dir = workspace.lambda_dir(&dirname).unwrap();
+        assert!(lambda_test_file(&lambda_dir).exists());
+        // the gateway is filled before the directory is published
+        assert!(!fs::read_to_string(lambda_apg_file(&lambda_dir))
+            .unwrap()
+            .contains("//lambda_here"));
+
+        // a re-submission with different line endings reuses the directory untouched
+        let lambda_sol = fs::read_to_string(lambda_file(&lambda_dir)).unwrap();
+        let (_, resubmitted) = generator
+            .write_lambda(&workspace, function.replace('\n', "\r\n"))
             .unwrap();
-        generator.write_apg("test_lambda".to_string()).unwrap();
+        assert_eq!(resubmitted, dirname);
+        assert_eq!(
+            fs::read_to_string(lambda_file(&lambda_dir)).unwrap(),
+            lambda_sol
+        );
+        fs::remove_dir_all(lambda_dir).unwrap();
+    }
+
+    #[test]
+    fn test_generate_in_memory() -> Result<(), Box<dyn Error>> {
+        let mut generator = ContractGenerator::default();
+        let function =
+            "function lambda(address user, uint256 amount) {\n    balances[user] = amount;\n}";
+        generator.process_lambda(function.to_string())?;
+
+        let sources = generator.generate(function);
+        assert!(sources
+            .lambda
+            .contains("mapping(address=>uint256) public balances;"));
+        // the generator injects the events indexers rely on
+        assert!(sources.lambda.contains(
+            "function lambda(address user, uint256 amount) { emit LambdaCalled(msg.sender, msg.data);\n    balances[user] = amount; emit BalancesUpdated(user, balances[user]);\n}"
+        ));
+        assert!(!sources.lambda.contains("//states_here"));
+        assert!(sources.app_gateway.contains("lambda.lambda(user, amount);"));
+        assert!(sources.deployer.contains("contract LambdaDeployer"));
+
+        let files = generator.generate(function).files();
+        assert_eq!(
+            files.keys().collect::<Vec<&String>>(),
+            vec![
+                "src/Lambda.sol",
+                "src/LambdaAppGateway.sol",
+                "src/LambdaDeployer.sol",
+                "test/LambdaTest.sol"
+            ]
+        );
+        assert_eq!(files["src/Lambda.sol"], sources.lambda);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_state_change_events() -> Result<(), Box<dyn Error>> {
+        let mut generator = ContractGenerator::default();
+        let function = "function lambda(address user, uint256 id) external {\n\
+            if (id > 0) count++;\n\
+            orders[user][id].amount = id;\n\
+            allowed[user] = true;\n\
+            nonces[keccak256(abi.encode(id))] = id;\n\
+            }\n\
+            /// @custom:lambda\n\
+            function peek() external view returns (uint256) { return count; }";
+        generator.process_lambda(function.to_string())?;
+        let lambda = generator.generate(function).lambda;
+
+        assert!(lambda.contains("event CountUpdated(uint256 value);"));
+        assert!(lambda.contains(
+            "event OrdersUpdated(address indexed key0, uint256 indexed key1, Orders value);"
+        ));
+        assert!(lambda.contains("event AllowedUpdated(address indexed key0, bool value);"));
+        // an unbraced body gets braces so the emit stays conditional
+        assert!(lambda.contains("if (id > 0) { count++; emit CountUpdated(count); }"));
+        assert!(lambda.contains(
+            "orders[user][id].amount = id; emit OrdersUpdated(user, id, orders[user][id]);"
+        ));
+        assert!(lambda.contains("allowed[user] = true; emit AllowedUpdated(user, allowed[user]);"));
+        // a call can't be evaluated twice, so that write goes without an event
+        assert!(lambda.contains("nonces[keccak256(abi.encode(id))] = id;\n"));
+        assert!(!lambda.contains("NoncesUpdated"));
+        assert_eq!(
+            lambda
+                .matches("emit LambdaCalled(msg.sender, msg.data);")
+                .count(),
+            1
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_events_of_overloads_and_nested_writes() -> Result<(), Box<dyn Error>> {
+        let mut generator = ContractGenerator::default();
+        let function = "function lambda(address user) external { delete balances[user]; }\n\
+            function lambda(address user, uint256 amount) external returns (uint256) {\n\
+            balances[user] = amount;\n\
+            amount > 1 ? flags[user] = true : flags[user] = false;\n\
+            return total = amount;\n\
+            }";
+        generator.process_lambda(function.to_string())?;
+        let sources = generator.generate(function);
+        let lambda = sources.lambda;
+
+        // both overloads are entrypoints with their own forwarder and test
+        assert_eq!(
+            lambda
+                .matches("emit LambdaCalled(msg.sender, msg.data);")
+                .count(),
+            2
+        );
+        assert_eq!(
+            sources.app_gateway.matches("function callLambda(").count(),
+            2
+        );
+        assert!(sources.test.contains("function test_lambda_1() public {"));
+        assert!(sources.test.contains(
+            "abi.encodeWithSignature(\"lambda(address,uint256)\", address(0xBEEF), uint256(1))"
+        ));
+        // the delete comes before the assignment that makes `balances` known
+        assert!(
+            lambda.contains("delete balances[user]; emit BalancesUpdated(user, balances[user]); }")
+        );
+        // writes in both branches of a conditional are announced once
+        assert!(lambda.contains(
+            "amount > 1 ? flags[user] = true : flags[user] = false; emit FlagsUpdated(user, flags[user]);\n"
+        ));
+        // nothing can follow a return, that write goes without an event
+        assert!(lambda.contains("return total = amount;\n"));
+        assert!(!lambda.contains("TotalUpdated"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_tuple_and_array_writes() -> Result<(), Box<dyn Error>> {
+        let mut generator = ContractGenerator::default();
+        let function = "function lambda(address user) external {\n\
+            uint256 x;\n\
+            (x, total) = (1, 2);\n\
+            queue.push(user);\n\
+            }";
+        generator.process_lambda(function.to_string())?;
+        let lambda = generator.generate(function).lambda;
+
+        assert!(lambda.contains("uint256 public total;"));
+        assert!(lambda.contains("address[] public queue;"));
+        assert!(!lambda.contains("public x;"));
+        assert!(lambda.contains("(x, total) = (1, 2); emit TotalUpdated(total);"));
+        assert!(lambda.contains("queue.push(user); emit QueueUpdated(queue);"));
+
+        // nothing to take the element type from
+        let mut generator = ContractGenerator::default();
+        let function = "function lambda() external { queue.pop(); }";
+        assert!(generator.process_lambda(function.to_string()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_gateway_guards() -> Result<(), Box<dyn Error>> {
+        let mut generator = ContractGenerator::default();
+        let function = "/// @custom:guard pausable\n\
+            /// @custom:guard bound amount 1 1000\n\
+            function lambda(uint256 amount) external { total += amount; }\n\
+            /// @custom:lambda\n\
+            /// @custom:guard min-fee 5000\n\
+            function reset() external { total = 0; }";
+        generator.process_lambda(function.to_string())?;
+        let app_gateway = generator.generate(function).app_gateway;
+
+        assert!(!app_gateway.contains("preExecutionChecks"));
+        assert!(app_gateway.contains("function setPaused(bool paused_) external {"));
+        assert!(app_gateway.contains(
+            "require(!paused, \"gateway paused\");\n            require(amount >= 1 && amount <= 1000, \"amount out of bounds\");\n            Lambda lambda = Lambda(lambdaAddress); \n            lambda.lambda(amount);"
+        ));
+        // the fee minimum of `reset` doesn't gate `lambda`, nor does its pause switch `reset`
+        assert!(app_gateway.contains(
+            "require(feesData.maxFees >= 5000, \"fees below minimum\");\n            Lambda lambda = Lambda(lambdaAddress); \n            lambda.reset();"
+        ));
+        assert_eq!(app_gateway.matches("fees below minimum").count(), 1);
+        assert_eq!(app_gateway.matches("gateway paused").count(), 1);
+        assert_eq!(app_gateway.matches("out of bounds").count(), 1);
+
+        let mut generator = ContractGenerator::default();
+        let error = generator
+            .process_lambda(
+                "/// @custom:guard bound user 0 1\nfunction lambda(address user) {}".to_string(),
+            )
+            .unwrap_err();
+        assert_eq!(error.kind(), "invalid_guard");
+
+        // guards in a NatSpec block count as much as `///` ones
+        let mut generator = ContractGenerator::default();
+        let function = "function lambda(uint256 amount) external { total += amount; }\n\
+            /**\n\
+             * @custom:lambda\n\
+             * @custom:guard allow 0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed\n\
+             */\n\
+            function withdraw(uint256 amount) external { total -= amount; }";
+        generator.process_lambda(function.to_string())?;
+        let app_gateway = generator.generate(function).app_gateway;
+        assert!(app_gateway.contains(
+            "require(msg.sender == 0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed, \"caller not allowed\");\n            Lambda lambda = Lambda(lambdaAddress); \n            lambda.withdraw(amount);"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_gateway_user_type_parameters() -> Result<(), Box<dyn Error>> {
+        let mut generator = ContractGenerator::default();
+        let function = "struct Order { address maker; uint256 amount; }\n\
+            enum Side { Buy, Sell }\n\
+            function lambda(Order memory order, Order[] calldata batch, Side side) external {\n\
+            orders[order.maker] = order;\n\
+            count = batch.length;\n\
+            sides[order.maker] = side;\n\
+            }";
+        generator.process_lambda(function.to_string())?;
+        let app_gateway = generator.generate(function).app_gateway;
+
+        // the gateway only sees the types through the Lambda contract
+        assert!(app_gateway.contains(
+            "Lambda.Order memory order, Lambda.Order[] calldata batch, Lambda.Side side"
+        ));
+        assert!(app_gateway.contains("lambda.lambda(order, batch, side);"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_generated_foundry_test() -> Result<(), Box<dyn Error>> {
+        let mut generator = ContractGenerator::default();
+        let function = "function lambda(address user, uint256 amount, bool flag) external {\n\
+            admin = user;\n\
+            balances[user] = amount;\n\
+            total = amount;\n\
+            if (flag) { total += 1; }\n\
+            label = \"set\";\n\
+            }\n\
+            /// @custom:lambda\n\
+            function fill(uint256[] calldata ids) external { count = ids.length; }";
+        generator.process_lambda(function.to_string())?;
+        let test = generator.generate(function).test;
+
+        assert!(test.contains("lambda.setSocket(address(this));"));
+        assert!(test.contains(
+            "emit LambdaCalled(address(this), abi.encodeCall(Lambda.lambda, (address(0xBEEF), uint256(1), true)));\n        lambda.lambda(address(0xBEEF), uint256(1), true);"
+        ));
+        assert!(test.contains("assertEq(address(lambda.admin()), address(0xBEEF));"));
+        assert!(test.contains("assertEq(uint256(lambda.balances(address(0xBEEF))), uint256(1));"));
+        assert!(test.contains("assertEq(lambda.label(), \"set\");"));
+        // `total` depends on the branch taken, and there's no sample for a uint256[]
+        assert!(!test.contains("lambda.total()"));
+        assert!(!test.contains("test_fill"));
+
+        // once the lambda assigns to a parameter, it no longer holds the sample argument
+        let mut generator = ContractGenerator::default();
+        let function = "function lambda(uint256 amount) external {\n\
+            before = amount;\n\
+            amount = amount * 2;\n\
+            total = amount;\n\
+            }";
+        generator.process_lambda(function.to_string())?;
+        let test = generator.generate(function).test;
+        assert!(test.contains("assertEq(uint256(lambda.before()), uint256(1));"));
+        assert!(!test.contains("lambda.total()"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_state_layout_follows_first_use() -> Result<(), Box<dyn Error>> {
+        let mut generator = ContractGenerator::default();
+        let function = "function lambda(address user) {\n    owner = user;\n    count = 1;\n    balances[user] = count;\n    paused = false;\n}";
+        generator.process_lambda(function.to_string())?;
+
+        let layout = generator.state_layout();
+        assert_eq!(
+            layout
+                .iter()
+                .map(|variable| variable.name.as_str())
+                .collect::<Vec<&str>>(),
+            vec!["owner", "count", "balances", "paused"]
+        );
+        assert_eq!(layout[2].var_type, "mapping(address=>uint256)");
+        let state = generator.global_state_string();
+        assert!(state.find("owner").unwrap() < state.find("paused").unwrap());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_statement_structure() -> Result<(), Box<dyn Error>> {
+        let mut generator = ContractGenerator::default();
+
+        let function = r#"
+            function lambda(address user, uint256 amount) {
+                uint256 fee =
+                    amount / 100;
+                if (balances[user] >= amount && fee == 0) {
+                    total += amount;
+                }
+                names[user] = "a = b // not a comment";
+                counter++;
+            }
+        "#
+        .to_string();
+
+        generator.process_lambda(function)?;
+
+        assert_eq!(
+            generator.global_states.get("total"),
+            Some(&PrimitiveSolidityType::Uint(256))
+        );
+        assert_eq!(
+            generator.global_states.get("counter"),
+            Some(&PrimitiveSolidityType::Uint(256))
+        );
+        assert_eq!(
+            generator.global_states.get("names"),
+            Some(&PrimitiveSolidityType::Mapping {
+                key: Box::new(PrimitiveSolidityType::Address),
+                value: Box::new(PrimitiveSolidityType::String),
+            })
+        );
+        // comparisons and locals never become state
+        assert!(!generator.global_states.contains_key("balances"));
+        assert!(!generator.global_states.contains_key("fee"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_error_spans() {
+        let mut generator = ContractGenerator::default();
+        let function = r#"function lambda(address user) {
+    balances[user] = 1;
+    owners[user] = missing;
+}"#
+        .to_string();
+
+        let err = generator.process_lambda(function).unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::UnknownVariable {
+                name: "missing".to_string(),
+                span: Span { line: 3, column: 5 },
+            }
+        );
+        assert_eq!(err.kind(), "unknown_variable");
+        assert_eq!(
+            err.to_string(),
+            "Unknown variable `missing` at line 3, column 5"
+        );
+
+        let err = ContractGenerator::default()
+            .process_lambda("function lambda() {\n  x = \"open;\n}".to_string())
+            .unwrap_err();
+        assert_eq!(err.span(), Some(Span { line: 2, column: 7 }));
+
+        // the length of a fixed array must be a literal
+        let err = ContractGenerator::default()
+            .process_lambda(
+                "function lambda(uint256[N] memory xs) {\n  total = xs[0];\n}".to_string(),
+            )
+            .unwrap_err();
+        assert_eq!(err.kind(), "unsupported_type");
+        assert!(err.to_string().contains("uint256[N]"));
+    }
+
+    #[test]
+    fn test_deeply_nested_input_is_rejected() {
+        let nested_parens = format!("function lambda() {{ x = {}1; }}", "(".repeat(100_000));
+        let err = ContractGenerator::default()
+            .process_lambda(nested_parens)
+            .unwrap_err();
+        assert_eq!(err.kind(), "nesting_too_deep");
+
+        let long_index = format!("function lambda() {{ x{} = 1; }}", "[0]".repeat(100_000));
+        assert!(ContractGenerator::default()
+            .process_lambda(long_index)
+            .is_err());
+
+        let long_member = format!("function lambda() {{ x{} = 1; }}", ".a".repeat(100_000));
+        assert!(ContractGenerator::default()
+            .process_lambda(long_member)
+            .is_err());
+
+        let nested_blocks = format!(
+            "function lambda() {}{}",
+            "{".repeat(100_000),
+            "}".repeat(100_000)
+        );
+        assert!(ContractGenerator::default()
+            .process_lambda(nested_blocks)
+            .is_err());
+    }
+}
+
+#[cfg(test)]
+mod proptests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const TOKENS: &[&str] = &[
+        "function",
+        "lambda",
+        "(",
+        ")",
+        "{",
+        "}",
+        "[",
+        "]",
+        "=",
+        "==",
+        "+=",
+        "++",
+        ";",
+        ",",
+        ".",
+        "a",
+        "b",
+        "msg",
+        "sender",
+        "uint256",
+        "address",
+        "bool",
+        "mapping",
+        "=>",
+        "memory",
+        "0x12",
+        "0x742d35Cc6634C0532925a3b844Bc454e4438f44e",
+        "1",
+        "-",
+        "!",
+        "?",
+        ":",
+        "\"s\"",
+        "if",
+        "else",
+        "for",
+        "while",
+        "return",
+        "new",
+        "delete",
+        "emit",
+        "true",
+        "//",
+        "/*",
+        "*/",
+    ];
+
+    fn token_soup() -> impl Strategy<Value = String> {
+        prop::collection::vec(prop::sample::select(TOKENS), 0..80)
+            .prop_map(|tokens| tokens.join(" "))
+    }
+
+    proptest! {
+        #[test]
+        fn process_lambda_never_panics_on_arbitrary_text(input in "\\PC*") {
+            let _ = ContractGenerator::default().process_lambda(input);
+        }
+
+        #[test]
+        fn process_lambda_never_panics_on_token_soup(input in token_soup()) {
+            let _ = ContractGenerator::default().process_lambda(input);
+        }
+
+        #[test]
+        fn process_lambda_never_panics_inside_lambda_body(body in token_soup()) {
+            let source = format!("function lambda(address user, uint256 amount) {{ {} }}", body);
+            let mut generator = ContractGenerator::default();
+            if generator.process_lambda(source).is_ok() {
+                let _ = generator.global_state_string();
+            }
+        }
     }
 }
@@ -1,4 +1,10 @@
-use std::{collections::HashMap, str::FromStr};
+use std::fmt;
+
+use crate::ast::{DataLocation, Expression, TypeName};
+use crate::error::ParseError;
+
+// bound on mapping/array nesting accepted by from_string
+const MAX_TYPE_DEPTH: usize = 32;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum PrimitiveSolidityType {
@@ -20,36 +26,64 @@ pub enum PrimitiveSolidityType {
         key: Box<PrimitiveSolidityType>,
         value: Box<PrimitiveSolidityType>,
     },
-    Nested
+
+    Struct {
+        name: String,
+        fields: Vec<(String, PrimitiveSolidityType)>,
+    },
+    Enum {
+        name: String,
+        members: Vec<String>,
+    },
 }
 
-impl ToString for PrimitiveSolidityType {
-    fn to_string(&self) -> String {
+// one entry of a function's parameter list, kept in declaration order so generated ABIs are stable
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Parameter {
+    pub name: String,
+    pub var_type: PrimitiveSolidityType,
+    pub location: Option<DataLocation>,
+}
+
+impl fmt::Display for Parameter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.location {
+            Some(location) => write!(f, "{} {} {}", self.var_type, location, self.name),
+            None => write!(f, "{} {}", self.var_type, self.name),
+        }
+    }
+}
+
+impl fmt::Display for PrimitiveSolidityType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            PrimitiveSolidityType::Bool => "bool".to_string(),
-            PrimitiveSolidityType::Address => "address".to_string(),
-            PrimitiveSolidityType::AddressPayable => "address payable".to_string(),
-            PrimitiveSolidityType::Int(bits) => format!("int{}", bits),
-            PrimitiveSolidityType::Uint(bits) => format!("uint{}", bits),
-            PrimitiveSolidityType::Array(inner_type, size) => {
-                format!("{}[{}]", inner_type.to_string(), size)
-            }
-            PrimitiveSolidityType::DynamicArray(inner_type) => {
-                format!("{}[]", inner_type.to_string())
-            }
+            PrimitiveSolidityType::Bool => write!(f, "bool"),
+            PrimitiveSolidityType::Address => write!(f, "address"),
+            PrimitiveSolidityType::AddressPayable => write!(f, "address payable"),
+            PrimitiveSolidityType::Int(bits) => write!(f, "int{}", bits),
+            PrimitiveSolidityType::Uint(bits) => write!(f, "uint{}", bits),
+            PrimitiveSolidityType::Array(inner_type, size) => write!(f, "{}[{}]", inner_type, size),
+            PrimitiveSolidityType::DynamicArray(inner_type) => write!(f, "{}[]", inner_type),
             PrimitiveSolidityType::Mapping { key, value } => {
-                format!("mapping({}=>{})", key.to_string(), value.to_string())
+                write!(f, "mapping({}=>{})", key, value)
+            }
+            PrimitiveSolidityType::String => write!(f, "string"),
+            PrimitiveSolidityType::Bytes => write!(f, "bytes"),
+            PrimitiveSolidityType::FixedBytes(size) => write!(f, "bytes{}", size),
+            PrimitiveSolidityType::Struct { name, .. }
+            | PrimitiveSolidityType::Enum { name, .. } => {
+                write!(f, "{}", name)
             }
-            PrimitiveSolidityType::String => "string".to_string(),
-            PrimitiveSolidityType::Bytes => "bytes".to_string(),
-            PrimitiveSolidityType::FixedBytes(size) => format!("bytes{}", size),
-            Self::Nested => "".to_string(),
         }
     }
 }
 
 impl PrimitiveSolidityType {
     pub fn from_string(string: &str) -> Option<Self> {
+        Self::parse_type_string(string, 0)
+    }
+
+    fn parse_type_string(string: &str, depth: usize) -> Option<Self> {
         let string = string.trim();
 
         //handling the basic types first
@@ -59,28 +93,46 @@ impl PrimitiveSolidityType {
             "bytes" => return Some(PrimitiveSolidityType::Bytes),
             "address" => return Some(PrimitiveSolidityType::Address),
             "address payable" => return Some(PrimitiveSolidityType::AddressPayable),
+            "uint" => return Some(PrimitiveSolidityType::Uint(256)),
+            "int" => return Some(PrimitiveSolidityType::Int(256)),
+            "byte" => return Some(PrimitiveSolidityType::FixedBytes(1)),
             _ => {}
         }
 
-        // Handle uint<N> and int<N>
-        if string.starts_with("uint") {
-            if let Ok(bits) = string[4..].parse::<u16>() {
+        // Handle uint<N> and int<N>, N being a multiple of 8 up to 256
+        if let Some(bits) = string.strip_prefix("uint") {
+            if let Some(bits) = type_size(bits).filter(|bits| bits % 8 == 0 && *bits <= 256) {
                 return Some(PrimitiveSolidityType::Uint(bits));
             }
         }
-        if string.starts_with("int") {
-            if let Ok(bits) = string[3..].parse::<u16>() {
+        if let Some(bits) = string.strip_prefix("int") {
+            if let Some(bits) = type_size(bits).filter(|bits| bits % 8 == 0 && *bits <= 256) {
                 return Some(PrimitiveSolidityType::Int(bits));
             }
         }
 
-        // handling dynamic bytes
-        if string.starts_with("bytes") && string.len() > 5 {
-            if let Ok(size) = string[5..].parse::<u8>() {
-                if size <= 32 {
-                    return Some(PrimitiveSolidityType::FixedBytes(size));
-                }
+        // handling fixed bytes, bytes1 to bytes32
+        if let Some(size) = string.strip_prefix("bytes") {
+            if let Some(size) = type_size(size).filter(|size| *size <= 32) {
+                return Some(PrimitiveSolidityType::FixedBytes(size as u8));
+            }
+        }
+
+        // Handle mappings
+        if let Some(inner) = string
+            .strip_prefix("mapping(")
+            .and_then(|rest| rest.strip_suffix(')'))
+        {
+            if depth >= MAX_TYPE_DEPTH {
+                return None;
             }
+            let separator_idx = inner.find("=>")?;
+            let key_type = inner[..separator_idx].trim();
+            let value_type = inner[separator_idx + 2..].trim();
+            return Some(PrimitiveSolidityType::Mapping {
+                key: Box::new(Self::parse_type_string(key_type, depth + 1)?),
+                value: Box::new(Self::parse_type_string(value_type, depth + 1)?),
+            });
         }
 
         // Handle arrays
@@ -88,7 +140,7 @@ impl PrimitiveSolidityType {
             let base_type = string[..bracket_idx].to_string();
             let array_spec = &string[bracket_idx..];
 
-            let base_type = match PrimitiveSolidityType::from_string(&base_type) {
+            let base_type = match Self::parse_type_string(&base_type, depth + 1) {
                 Some(ty) => ty,
                 None => {
                     return None;
@@ -108,84 +160,139 @@ impl PrimitiveSolidityType {
             }
         }
 
-        // Handle mappings
-        if string.starts_with("mapping(") && string.ends_with(')') {
-            let inner = &string[8..string.len() - 1];
-            if let Some(separator_idx) = inner.find("=>") {
-                let key_type = inner[..separator_idx].trim();
-                let value_type = inner[separator_idx + 2..].trim();
-                return Some(PrimitiveSolidityType::Mapping {
-                    key: Box::new(PrimitiveSolidityType::from_string(key_type).unwrap()),
-                    value: Box::new(PrimitiveSolidityType::from_string(value_type).unwrap()),
-                });
+        None
+    }
+
+    pub fn from_type_name(type_name: &TypeName) -> Option<Self> {
+        Self::from_type_name_with(type_name, &|_| None)
+    }
+
+    // like from_type_name, resolving struct and other user defined names through `user_type`
+    pub fn from_type_name_with(
+        type_name: &TypeName,
+        user_type: &dyn Fn(&str) -> Option<Self>,
+    ) -> Option<Self> {
+        match type_name {
+            TypeName::Elementary(name) => Self::from_string(name),
+            TypeName::UserDefined(name) => user_type(name),
+            TypeName::Array(inner, None) => Some(PrimitiveSolidityType::DynamicArray(Box::new(
+                Self::from_type_name_with(inner, user_type)?,
+            ))),
+            TypeName::Array(inner, Some(length)) => {
+                let inner = Box::new(Self::from_type_name_with(inner, user_type)?);
+                // a length given by a constant can't be resolved here, so the type is unsupported
+                let Expression::Number(size) = length.as_ref() else {
+                    return None;
+                };
+                Some(PrimitiveSolidityType::Array(inner, size.parse().ok()?))
             }
+            TypeName::Mapping { key, value } => Some(PrimitiveSolidityType::Mapping {
+                key: Box::new(Self::from_type_name_with(key, user_type)?),
+                value: Box::new(Self::from_type_name_with(value, user_type)?),
+            }),
         }
-        None
     }
 
-    pub fn parse_function_declaration(function_declaration: &str) -> HashMap<String, Self> {
-        let mut input_args = HashMap::new();
+    // types that need a data location when used as a function parameter
+    pub fn is_reference_type(&self) -> bool {
+        matches!(
+            self,
+            PrimitiveSolidityType::String
+                | PrimitiveSolidityType::Bytes
+                | PrimitiveSolidityType::Array(..)
+                | PrimitiveSolidityType::DynamicArray(_)
+                | PrimitiveSolidityType::Mapping { .. }
+                | PrimitiveSolidityType::Struct { .. }
+        )
+    }
 
-        let bracket_indx = function_declaration.find('(').unwrap();
-        let closing_indx = function_declaration.find(')').unwrap();
+    // mappings can't be copied out of storage, so such values can't be passed to an event
+    pub fn contains_mapping(&self) -> bool {
+        match self {
+            PrimitiveSolidityType::Mapping { .. } => true,
+            PrimitiveSolidityType::Array(inner_type, _)
+            | PrimitiveSolidityType::DynamicArray(inner_type) => inner_type.contains_mapping(),
+            PrimitiveSolidityType::Struct { fields, .. } => fields
+                .iter()
+                .any(|(_, field_type)| field_type.contains_mapping()),
+            _ => false,
+        }
+    }
+
+    pub fn parse_function_declaration(
+        function_declaration: &str,
+    ) -> Result<Vec<Parameter>, ParseError> {
+        let invalid = || ParseError::InvalidFunctionDeclaration {
+            declaration: function_declaration.to_string(),
+        };
+        let mut input_args = Vec::new();
+
+        let bracket_indx = function_declaration.find('(').ok_or_else(invalid)?;
+        let closing_indx = function_declaration.find(')').ok_or_else(invalid)?;
+        if closing_indx < bracket_indx {
+            return Err(invalid());
+        }
 
         if bracket_indx + 1 == closing_indx {
-            return input_args;
+            return Ok(input_args);
         }
 
         let input_args_string = &function_declaration[(bracket_indx + 1)..closing_indx];
         for words in input_args_string.split(',') {
-            let word: Vec<&str> = words.split(' ').filter(|&word| word != "").collect();
-            let arg_type = PrimitiveSolidityType::from_string(word[0]).unwrap();
-            input_args.insert(word[1].to_string(), arg_type);
+            let word: Vec<&str> = words.split(' ').filter(|&word| !word.is_empty()).collect();
+            let (location, [arg_type, name]) = match word.as_slice() {
+                [arg_type, location, name] => (
+                    Some(DataLocation::from_keyword(location).ok_or_else(invalid)?),
+                    [arg_type, name],
+                ),
+                [arg_type, name] => (None, [arg_type, name]),
+                _ => return Err(invalid()),
+            };
+            input_args.push(Parameter {
+                name: name.to_string(),
+                var_type: PrimitiveSolidityType::from_string(arg_type).ok_or_else(invalid)?,
+                location,
+            });
         }
 
-        input_args
+        Ok(input_args)
     }
 
-    pub fn from_assignment(assignment: &str) -> Self {
+    pub fn from_assignment(assignment: &str) -> Result<Self, ParseError> {
         let assignment = assignment.trim();
+        let invalid = || ParseError::InvalidAssignment {
+            assignment: assignment.to_string(),
+        };
 
         // Split into left side and value
         let parts: Vec<&str> = assignment.split('=').collect();
         if parts.len() != 2 {
-            panic!("Invalid assignment: {}", assignment);
+            return Err(invalid());
         }
 
         let left_side = parts[0].trim();
         let value = parts[1].trim();
 
         // Handle mapping assignments like map[key]=value
-        if left_side.contains('[') && left_side.contains(']') {
-            let map_name = left_side.split('[').next().unwrap();
-            // Extract key type from the brackets
-            let key_start = left_side.find('[').unwrap() + 1;
-            let key_end = left_side.find(']').unwrap();
-            let key_value = &left_side[key_start..key_end];
-
-            return PrimitiveSolidityType::Mapping {
+        if let (Some(key_start), Some(key_end)) = (left_side.find('['), left_side.find(']')) {
+            if key_end < key_start {
+                return Err(invalid());
+            }
+            let key_value = &left_side[key_start + 1..key_end];
+
+            return Ok(PrimitiveSolidityType::Mapping {
                 key: Box::new(Self::infer_type_from_value(key_value)),
                 value: Box::new(Self::infer_type_from_value(value)),
-            };
+            });
         }
 
-        // Handle array assignments like arr[0]=value
+        // a closing bracket without an opening one
         if left_side.ends_with(']') {
-            let base_type = Self::infer_type_from_value(value);
-            if left_side.ends_with("[]") {
-                return PrimitiveSolidityType::DynamicArray(Box::new(base_type));
-            } else {
-                // Extract size from fixed array
-                let size_start = left_side.rfind('[').unwrap() + 1;
-                let size_end = left_side.len() - 1;
-                if let Ok(size) = left_side[size_start..size_end].parse::<usize>() {
-                    return PrimitiveSolidityType::Array(Box::new(base_type), size);
-                }
-            }
+            return Err(invalid());
         }
 
         // For simple assignments, infer from the value
-        Self::infer_type_from_value(value)
+        Ok(Self::infer_type_from_value(value))
     }
 
     fn infer_type_from_value(value: &str) -> Self {
@@ -209,7 +316,7 @@ impl PrimitiveSolidityType {
         // Handle hex values
         if value.starts_with("0x") {
             let byte_length = (value.len() - 2) / 2;
-            if byte_length <= 32 {
+            if (1..=32).contains(&byte_length) {
                 return PrimitiveSolidityType::FixedBytes(byte_length as u8);
             }
             return PrimitiveSolidityType::Bytes;
@@ -228,8 +335,130 @@ impl PrimitiveSolidityType {
     }
 }
 
+// the size suffix of a sized type, digits only and without leading zeros: `uint08` is no type
+fn type_size(suffix: &str) -> Option<u16> {
+    if suffix.starts_with('0') || !suffix.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    suffix.parse().ok()
+}
+
 #[test]
 fn test_parse_function_declaration() {
-    let function_dec = "function lambda(uint256 param1,bytes32 param2)";
-    let res = PrimitiveSolidityType::parse_function_declaration(function_dec);
+    let function_dec = "function lambda(uint256 param1,bytes32 param2, string memory param3)";
+    let res = PrimitiveSolidityType::parse_function_declaration(function_dec).unwrap();
+    assert_eq!(
+        res,
+        vec![
+            Parameter {
+                name: "param1".to_string(),
+                var_type: PrimitiveSolidityType::Uint(256),
+                location: None,
+            },
+            Parameter {
+                name: "param2".to_string(),
+                var_type: PrimitiveSolidityType::FixedBytes(32),
+                location: None,
+            },
+            Parameter {
+                name: "param3".to_string(),
+                var_type: PrimitiveSolidityType::String,
+                location: Some(DataLocation::Memory),
+            },
+        ]
+    );
+    assert_eq!(res[2].to_string(), "string memory param3");
+}
+
+#[test]
+fn test_invalid_declarations_are_errors() {
+    assert!(PrimitiveSolidityType::parse_function_declaration("function lambda").is_err());
+    assert!(PrimitiveSolidityType::parse_function_declaration("function lambda(uint256)").is_err());
+    assert!(
+        PrimitiveSolidityType::parse_function_declaration("function lambda(string heap s)")
+            .is_err()
+    );
+    assert!(PrimitiveSolidityType::from_assignment("a = b = c").is_err());
+    assert!(PrimitiveSolidityType::from_assignment("a] = 1").is_err());
+    assert_eq!(
+        PrimitiveSolidityType::from_assignment("flags[5] = true"),
+        Ok(PrimitiveSolidityType::Mapping {
+            key: Box::new(PrimitiveSolidityType::Uint(256)),
+            value: Box::new(PrimitiveSolidityType::Bool),
+        })
+    );
+}
+
+#[test]
+fn test_nested_type_strings() {
+    assert_eq!(
+        PrimitiveSolidityType::from_string("mapping(address=>uint256[])"),
+        Some(PrimitiveSolidityType::Mapping {
+            key: Box::new(PrimitiveSolidityType::Address),
+            value: Box::new(PrimitiveSolidityType::DynamicArray(Box::new(
+                PrimitiveSolidityType::Uint(256)
+            ))),
+        })
+    );
+    assert_eq!(
+        PrimitiveSolidityType::from_string("mapping(foo=>bool)"),
+        None
+    );
+    for invalid in [
+        "uint7", "uint0", "int300", "uint264", "bytes0", "bytes33", "uint+8",
+    ] {
+        assert_eq!(
+            PrimitiveSolidityType::from_string(invalid),
+            None,
+            "{}",
+            invalid
+        );
+    }
+    assert_eq!(
+        PrimitiveSolidityType::from_string("int8"),
+        Some(PrimitiveSolidityType::Int(8))
+    );
+    assert!(PrimitiveSolidityType::parse_function_declaration("function lambda(uint7 a)").is_err());
+    let deep = format!("{}bool{}", "mapping(uint=>".repeat(1000), ")".repeat(1000));
+    assert_eq!(PrimitiveSolidityType::from_string(&deep), None);
+}
+
+#[cfg(test)]
+mod proptests {
+    use super::*;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn from_string_never_panics(input in "\\PC*") {
+            let _ = PrimitiveSolidityType::from_string(&input);
+        }
+
+        #[test]
+        fn type_like_strings_never_panic(input in "(mapping\\(|uint|int|bytes|address|=>|\\[|\\]|\\)|[0-9]| )*") {
+            let _ = PrimitiveSolidityType::from_string(&input);
+        }
+
+        #[test]
+        fn parse_function_declaration_never_panics(input in "\\PC*") {
+            let _ = PrimitiveSolidityType::parse_function_declaration(&input);
+        }
+
+        #[test]
+        fn from_assignment_never_panics(input in "[a-z0-9\\[\\]=x\" ]*") {
+            let _ = PrimitiveSolidityType::from_assignment(&input);
+        }
+
+        #[test]
+        fn display_round_trips(bits in 1u16..=32, size in 1usize..=32) {
+            let ty = PrimitiveSolidityType::Mapping {
+                key: Box::new(PrimitiveSolidityType::Uint(bits * 8)),
+                value: Box::new(PrimitiveSolidityType::Array(
+                    Box::new(PrimitiveSolidityType::FixedBytes(bits as u8)),
+                    size,
+                )),
+            };
+            prop_assert_eq!(PrimitiveSolidityType::from_string(&ty.to_string()), Some(ty));
+        }
+    }
 }
@@ -1,7 +1,34 @@
+use sha2::{Digest, Sha256};
 use std::fs;
 use std::io;
 use std::path::Path;
 
+// same lambda, same id: line endings and trailing whitespace don't change the generated contract
+pub fn normalize_source(source: &str) -> String {
+    source
+        .replace("\r\n", "\n")
+        .lines()
+        .map(str::trim_end)
+        .collect::<Vec<&str>>()
+        .join("\n")
+        .trim()
+        .to_string()
+}
+
+pub fn sha256_hex(parts: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        // length prefix so ("ab", "c") and ("a", "bc") differ
+        hasher.update((part.len() as u64).to_be_bytes());
+        hasher.update(part.as_bytes());
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
 pub fn copy_dir_recursively(src: &Path, dst: &Path) -> io::Result<()> {
     if !dst.exists() {
         fs::create_dir_all(dst)?;
@@ -0,0 +1,69 @@
+use std::{
+    env, io,
+    path::{Path, PathBuf},
+};
+
+// root the generator resolves `template/` and `output/` against, instead of the process cwd
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Workspace {
+    root: PathBuf,
+}
+
+impl Workspace {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    // LAMBDA_WORKSPACE if set, otherwise the directory the process was started in
+    pub fn from_env() -> io::Result<Self> {
+        let root = match env::var_os("LAMBDA_WORKSPACE") {
+            Some(root) => PathBuf::from(root),
+            None => env::current_dir()?,
+        };
+        Ok(Self::new(root.canonicalize()?))
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn template_dir(&self) -> PathBuf {
+        self.root.join("template")
+    }
+
+    pub fn output_dir(&self) -> PathBuf {
+        self.root.join("output")
+    }
+
+    // dirnames come from API callers, so only a plain `lambda_<id>` style name is accepted
+    pub fn lambda_dir(&self, dirname: &str) -> io::Result<PathBuf> {
+        let valid = !dirname.is_empty()
+            && dirname
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if !valid {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid lambda directory name `{}`", dirname),
+            ));
+        }
+        Ok(self.output_dir().join(dirname))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lambda_dir_stays_inside_output() {
+        let workspace = Workspace::new("/srv/lambdas");
+        assert_eq!(
+            workspace.lambda_dir("lambda_ab12").unwrap(),
+            PathBuf::from("/srv/lambdas/output/lambda_ab12")
+        );
+        assert!(workspace.lambda_dir("../template").is_err());
+        assert!(workspace.lambda_dir("a/b").is_err());
+        assert!(workspace.lambda_dir("").is_err());
+    }
+}
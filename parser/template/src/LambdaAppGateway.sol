@@ -17,11 +17,6 @@ contract LambdaAppGateway is AppGatewayBase {
     // create the lambda function gateway here
     // it is of the type
 
-    modifier preExecutionChecks() {
-        //preexecution_checks_here
-        _;
-    }
-
     //lambda_here
 
     function setFees(FeesData memory feesData_) public {
@@ -5,11 +5,16 @@ import { Button } from "@/components/ui/button";
 import { useFunctionsStore } from "@/lib/stores/functions-store";
 import { MoveRight, Plus } from "lucide-react";
 import Link from "next/link";
+import { useEffect } from "react";
 
 const Page = () => {
-  const { functions, setFunctions, activeFunction, setActiveFunction } =
+  const { functions, fetchFunctions, activeFunction, setActiveFunction } =
     useFunctionsStore();
 
+  useEffect(() => {
+    fetchFunctions().catch(console.error);
+  }, [fetchFunctions]);
+
   return (
     <>
       <div className="h-12 w-full flex items-center border-b px-4">
@@ -20,15 +25,16 @@ const Page = () => {
       <div className="pl-4 pr-6 space-y-4">
         <div className="flex flex-row gap-2 py-4 items-center border-b border-gray-100">
           {functions.length ? (
-            functions.map((func, index) => (
+            functions.map((func) => (
               <Button
-                variant={func == activeFunction ? "active" : "inactive"}
+                key={func.id}
+                variant={func.id == activeFunction ? "active" : "inactive"}
                 className="w-32 line-clamp-1"
-                title={func}
+                title={func.name}
                 size={"sm"}
-                onClick={() => setActiveFunction(functions[index])}
+                onClick={() => setActiveFunction(func.id)}
               >
-                {func.length > 8 ? func.slice(0, 8) + "..." : func}
+                {func.name.length > 8 ? func.name.slice(0, 8) + "..." : func.name}
               </Button>
             ))
           ) : (
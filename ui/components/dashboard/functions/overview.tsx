@@ -1,15 +1,31 @@
+import { LAMBDA_METRICS_API } from "@/lib/constant";
 import { useFunctionsStore } from "@/lib/stores/functions-store";
 import { formatToUSD } from "@/lib/utils";
-import { useState } from "react";
+import axios from "axios";
+import { useEffect, useState } from "react";
 
 const Overview = () => {
   const { activeFunction } = useFunctionsStore();
 
-  // TODO: Fetch metrics from server for 'activeFunction'
   const [uniqueTxn, setUniqueTxn] = useState(0);
   const [savings, setSavings] = useState(0);
   const [tx24h, setTx24h] = useState(0);
 
+  useEffect(() => {
+    if (!activeFunction) return;
+    const now = Math.floor(Date.now() / 1000);
+    // a single bucket each, only the totals are shown
+    Promise.all([
+      axios.get(LAMBDA_METRICS_API(activeFunction, 0, now, now)),
+      axios.get(LAMBDA_METRICS_API(activeFunction, now - 86400, now, 86400)),
+    ])
+      .then(([allTime, lastDay]) => {
+        setUniqueTxn(allTime.data.totals.transactions);
+        setTx24h(lastDay.data.totals.transactions);
+      })
+      .catch(console.error);
+  }, [activeFunction]);
+
   const metrics = [
     {
       title: "Unique Txn till Date",
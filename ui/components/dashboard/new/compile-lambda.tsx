@@ -19,11 +19,10 @@ import { useToast } from "@/hooks/use-toast";
 import { Input } from "@/components/ui/input";
 import { Alert, AlertTitle, AlertDescription } from "@/components/ui/alert";
 import axios from "axios";
-import { COMPILE_LAMDA_API, HANDLE_LAMDA_API, LAMBDA_GATEWAY_ADDRESS } from "@/lib/constant";
+import { COMPILE_LAMDA_API, HANDLE_LAMDA_API, JOB_STATUS_API, LAMBDA_GATEWAY_ADDRESS } from "@/lib/constant";
 import { compileFunction } from "vm";
 import { useWriteContract } from "wagmi";
 import { abi } from "@/lib/abi";
-import { addEmitToLambda } from "@/lib/emit";
 
 interface ContractState {
   name: string;
@@ -77,11 +76,9 @@ const CompileLambda = ({
   async function parse() {
 
     try {
-      // add a line in the function to emit the event
-      const lambdaFunction_ = addEmitToLambda(lambdaFunction);
-      console.log(lambdaFunction_);
+      // the backend injects the LambdaCalled and state change events itself
       const res = await axios.post(HANDLE_LAMDA_API, {
-        function: lambdaFunction_,
+        function: lambdaFunction,
         lambda_name: lambdaFunctionName
       })
       console.log(res.data);
@@ -115,11 +112,20 @@ const CompileLambda = ({
       const res = await axios.post(COMPILE_LAMDA_API, {
         dirname,
       });
-      console.log(res.data)
-      setAppGatewayByteCode(res.data.appgateway_bytecode);
-      setDeployerByteCode(res.data.deployer_bytecode);
-      setAppGatewayABI(res.data.appgateway_abi);
-      setDeployerABI(res.data.deployer_abi);
+      // compilation runs as a background job, poll until it finishes
+      let job = (await axios.get(JOB_STATUS_API(res.data.job_id))).data;
+      while (job.status === "queued" || job.status === "running") {
+        await new Promise((resolve) => setTimeout(resolve, 1000));
+        job = (await axios.get(JOB_STATUS_API(res.data.job_id))).data;
+      }
+      console.log(job)
+      if (job.status !== "succeeded") {
+        throw new Error(job.message);
+      }
+      setAppGatewayByteCode(job.result.appgateway_bytecode);
+      setDeployerByteCode(job.result.deployer_bytecode);
+      setAppGatewayABI(job.result.appgateway_abi);
+      setDeployerABI(job.result.deployer_abi);
       toast({
         title: "Compiled, ready for deployment",
         description: "Lambda function has been compiled",
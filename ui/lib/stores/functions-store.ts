@@ -1,22 +1,56 @@
+import axios from "axios";
 import { create } from "zustand";
+import { LAMBDAS_API } from "../constant";
+
+export interface LambdaBuild {
+  id: string;
+  status: "queued" | "running" | "succeeded" | "failed";
+  message: string | null;
+  artifacts: Object | null;
+  diagnostics: Object[] | null;
+}
+
+export interface LambdaFunction {
+  id: string;
+  name: string;
+  source: string;
+  state: string;
+  dirname: string;
+  created_at: number;
+  updated_at: number;
+  build: LambdaBuild | null;
+}
 
 interface FunctionsState {
-  functions: string[];
+  functions: LambdaFunction[];
   activeFunction: string;
   isLoading: boolean;
-  setFunctions: (functions: string[]) => void;
-  setActiveFunction: (functionName: string) => void;
+  setFunctions: (functions: LambdaFunction[]) => void;
+  setActiveFunction: (id: string) => void;
   setIsLoading: (isLoading: boolean) => void;
+  fetchFunctions: () => Promise<void>;
 }
 
-export const useFunctionsStore = create<FunctionsState>((set) => {
-  const initialFunctions = ["abcd", "efgh"];
-  return {
-    functions: initialFunctions,
-    activeFunction: initialFunctions[0],
-    isLoading: false,
-    setFunctions: (functions) => set({ functions }),
-    setActiveFunction: (functionName) => set({ activeFunction: functionName }),
-    setIsLoading: (isLoading) => set({ isLoading }),
-  };
-});
+export const useFunctionsStore = create<FunctionsState>((set, get) => ({
+  functions: [],
+  activeFunction: "",
+  isLoading: false,
+  setFunctions: (functions) => set({ functions }),
+  setActiveFunction: (id) => set({ activeFunction: id }),
+  setIsLoading: (isLoading) => set({ isLoading }),
+  fetchFunctions: async () => {
+    set({ isLoading: true });
+    try {
+      const functions: LambdaFunction[] = (await axios.get(LAMBDAS_API)).data;
+      const active = get().activeFunction;
+      set({
+        functions,
+        activeFunction: functions.some((func) => func.id == active)
+          ? active
+          : functions[0]?.id ?? "",
+      });
+    } finally {
+      set({ isLoading: false });
+    }
+  },
+}));